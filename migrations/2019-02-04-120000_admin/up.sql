@@ -0,0 +1,9 @@
+ALTER TABLE users
+	ADD COLUMN admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE audit_log
+	( id      SERIAL PRIMARY KEY
+	, adminId INTEGER REFERENCES users(id) ON DELETE SET NULL
+	, at      TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, note    TEXT NOT NULL
+	);
@@ -0,0 +1,5 @@
+ALTER TABLE teams
+	ADD COLUMN captain INTEGER REFERENCES users(id) ON DELETE SET NULL;
+
+UPDATE teams
+	SET captain = (SELECT min(id) FROM users WHERE users.teamId = teams.id);
@@ -0,0 +1,2 @@
+ALTER TABLE teams
+	ADD COLUMN personal BOOLEAN NOT NULL DEFAULT FALSE;
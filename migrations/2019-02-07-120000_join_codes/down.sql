@@ -0,0 +1,4 @@
+ALTER TABLE teams
+	DROP COLUMN join_code,
+	DROP COLUMN join_code_expires,
+	DROP COLUMN join_code_uses;
@@ -0,0 +1,12 @@
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+ALTER TABLE teams
+	ADD COLUMN join_code         VARCHAR(16) UNIQUE,
+	ADD COLUMN join_code_expires TIMESTAMP WITH TIME ZONE,
+	ADD COLUMN join_code_uses    INTEGER;
+
+UPDATE teams
+	SET join_code = upper(encode(gen_random_bytes(5), 'hex'));
+
+ALTER TABLE teams
+	ALTER COLUMN join_code SET NOT NULL;
@@ -0,0 +1,13 @@
+CREATE TABLE invites
+	( id      UUID PRIMARY KEY
+	, teamId  UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE
+	, email   VARCHAR(128) NOT NULL
+	, token   UUID UNIQUE NOT NULL
+	, created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, expires TIMESTAMP WITH TIME ZONE NOT NULL
+	, used    BOOLEAN NOT NULL DEFAULT FALSE
+	, CONSTRAINT email_fmt CHECK (email like '%@%.%')
+	, CONSTRAINT email_len CHECK (char_length(email) > 5)
+	);
+
+CREATE INDEX invites_created ON invites (created);
@@ -0,0 +1,6 @@
+ALTER TABLE teams
+	DROP CONSTRAINT website_fmt,
+	DROP COLUMN affiliation,
+	DROP COLUMN country,
+	DROP COLUMN website,
+	DROP COLUMN bio;
@@ -0,0 +1,6 @@
+ALTER TABLE teams
+	ADD COLUMN affiliation VARCHAR(128)  NOT NULL DEFAULT '',
+	ADD COLUMN country     VARCHAR(64)   NOT NULL DEFAULT '',
+	ADD COLUMN website     VARCHAR(256)  NOT NULL DEFAULT '',
+	ADD COLUMN bio         VARCHAR(1024) NOT NULL DEFAULT '',
+	ADD CONSTRAINT website_fmt CHECK (website = '' OR website similar to 'https?://%');
@@ -0,0 +1,3 @@
+ALTER TABLE teams
+	DROP COLUMN divisionId;
+DROP TABLE divisions;
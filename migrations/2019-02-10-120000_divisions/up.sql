@@ -0,0 +1,8 @@
+CREATE TABLE divisions
+	( id            SERIAL PRIMARY KEY
+	, name          VARCHAR(64) NOT NULL UNIQUE
+	, email_domains TEXT[] NOT NULL DEFAULT '{}'
+	);
+
+ALTER TABLE teams
+	ADD COLUMN divisionId INTEGER REFERENCES divisions(id) ON DELETE SET NULL;
@@ -0,0 +1,7 @@
+ALTER TABLE auths
+	DROP COLUMN seq,
+	DROP COLUMN created,
+	DROP COLUMN last_used,
+	DROP COLUMN expires,
+	DROP COLUMN ip,
+	DROP COLUMN user_agent;
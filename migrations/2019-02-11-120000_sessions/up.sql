@@ -0,0 +1,10 @@
+ALTER TABLE auths
+	ADD COLUMN seq        SERIAL NOT NULL UNIQUE,
+	ADD COLUMN created    TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+	ADD COLUMN last_used  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+	ADD COLUMN expires    TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now() + interval '14 days',
+	ADD COLUMN ip         VARCHAR(45),
+	ADD COLUMN user_agent VARCHAR(256);
+
+ALTER TABLE auths
+	ALTER COLUMN expires DROP DEFAULT;
@@ -0,0 +1,2 @@
+ALTER TABLE auths
+	DROP COLUMN csrf;
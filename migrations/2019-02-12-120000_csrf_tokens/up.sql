@@ -0,0 +1,7 @@
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+ALTER TABLE auths
+	ADD COLUMN csrf UUID NOT NULL DEFAULT gen_random_uuid();
+
+ALTER TABLE auths
+	ALTER COLUMN csrf DROP DEFAULT;
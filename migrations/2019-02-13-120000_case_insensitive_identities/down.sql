@@ -0,0 +1,6 @@
+DROP INDEX users_name_key;
+DROP INDEX users_email_key;
+
+ALTER TABLE users
+	ADD CONSTRAINT users_name_key UNIQUE (name),
+	ADD CONSTRAINT users_email_key UNIQUE (email);
@@ -0,0 +1,33 @@
+-- Usernames and emails that only differ by case have to be fixed by hand before this can run.
+DO $$
+DECLARE
+	collisions TEXT;
+BEGIN
+	SELECT string_agg(names, '; ') INTO collisions
+		FROM ( SELECT string_agg(name, ', ') AS names
+		       FROM users
+		       GROUP BY lower(name)
+		       HAVING count(*) > 1
+		     ) AS collisions;
+	IF collisions IS NOT NULL THEN
+		RAISE EXCEPTION 'Usernames differ only by case: %', collisions;
+	END IF;
+
+	SELECT string_agg(emails, '; ') INTO collisions
+		FROM ( SELECT string_agg(email, ', ') AS emails
+		       FROM users
+		       GROUP BY lower(email)
+		       HAVING count(*) > 1
+		     ) AS collisions;
+	IF collisions IS NOT NULL THEN
+		RAISE EXCEPTION 'Emails differ only by case: %', collisions;
+	END IF;
+END
+$$;
+
+ALTER TABLE users
+	DROP CONSTRAINT users_name_key,
+	DROP CONSTRAINT users_email_key;
+
+CREATE UNIQUE INDEX users_name_key ON users (lower(name));
+CREATE UNIQUE INDEX users_email_key ON users (lower(email));
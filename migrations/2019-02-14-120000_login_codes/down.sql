@@ -0,0 +1,3 @@
+ALTER TABLE logins
+	DROP COLUMN code,
+	DROP COLUMN attempts;
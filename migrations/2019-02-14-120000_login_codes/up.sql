@@ -0,0 +1,6 @@
+ALTER TABLE logins
+	ADD COLUMN code     VARCHAR(8) NOT NULL DEFAULT '',
+	ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE logins
+	ALTER COLUMN code DROP DEFAULT;
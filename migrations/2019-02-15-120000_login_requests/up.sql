@@ -0,0 +1,10 @@
+CREATE TABLE login_requests
+	( id     SERIAL PRIMARY KEY
+	, ip     VARCHAR(45)
+	, userId INTEGER REFERENCES users(id) ON DELETE CASCADE
+	, mailed BOOLEAN NOT NULL
+	, at     TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	);
+
+CREATE INDEX login_requests_ip ON login_requests (ip, at);
+CREATE INDEX login_requests_user ON login_requests (userId, at);
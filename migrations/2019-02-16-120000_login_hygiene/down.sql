@@ -0,0 +1,12 @@
+DROP INDEX logins_expires;
+DROP INDEX auths_expires;
+
+ALTER TABLE auths
+	DROP CONSTRAINT auths_userid_fkey,
+	ADD CONSTRAINT auths_userid_fkey FOREIGN KEY (userId) REFERENCES users(id);
+ALTER TABLE logins
+	DROP CONSTRAINT logins_userid_fkey,
+	ADD CONSTRAINT logins_userid_fkey FOREIGN KEY (userId) REFERENCES users(id);
+
+ALTER TABLE users
+	DROP COLUMN banned;
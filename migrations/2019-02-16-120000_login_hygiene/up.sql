@@ -0,0 +1,13 @@
+ALTER TABLE users
+	ADD COLUMN banned BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Deleting a user takes their sessions and login links with them.
+ALTER TABLE auths
+	DROP CONSTRAINT auths_userid_fkey,
+	ADD CONSTRAINT auths_userid_fkey FOREIGN KEY (userId) REFERENCES users(id) ON DELETE CASCADE;
+ALTER TABLE logins
+	DROP CONSTRAINT logins_userid_fkey,
+	ADD CONSTRAINT logins_userid_fkey FOREIGN KEY (userId) REFERENCES users(id) ON DELETE CASCADE;
+
+CREATE INDEX logins_expires ON logins (expires);
+CREATE INDEX auths_expires ON auths (expires);
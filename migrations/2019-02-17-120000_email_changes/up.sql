@@ -0,0 +1,10 @@
+CREATE TABLE email_changes
+	( id      UUID PRIMARY KEY
+	, userId  INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE
+	, email   VARCHAR(128) NOT NULL
+	, expires TIMESTAMP WITH TIME ZONE NOT NULL
+	, used    BOOLEAN NOT NULL DEFAULT FALSE
+	, CONSTRAINT email_fmt CHECK (email like '%@%.%')
+	, CONSTRAINT email_len CHECK (char_length(email) > 5)
+	);
+CREATE INDEX email_changes_expires ON email_changes (expires);
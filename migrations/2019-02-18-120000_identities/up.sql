@@ -0,0 +1,7 @@
+CREATE TABLE identities
+	( provider VARCHAR(256) NOT NULL
+	, subject  VARCHAR(256) NOT NULL
+	, userId   INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE
+	, PRIMARY KEY (provider, subject)
+	);
+CREATE INDEX identities_userid ON identities (userId);
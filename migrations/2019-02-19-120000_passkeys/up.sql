@@ -0,0 +1,20 @@
+CREATE TABLE passkeys
+	( id            SERIAL PRIMARY KEY
+	, userId        INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE
+	, credential_id BYTEA NOT NULL UNIQUE
+	, algorithm     INTEGER NOT NULL
+	, public_key    BYTEA NOT NULL
+	, sign_count    BIGINT NOT NULL DEFAULT 0
+	, name          VARCHAR(64) NOT NULL
+	, created       TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, last_used     TIMESTAMP WITH TIME ZONE
+	);
+CREATE INDEX passkeys_userid ON passkeys (userId);
+
+-- A challenge for registering a passkey has the user it's for; one for logging in doesn't.
+CREATE TABLE webauthn_challenges
+	( id      UUID PRIMARY KEY
+	, userId  INTEGER REFERENCES users(id) ON DELETE CASCADE
+	, expires TIMESTAMP WITH TIME ZONE NOT NULL
+	);
+CREATE INDEX webauthn_challenges_expires ON webauthn_challenges (expires);
@@ -0,0 +1,5 @@
+ALTER TABLE auths
+	DROP COLUMN stepped_up,
+	DROP COLUMN failed_codes;
+DROP TABLE recovery_codes;
+DROP TABLE totp_secrets;
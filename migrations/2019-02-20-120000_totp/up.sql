@@ -0,0 +1,21 @@
+CREATE TABLE totp_secrets
+	( userId    INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE
+	, secret    BYTEA NOT NULL
+	, enabled   BOOLEAN NOT NULL DEFAULT FALSE
+	, last_step BIGINT NOT NULL DEFAULT 0
+	, created   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	);
+
+-- Recovery codes are as good as the secret, so only their hashes are kept.
+CREATE TABLE recovery_codes
+	( userId INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE
+	, hash   BYTEA NOT NULL
+	, used   BOOLEAN NOT NULL DEFAULT FALSE
+	, PRIMARY KEY (userId, hash)
+	);
+
+-- A session is stepped up when a second factor was last checked in it. Wrong codes are counted
+-- per session, so guessing needs a fresh login (and login mail) every few tries.
+ALTER TABLE auths
+	ADD COLUMN stepped_up TIMESTAMP WITH TIME ZONE,
+	ADD COLUMN failed_codes INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+DROP TABLE wrong_flags;
+DROP TABLE solves;
+DROP TABLE attachments;
+DROP TABLE problems;
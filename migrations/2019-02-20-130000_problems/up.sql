@@ -0,0 +1,44 @@
+-- Problem names are used in URLs and as folder names by the command-line client, so they're kept
+-- to letters, digits, and dashes. Hidden problems are only shown to admins.
+CREATE TABLE problems
+	( id          SERIAL PRIMARY KEY
+	, name        VARCHAR(64) UNIQUE NOT NULL
+	, category    VARCHAR(32) NOT NULL
+	, description VARCHAR(8192) NOT NULL
+	, points      INTEGER NOT NULL
+	, flag        VARCHAR(256) NOT NULL
+	, visible     BOOLEAN NOT NULL DEFAULT false
+	, created     TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, CONSTRAINT name_fmt CHECK (name ~ '^[a-zA-Z0-9-]+$')
+	, CONSTRAINT points_positive CHECK (points > 0)
+	, CONSTRAINT flag_len CHECK (char_length(flag) > 0)
+	);
+
+-- Attachments are hosted elsewhere; only links to them are kept. Their names are used as file
+-- names, so they can't contain slashes or start with a dot.
+CREATE TABLE attachments
+	( id        SERIAL PRIMARY KEY
+	, problemId INTEGER NOT NULL REFERENCES problems(id) ON DELETE CASCADE
+	, name      VARCHAR(128) NOT NULL
+	, url       VARCHAR(1024) NOT NULL
+	, UNIQUE (problemId, name)
+	, CONSTRAINT name_fmt CHECK (name ~ '^[a-zA-Z0-9_-][a-zA-Z0-9._-]*$')
+	, CONSTRAINT url_fmt CHECK (url similar to 'https?://%')
+	);
+
+CREATE TABLE solves
+	( teamId    UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE
+	, problemId INTEGER NOT NULL REFERENCES problems(id) ON DELETE CASCADE
+	, userId    INTEGER REFERENCES users(id) ON DELETE SET NULL
+	, solved    TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, PRIMARY KEY (teamId, problemId)
+	);
+CREATE INDEX solves_problemid ON solves (problemId, solved);
+
+-- Wrong flags are only kept long enough to rate-limit guessing.
+CREATE TABLE wrong_flags
+	( id     SERIAL PRIMARY KEY
+	, teamId UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE
+	, at     TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	);
+CREATE INDEX wrong_flags_teamid ON wrong_flags (teamId, at);
@@ -0,0 +1,10 @@
+-- Only a hash of each token is kept, so a leaked database doesn't leak working tokens.
+CREATE TABLE api_tokens
+	( id        SERIAL PRIMARY KEY
+	, userId    INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE
+	, hash      BYTEA NOT NULL UNIQUE
+	, name      VARCHAR(64) NOT NULL
+	, created   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, last_used TIMESTAMP WITH TIME ZONE
+	);
+CREATE INDEX api_tokens_userid ON api_tokens (userId);
@@ -0,0 +1,12 @@
+-- An announcement can be about a problem, such as a correction to it; it's kept if the problem is
+-- deleted, since it was still posted.
+CREATE TABLE announcements
+	( id        SERIAL PRIMARY KEY
+	, title     VARCHAR(128) NOT NULL
+	, body      VARCHAR(4096) NOT NULL
+	, created   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, edited    TIMESTAMP WITH TIME ZONE
+	, problemId INTEGER REFERENCES problems(id) ON DELETE SET NULL
+	, CONSTRAINT title_len CHECK (char_length(title) > 0)
+	);
+CREATE INDEX announcements_problemid ON announcements (problemId);
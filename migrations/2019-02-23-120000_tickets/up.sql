@@ -0,0 +1,24 @@
+-- Tickets outlive their team and the users who wrote in them, so there's a record of who asked
+-- what even after teams are merged or disbanded. A ticket can be about a problem.
+CREATE TABLE tickets
+	( id        SERIAL PRIMARY KEY
+	, teamId    UUID REFERENCES teams(id) ON DELETE SET NULL
+	, subject   VARCHAR(128) NOT NULL
+	, created   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, closed    TIMESTAMP WITH TIME ZONE
+	, problemId INTEGER REFERENCES problems(id) ON DELETE SET NULL
+	, CONSTRAINT subject_len CHECK (char_length(subject) > 0)
+	);
+CREATE INDEX tickets_teamid ON tickets (teamId);
+
+CREATE TABLE ticket_messages
+	( id             SERIAL PRIMARY KEY
+	, ticketId       INTEGER NOT NULL REFERENCES tickets(id) ON DELETE CASCADE
+	, userId         INTEGER REFERENCES users(id) ON DELETE SET NULL
+	, from_mod       BOOLEAN NOT NULL
+	, body           VARCHAR(4096) NOT NULL
+	, sent           TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, announcementId INTEGER REFERENCES announcements(id) ON DELETE SET NULL
+	, CONSTRAINT body_len CHECK (char_length(body) > 0)
+	);
+CREATE INDEX ticket_messages_ticketid ON ticket_messages (ticketId);
@@ -0,0 +1,15 @@
+-- Mail is queued here and delivered by a background worker, so requests don't wait on, or fail
+-- because of, the SMTP server.
+CREATE TABLE mail_outbox
+	( id           SERIAL PRIMARY KEY
+	, recipient    VARCHAR(256) NOT NULL
+	, subject      VARCHAR(256) NOT NULL
+	, body         TEXT NOT NULL
+	, created      TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, attempts     INTEGER NOT NULL DEFAULT 0
+	, next_attempt TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+	, sent         TIMESTAMP WITH TIME ZONE
+	, failed       BOOLEAN NOT NULL DEFAULT false
+	, last_error   TEXT
+	);
+CREATE INDEX mail_outbox_due ON mail_outbox (next_attempt) WHERE sent IS NULL AND NOT failed;
@@ -0,0 +1,537 @@
+//! A command-line client for players, which talks to the JSON API.
+//!
+//! `ctf login` saves an API token from the settings page to `~/.config/ctf/config.json` (or under
+//! `$XDG_CONFIG_HOME`), and every other command uses it. `ctf download` saves each problem's
+//! attachments in a folder named after the problem.
+
+use failure::{bail, format_err, Fallible, ResultExt};
+use reqwest::{header::AUTHORIZATION, Client, Method, Response, Url};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{stdin, stdout, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
+use structopt::StructOpt;
+
+/// The server used if none is given.
+const DEFAULT_URL: &str = "https://ctf.acm.umn.edu";
+
+fn main() {
+    let options = Options::from_args();
+    if let Err(err) = run(options) {
+        eprintln!("{}", err);
+        for cause in err.iter_causes() {
+            eprintln!("caused by: {}", cause);
+        }
+        exit(1);
+    }
+}
+
+fn run(options: Options) -> Fallible<()> {
+    let url = options.url;
+    match options.command {
+        Command::Divisions => {
+            let divisions: Vec<Division> = Api::load(url)?.get("divisions")?;
+            for division in divisions {
+                if division.email_domains.is_empty() {
+                    println!("{}", division.name);
+                } else {
+                    println!("{} ({})", division.name, division.email_domains.join(", "));
+                }
+            }
+        }
+        Command::Download { dir, problems } => {
+            let api = Api::load(url)?;
+            let listings: Vec<Problem> = if problems.is_empty() {
+                api.get("problems")?
+            } else {
+                problems
+                    .iter()
+                    .map(|name| api.get(&format!("problems/{}", name)))
+                    .collect::<Fallible<_>>()?
+            };
+            for problem in listings {
+                download(&api, &dir, &problem)?;
+            }
+        }
+        Command::Join { code } => {
+            let api = Api::load(url)?;
+            api.post("team/join", json!({ "join_code": code }))?;
+            print_team(&api.get("team")?);
+        }
+        Command::Leave => Api::load(url)?.post("team/leave", json!({}))?,
+        Command::Login => login(url)?,
+        Command::Logout => {
+            let path = config_path()?;
+            if path.exists() {
+                fs::remove_file(&path)
+                    .with_context(|_| format!("Couldn't remove {}", path.display()))?;
+            }
+        }
+        Command::Problem { name } => {
+            let problem: Problem = Api::load(url)?.get(&format!("problems/{}", name))?;
+            println!(
+                "{} ({}, {} points, {} solves){}",
+                problem.name,
+                problem.category,
+                problem.points,
+                problem.solves,
+                if problem.solved { " [solved]" } else { "" }
+            );
+            println!();
+            println!("{}", problem.description.trim_end());
+            if !problem.attachments.is_empty() {
+                println!();
+                for attachment in &problem.attachments {
+                    println!("{}: {}", attachment.name, attachment.url);
+                }
+            }
+        }
+        Command::Problems => {
+            let problems: Vec<Problem> = Api::load(url)?.get("problems")?;
+            for problem in problems {
+                println!(
+                    "{:<12} {:<24} {:>5} points {:>4} solves{}",
+                    problem.category,
+                    problem.name,
+                    problem.points,
+                    problem.solves,
+                    if problem.solved { " [solved]" } else { "" }
+                );
+            }
+        }
+        Command::Scoreboard { division } => {
+            let path = match division {
+                Some(division) => format!("scoreboard/{}", division),
+                None => "scoreboard".to_string(),
+            };
+            let scoreboard: Scoreboard = Api::load(url)?.get(&path)?;
+            for standing in scoreboard.standings {
+                println!(
+                    "{:>4}. {:<24} {:>6}",
+                    standing.place, standing.team, standing.points
+                );
+            }
+        }
+        Command::Submit { problem, flag } => {
+            let path = format!("problems/{}/flag", problem);
+            let result: FlagResult = Api::load(url)?.post_json(&path, json!({ "flag": flag }))?;
+            if result.already_solved {
+                println!("Correct, but your team had already solved {}.", problem);
+            } else {
+                println!("Correct! Your team solved {}.", problem);
+            }
+        }
+        Command::Team => print_team(&Api::load(url)?.get("team")?),
+        Command::Teams => {
+            let teams: Vec<TeamSummary> = Api::load(url)?.get("teams")?;
+            for team in teams {
+                println!("{}: {}", team.team.name, team.members.join(", "));
+            }
+        }
+        Command::Whoami => {
+            let me: User = Api::load(url)?.get("me")?;
+            println!("{} <{}>", me.name, me.email);
+        }
+    }
+    Ok(())
+}
+
+/// Downloads a problem's attachments into a folder named after it under `dir`, skipping files that
+/// are already there.
+fn download(api: &Api, dir: &Path, problem: &Problem) -> Fallible<()> {
+    let folder = dir.join(
+        safe_file_name(&problem.name)
+            .ok_or_else(|| format_err!("Can't save a problem named {:?}", problem.name))?,
+    );
+    for attachment in &problem.attachments {
+        let name = safe_file_name(&attachment.name).ok_or_else(|| {
+            format_err!(
+                "Can't save {:?} from {}; download it from {}",
+                attachment.name,
+                problem.name,
+                attachment.url
+            )
+        })?;
+        let path = folder.join(name);
+        if path.exists() {
+            println!("{} already exists", path.display());
+            continue;
+        }
+
+        fs::create_dir_all(&folder)
+            .with_context(|_| format!("Couldn't create {}", folder.display()))?;
+        api.download(&attachment.url, &path)
+            .with_context(|_| format!("Couldn't download {}", attachment.url))?;
+        println!("Downloaded {}", path.display());
+    }
+    Ok(())
+}
+
+/// Asks for an API token, checks it, and saves it.
+fn login(url: Option<String>) -> Fallible<()> {
+    let url = match url {
+        Some(url) => url,
+        None => Config::load()
+            .map(|config| config.url)
+            .unwrap_or_else(|_| DEFAULT_URL.to_string()),
+    };
+    print!(
+        "Create an API token at {}/settings, then paste it here: ",
+        url.trim_end_matches('/')
+    );
+    stdout().flush()?;
+    let mut token = String::new();
+    let _ = stdin().read_line(&mut token)?;
+
+    let config = Config {
+        token: token.trim().to_string(),
+        url,
+    };
+    let api = Api::new(&config)?;
+    let me: User = api.get("me")?;
+    config.save()?;
+    println!("Logged in as {}.", me.name);
+    Ok(())
+}
+
+fn print_team(team: &TeamWithMembers) {
+    println!("{}", team.team.name);
+    for member in &team.members {
+        if member.captain {
+            println!("  {} (captain)", member.name);
+        } else {
+            println!("  {}", member.name);
+        }
+    }
+}
+
+/// The last component of a file name from the server, or `None` if it doesn't name a file. Names
+/// come from whoever wrote the problem, so they mustn't be able to write outside its folder.
+fn safe_file_name(name: &str) -> Option<&str> {
+    let name = name.rsplit(|c| c == '/' || c == '\\').next()?;
+    match name {
+        "" | "." | ".." => None,
+        _ => Some(name),
+    }
+}
+
+/// The path of the configuration file.
+fn config_path() -> Fallible<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".config"),
+            None => bail!("Couldn't find your home directory; set XDG_CONFIG_HOME"),
+        },
+    };
+    Ok(dir.join("ctf").join("config.json"))
+}
+
+/// The saved server and API token.
+#[derive(Debug, Deserialize, Serialize)]
+struct Config {
+    token: String,
+    url: String,
+}
+
+impl Config {
+    fn load() -> Fallible<Config> {
+        let path = config_path()?;
+        let file =
+            fs::read(&path).map_err(|_| format_err!("You aren't logged in; run `ctf login`"))?;
+        serde_json::from_slice(&file)
+            .with_context(|_| format!("Couldn't read {}", path.display()))
+            .map_err(Into::into)
+    }
+
+    /// Saves the configuration, readable only by the user, since the token is as good as a login.
+    fn save(&self) -> Fallible<()> {
+        let path = config_path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut options = OpenOptions::new();
+        let _ = options.create(true).truncate(true).write(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            let _ = options.mode(0o600);
+        }
+        let mut file = options
+            .open(&path)
+            .with_context(|_| format!("Couldn't write {}", path.display()))?;
+        serde_json::to_writer_pretty(&mut file, self)?;
+        Ok(())
+    }
+}
+
+/// A client for the API.
+#[derive(Debug)]
+struct Api {
+    base: Url,
+    client: Client,
+    token: String,
+}
+
+impl Api {
+    fn load(url: Option<String>) -> Fallible<Api> {
+        let mut config = Config::load()?;
+        if let Some(url) = url {
+            config.url = url;
+        }
+        Api::new(&config)
+    }
+
+    fn new(config: &Config) -> Fallible<Api> {
+        let base = format!("{}/api/v1/", config.url.trim_end_matches('/'));
+        Ok(Api {
+            base: Url::parse(&base).with_context(|_| format!("Invalid URL: {}", config.url))?,
+            client: Client::new(),
+            token: config.token.clone(),
+        })
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Fallible<T> {
+        self.request::<()>(Method::GET, path, None)?
+            .json()
+            .map_err(Into::into)
+    }
+
+    fn post<T: serde::Serialize>(&self, path: &str, body: T) -> Fallible<()> {
+        let _ = self.request(Method::POST, path, Some(body))?;
+        Ok(())
+    }
+
+    fn post_json<T: serde::Serialize, R: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: T,
+    ) -> Fallible<R> {
+        self.request(Method::POST, path, Some(body))?
+            .json()
+            .map_err(Into::into)
+    }
+
+    /// Downloads a file to the given path. Attachments are hosted elsewhere, so the API token isn't
+    /// sent. The file is written under a temporary name first, so a failed download isn't mistaken
+    /// for a finished one.
+    fn download(&self, url: &str, path: &Path) -> Fallible<()> {
+        let mut response = self.client.get(url).send()?.error_for_status()?;
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".part");
+        let mut file = File::create(&partial)?;
+        let _ = response.copy_to(&mut file)?;
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    /// Makes a request, turning error responses into errors with the server's message.
+    fn request<T: serde::Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<T>,
+    ) -> Fallible<Response> {
+        let mut request = self
+            .client
+            .request(method, self.base.join(path)?)
+            .header(AUTHORIZATION, format!("Bearer {}", self.token));
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+
+        let mut response = request.send()?;
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        #[derive(Deserialize)]
+        struct ApiError {
+            error: String,
+        }
+        match response.json::<ApiError>() {
+            Ok(err) => bail!("{}", err.error),
+            Err(_) => bail!("The server responded with {}", response.status()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Attachment {
+    name: String,
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Division {
+    name: String,
+    email_domains: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FlagResult {
+    already_solved: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Problem {
+    name: String,
+    category: String,
+    description: String,
+    points: i32,
+    attachments: Vec<Attachment>,
+    solves: i64,
+    solved: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Scoreboard {
+    standings: Vec<Standing>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Standing {
+    place: usize,
+    team: String,
+    points: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Team {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TeamMember {
+    name: String,
+    captain: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct TeamSummary {
+    team: Team,
+    members: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TeamWithMembers {
+    team: Team,
+    members: Vec<TeamMember>,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    name: String,
+    email: String,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(raw(setting = "::structopt::clap::AppSettings::ColoredHelp"))]
+struct Options {
+    /// The URL of the CTF. Defaults to the one given when logging in.
+    #[structopt(long = "url", env = "CTF_URL")]
+    url: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Lists the divisions teams can compete in.
+    #[structopt(name = "divisions")]
+    Divisions,
+
+    /// Downloads problems' attachments, each into a folder named after its problem.
+    #[structopt(name = "download")]
+    Download {
+        /// The folder to put the problems' folders in.
+        #[structopt(short = "d", long = "dir", default_value = ".", parse(from_os_str))]
+        dir: PathBuf,
+
+        /// The problems to download the attachments of. Defaults to every problem.
+        problems: Vec<String>,
+    },
+
+    /// Joins a team with its join code.
+    #[structopt(name = "join")]
+    Join {
+        /// The team's join code, from its captain.
+        code: String,
+    },
+
+    /// Leaves your team.
+    #[structopt(name = "leave")]
+    Leave,
+
+    /// Saves an API token, created on the settings page, for the other commands to use.
+    #[structopt(name = "login")]
+    Login,
+
+    /// Forgets the saved API token. It still works until it's revoked on the settings page.
+    #[structopt(name = "logout")]
+    Logout,
+
+    /// Shows a problem's description and attachments.
+    #[structopt(name = "problem")]
+    Problem {
+        /// The problem's name.
+        name: String,
+    },
+
+    /// Lists the problems, marking the ones your team has solved.
+    #[structopt(name = "problems")]
+    Problems,
+
+    /// Shows the scoreboard.
+    #[structopt(name = "scoreboard")]
+    Scoreboard {
+        /// Only show the teams in this division.
+        division: Option<String>,
+    },
+
+    /// Submits a flag for a problem.
+    #[structopt(name = "submit")]
+    Submit {
+        /// The problem's name.
+        problem: String,
+
+        /// The flag.
+        flag: String,
+    },
+
+    /// Shows your team and its members.
+    #[structopt(name = "team")]
+    Team,
+
+    /// Lists every team.
+    #[structopt(name = "teams")]
+    Teams,
+
+    /// Shows who you're logged in as.
+    #[structopt(name = "whoami")]
+    Whoami,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_stay_in_their_folder() {
+        assert_eq!(safe_file_name("chall.zip"), Some("chall.zip"));
+        assert_eq!(safe_file_name("../../.bashrc"), Some(".bashrc"));
+        assert_eq!(safe_file_name("/etc/passwd"), Some("passwd"));
+        assert_eq!(safe_file_name("..\\evil.exe"), Some("evil.exe"));
+        assert_eq!(safe_file_name("dir/"), None);
+        assert_eq!(safe_file_name(".."), None);
+        assert_eq!(safe_file_name(""), None);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::schema::Event;
+use antidote::Mutex;
+use futures::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+/// How many events a subscriber can fall behind by before it's dropped.
+const SUBSCRIBER_BUFFER: usize = 64;
+
+/// A broadcast channel for events, which every open event stream listens to. This is in-process,
+/// so events only reach clients connected to the same server.
+#[derive(Clone, Debug, Default)]
+pub struct Events {
+    subscribers: Arc<Mutex<Vec<Sender<Event>>>>,
+}
+
+impl Events {
+    /// Creates a channel with no subscribers.
+    pub fn new() -> Events {
+        Events::default()
+    }
+
+    /// Sends an event to every subscriber, forgetting the ones that have disconnected or fallen
+    /// `SUBSCRIBER_BUFFER` events behind. A stalled client's stream ends when it's forgotten, so
+    /// it can't hold on to an ever-growing backlog; browsers reconnect on their own.
+    pub fn publish(&self, event: Event) {
+        let mut subscribers = self.subscribers.lock();
+        let live = subscribers
+            .drain(..)
+            .filter_map(|mut subscriber| match subscriber.try_send(event.clone()) {
+                Ok(()) => Some(subscriber),
+                Err(_) => None,
+            })
+            .collect();
+        *subscribers = live;
+    }
+
+    /// Starts listening for events. Only events published after this are received.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (send, recv) = channel(SUBSCRIBER_BUFFER);
+        self.subscribers.lock().push(send);
+        recv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+
+    #[test]
+    fn disconnected_subscribers_are_forgotten() {
+        let events = Events::new();
+        drop(events.subscribe());
+        events.publish(Event::TeamsChanged);
+        assert!(events.subscribers.lock().is_empty());
+    }
+
+    #[test]
+    fn stalled_subscribers_are_dropped() {
+        let events = Events::new();
+        let stalled = events.subscribe();
+        for _ in 0..SUBSCRIBER_BUFFER {
+            events.publish(Event::TeamsChanged);
+        }
+        assert_eq!(events.subscribers.lock().len(), 1);
+
+        for _ in 0..SUBSCRIBER_BUFFER {
+            events.publish(Event::TeamsChanged);
+        }
+        assert!(events.subscribers.lock().is_empty());
+
+        // What was buffered is still delivered, and then the stream ends.
+        let received = stalled.wait().collect::<Result<Vec<_>, ()>>().unwrap();
+        assert!(received.len() >= SUBSCRIBER_BUFFER);
+        assert!(received.len() < 2 * SUBSCRIBER_BUFFER);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::util::blocking;
+use crate::{dal::DB, schema::QueuedMail, util::blocking};
 use antidote::Mutex;
 use failure::{format_err, Error, Fallible};
 use futures::{
@@ -13,20 +13,23 @@ use lettre::{
     },
     EmailTransport,
 };
-use lettre_email::EmailBuilder;
+use lettre_email::{Email, EmailBuilder};
 use native_tls::TlsConnector;
 use std::sync::Arc;
 
-/// A connection to the mailer.
+/// A connection to the mailer. Mail is queued in the database's outbox when it's sent, and
+/// delivered over SMTP by the mail worker, so sending mail never waits on the SMTP server.
 #[allow(missing_debug_implementations)]
 #[derive(Clone)]
 pub struct Mailer {
+    db: DB,
     inner: Arc<MailerInner>,
 }
 
 impl Mailer {
-    /// Connects to an SMTP server.
+    /// Connects to an SMTP server, queueing mail in the given database.
     pub fn connect(
+        db: DB,
         host: &str,
         secure: bool,
         user: String,
@@ -48,6 +51,7 @@ impl Mailer {
             .credentials(Credentials::new(user.clone(), pass))
             .build();
         Ok(Mailer {
+            db,
             inner: Arc::new(MailerInner {
                 from,
                 smtp: Mutex::new(smtp),
@@ -55,22 +59,11 @@ impl Mailer {
         })
     }
 
-    /// Sends an email.
-    pub fn send(
-        &self,
-        to: &str,
-        subject: &str,
-        text: &str,
-    ) -> impl Future<Item = (), Error = Error> {
-        let r = EmailBuilder::new()
-            .to(to)
-            .from(self.inner.from.as_str())
-            .subject(subject)
-            .text(text)
-            .build();
-        let email = match r {
+    /// Delivers a queued email over SMTP.
+    pub fn deliver(&self, mail: &QueuedMail) -> impl Future<Item = (), Error = Error> {
+        let email = match self.build(&mail.recipient, &mail.subject, &mail.body) {
             Ok(email) => email,
-            Err(e) => return Either::B(err(e.into())),
+            Err(e) => return Either::B(err(e)),
         };
 
         let inner = self.inner.clone();
@@ -86,6 +79,34 @@ impl Mailer {
                 }),
         )
     }
+
+    /// Sends an email, by queueing it to be delivered. The email is checked first, so mail to an
+    /// invalid address fails here rather than in the outbox.
+    pub fn send(
+        &self,
+        to: &str,
+        subject: &str,
+        text: &str,
+    ) -> impl Future<Item = (), Error = Error> {
+        if let Err(e) = self.build(to, subject, text) {
+            return Either::B(err(e));
+        }
+        Either::A(
+            self.db
+                .queue_mail(to.to_string(), subject.to_string(), text.to_string()),
+        )
+    }
+
+    /// Builds an email from the configured address.
+    fn build(&self, to: &str, subject: &str, text: &str) -> Fallible<Email> {
+        EmailBuilder::new()
+            .to(to)
+            .from(self.inner.from.as_str())
+            .subject(subject)
+            .text(text)
+            .build()
+            .map_err(Error::from)
+    }
 }
 
 #[allow(missing_debug_implementations)]
@@ -4,25 +4,55 @@
 //! > want to use "model"), is the only module that does any talking to the database, or any other
 //! > IO or interaction with other kinds of externalized state for that matter.
 
+mod events;
 mod mailer;
+mod oidc;
 #[allow(proc_macro_derive_resolution_fallback, unused_import_braces)]
 mod schema;
 
 embed_migrations!("migrations");
 
-pub use crate::dal::mailer::Mailer;
+/// The length of a team's join code.
+const JOIN_CODE_LEN: usize = 10;
+
+/// The number of digits in a login code.
+const LOGIN_CODE_LEN: usize = 6;
+
+/// The number of wrong guesses a login code tolerates before it stops working.
+const MAX_LOGIN_CODE_ATTEMPTS: i32 = 5;
+
+/// The most characters a team's name can have.
+const MAX_TEAM_NAME_LEN: usize = 64;
+
+sql_function! {
+    /// Converts a string to lowercase, for comparing usernames and emails case-insensitively.
+    fn lower(x: Text) -> Text;
+}
+
+pub use crate::dal::{events::Events, mailer::Mailer, oidc::Oidc};
 use crate::{
-    dal::schema::{auths, logins, teams, users},
-    schema::{Team, User},
-    util::blocking,
+    dal::schema::{
+        announcements, api_tokens, attachments, audit_log, auths, divisions, email_changes,
+        identities, invites, login_requests, logins, mail_outbox, passkeys, problems,
+        recovery_codes, solves, teams, ticket_messages, tickets, totp_secrets, users,
+        webauthn_challenges, wrong_flags,
+    },
+    schema::{
+        Announcement, ApiToken, Attachment, ClientInfo, Division, Invite, OidcIdentity, Passkey,
+        PasskeyCredential, Problem, ProblemDraft, ProblemListing, QueuedMail, Session, Solve,
+        Standing, Team, TeamMember, TeamProfile, Ticket, TicketMessage, User,
+    },
+    util::{blocking, random_code, random_digits},
 };
 use chrono::{DateTime, Utc};
 use diesel::{
-    dsl::{insert_into, now, update},
+    dsl::{delete, insert_into, now, update},
     prelude::*,
     r2d2::{ConnectionManager, Pool},
+    result::{DatabaseErrorKind, Error as DieselError},
+    sql_types::Text,
 };
-use failure::{bail, Error};
+use failure::Error;
 use futures::{
     future::{err, Either},
     Future,
@@ -45,10 +75,347 @@ impl DB {
         Ok(DB { pool })
     }
 
-    /// Turns a login link into an authentication token, invalidating the login link.
-    pub fn consume_login_link(&self, login: Uuid) -> impl Future<Item = Uuid, Error = Error> {
+    /// Accepts an invite to a team with the token from the invite mail. The invite is accepted by
+    /// the given user if there is one. Otherwise, the invite's email address mustn't be registered
+    /// yet; a user is created for it with the given username, logged in, and the authentication
+    /// token is returned.
+    pub fn accept_invite(
+        &self,
+        token: Uuid,
+        user: Option<i32>,
+        username: Option<String>,
+        max_team_size: i64,
+        session_expires: DateTime<Utc>,
+        client: ClientInfo,
+    ) -> impl Future<Item = Option<Uuid>, Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let invite = update(
+                    invites::table
+                        .filter(invites::expires.gt(now))
+                        .filter(invites::used.eq(false))
+                        .filter(invites::token.eq(token)),
+                )
+                .set(invites::used.eq(true))
+                .returning((invites::teamid, invites::email))
+                .get_result::<(Uuid, String)>(conn)
+                .optional()?;
+                let (team, email) = match invite {
+                    Some(invite) => invite,
+                    None => user_bail!(NotFound, "That invite is invalid or has expired."),
+                };
+
+                let (user, token) = if let Some(user) = user {
+                    let banned = users::table
+                        .find(user)
+                        .select(users::banned)
+                        .get_result::<bool>(conn)?;
+                    if banned {
+                        user_bail!(Forbidden, "This account has been banned.");
+                    }
+                    (user, None)
+                } else {
+                    let existing = users::table
+                        .filter(lower(users::email).eq(lower(&email)))
+                        .count()
+                        .get_result::<i64>(conn)?;
+                    if existing != 0 {
+                        user_bail!(
+                            Unauthorized,
+                            "You already have an account; log in, then open the invite link again."
+                        );
+                    }
+                    let username = match username {
+                        Some(username) => username,
+                        None => user_bail!(BadRequest, "Pick a username to register."),
+                    };
+
+                    // The token was only ever mailed to this address, so having it verifies the
+                    // address just like a login link does.
+                    let user = insert_into(users::table)
+                        .values((users::name.eq(username), users::email.eq(&email)))
+                        .returning(users::id)
+                        .get_result(conn)?;
+                    let token = create_session(conn, user, session_expires, &client)?;
+                    (user, Some(token))
+                };
+
+                add_to_team(conn, user, team, max_team_size)?;
+                Ok(token)
+            })
+        })
+    }
+
+    /// Replies to a ticket as a mod. If `public` is set, the reply is also posted as an
+    /// announcement, titled with the ticket's subject and about the same problem as the ticket,
+    /// and the announcement's ID is returned. Used by admins.
+    pub fn answer_ticket(
+        &self,
+        admin: i32,
+        ticket: i32,
+        body: String,
+        public: bool,
+    ) -> impl Future<Item = (Ticket, Option<i32>), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let ticket = load_ticket(conn, ticket)?;
+                let announcement = if public {
+                    let problem = tickets::table
+                        .find(ticket.id)
+                        .select(tickets::problemid)
+                        .get_result::<Option<i32>>(conn)?;
+                    let id = insert_into(announcements::table)
+                        .values((
+                            announcements::title.eq(&ticket.subject),
+                            announcements::body.eq(&body),
+                            announcements::problemid.eq(problem),
+                        ))
+                        .returning(announcements::id)
+                        .get_result::<i32>(conn)?;
+                    Some(id)
+                } else {
+                    None
+                };
+                let _ = insert_into(ticket_messages::table)
+                    .values((
+                        ticket_messages::ticketid.eq(ticket.id),
+                        ticket_messages::userid.eq(admin),
+                        ticket_messages::from_mod.eq(true),
+                        ticket_messages::body.eq(&body),
+                        ticket_messages::announcementid.eq(announcement),
+                    ))
+                    .execute(conn)?;
+                let note = if public {
+                    format!("Answered ticket {} publicly", ticket.id)
+                } else {
+                    format!("Answered ticket {}", ticket.id)
+                };
+                audit(conn, admin, note)?;
+                Ok((ticket, announcement))
+            })
+        })
+    }
+
+    /// Cancels a user's pending email change, if they have one.
+    pub fn cancel_email_change(&self, user: i32) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            update(
+                email_changes::table
+                    .filter(email_changes::userid.eq(user))
+                    .filter(email_changes::used.eq(false)),
+            )
+            .set(email_changes::used.eq(true))
+            .execute(conn)
+            .map(|_| ())
+        })
+    }
+
+    /// Claims up to `limit` mails that are due to be delivered, so they aren't claimed again until
+    /// `lease_until`. Mails being claimed by another server at the same time are skipped.
+    pub fn claim_due_mail(
+        &self,
+        limit: i64,
+        lease_until: DateTime<Utc>,
+    ) -> impl Future<Item = Vec<QueuedMail>, Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let mail = mail_outbox::table
+                    .filter(mail_outbox::sent.is_null())
+                    .filter(mail_outbox::failed.eq(false))
+                    .filter(mail_outbox::next_attempt.le(now))
+                    .order(mail_outbox::next_attempt)
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .load::<QueuedMail>(conn)?;
+                let ids = mail.iter().map(|mail| mail.id).collect::<Vec<_>>();
+                let _ = update(mail_outbox::table.filter(mail_outbox::id.eq_any(ids)))
+                    .set(mail_outbox::next_attempt.eq(lease_until))
+                    .execute(conn)?;
+                Ok(mail)
+            })
+        })
+    }
+
+    /// Deletes login links and email changes that have expired or been used, sessions that have
+    /// expired or belong to banned users, expired passkey challenges, delivered mail, and login
+    /// requests, wrong flags, and invites made or expired before the given time. Returns how many
+    /// login links and sessions were deleted.
+    pub fn clean_up(
+        &self,
+        requests_before: DateTime<Utc>,
+    ) -> impl Future<Item = (usize, usize), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let logins =
+                    delete(logins::table.filter(logins::expires.lt(now).or(logins::used.eq(true))))
+                        .execute(conn)?;
+                let banned = users::table
+                    .filter(users::banned.eq(true))
+                    .select(users::id);
+                let auths = delete(
+                    auths::table.filter(auths::expires.lt(now).or(auths::userid.eq_any(banned))),
+                )
+                .execute(conn)?;
+                let _ =
+                    delete(login_requests::table.filter(login_requests::at.lt(requests_before)))
+                        .execute(conn)?;
+                let _ = delete(invites::table.filter(invites::expires.lt(requests_before)))
+                    .execute(conn)?;
+                let _ = delete(wrong_flags::table.filter(wrong_flags::at.lt(requests_before)))
+                    .execute(conn)?;
+                let _ = delete(
+                    email_changes::table.filter(
+                        email_changes::expires
+                            .lt(now)
+                            .or(email_changes::used.eq(true)),
+                    ),
+                )
+                .execute(conn)?;
+                let _ =
+                    delete(webauthn_challenges::table.filter(webauthn_challenges::expires.lt(now)))
+                        .execute(conn)?;
+                let _ = delete(mail_outbox::table.filter(mail_outbox::sent.is_not_null()))
+                    .execute(conn)?;
+                Ok((logins, auths))
+            })
+        })
+    }
+
+    /// Closes a ticket. Used by admins.
+    pub fn close_ticket(
+        &self,
+        admin: i32,
+        ticket: i32,
+    ) -> impl Future<Item = Ticket, Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let closed = update(
+                    tickets::table
+                        .find(ticket)
+                        .filter(tickets::closed.is_null()),
+                )
+                .set(tickets::closed.eq(Utc::now()))
+                .returning(tickets::id)
+                .get_result::<i32>(conn)
+                .optional()?;
+                match closed {
+                    Some(ticket) => {
+                        audit(conn, admin, format!("Closed ticket {}", ticket))?;
+                        load_ticket(conn, ticket)
+                    }
+                    None => user_bail!(BadRequest, "That ticket is already closed."),
+                }
+            })
+        })
+    }
+
+    /// Applies an email change, which stops working along with the user's other pending email
+    /// changes and login links. Fails if the new address isn't eligible for the division of the
+    /// user's team.
+    pub fn confirm_email_change(&self, change: Uuid) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let pending = update(
+                    email_changes::table
+                        .filter(email_changes::expires.gt(now))
+                        .filter(email_changes::used.eq(false))
+                        .find(change),
+                )
+                .set(email_changes::used.eq(true))
+                .returning((email_changes::userid, email_changes::email))
+                .get_result::<(i32, String)>(conn)
+                .optional()?;
+                let (user, email) = match pending {
+                    Some(pending) => pending,
+                    None => user_bail!(NotFound, "That link is invalid or has expired."),
+                };
+
+                let team = update(users::table.find(user))
+                    .set(users::email.eq(&email))
+                    .returning(users::teamid)
+                    .get_result::<Option<Uuid>>(conn)?;
+                if let Some(team) = team {
+                    if !division_eligible(conn, team)? {
+                        user_bail!(
+                            BadRequest,
+                            "Your new email address isn't eligible for your team's division."
+                        );
+                    }
+                }
+
+                let _ = update(
+                    email_changes::table
+                        .filter(email_changes::userid.eq(user))
+                        .filter(email_changes::used.eq(false)),
+                )
+                .set(email_changes::used.eq(true))
+                .execute(conn)?;
+                let _ = update(
+                    logins::table
+                        .filter(logins::userid.eq(user))
+                        .filter(logins::used.eq(false)),
+                )
+                .set(logins::used.eq(true))
+                .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Checks a login code, typed in by the user with the given username or email address,
+    /// invalidating the login link it was sent with. Returns the ID of the user it was sent to. A
+    /// wrong guess counts against all of the user's outstanding login codes, which stop working
+    /// after `MAX_LOGIN_CODE_ATTEMPTS` of them.
+    pub fn consume_login_code(
+        &self,
+        login: String,
+        code: String,
+    ) -> impl Future<Item = i32, Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let user = conn.transaction::<_, Error, _>(|| {
+                let user = users::table
+                    .filter(
+                        lower(users::name)
+                            .eq(lower(&login))
+                            .or(lower(users::email).eq(lower(&login))),
+                    )
+                    .select(users::id)
+                    .get_result::<i32>(conn)
+                    .optional()?;
+                let user = match user {
+                    Some(user) => user,
+                    None => return Ok(None),
+                };
+
+                let live = logins::table
+                    .filter(logins::userid.eq(user))
+                    .filter(logins::expires.gt(now))
+                    .filter(logins::used.eq(false))
+                    .filter(logins::attempts.lt(MAX_LOGIN_CODE_ATTEMPTS));
+                let matched = update(live.clone().filter(logins::code.eq(&code)))
+                    .set(logins::used.eq(true))
+                    .returning(logins::userid)
+                    .get_result::<i32>(conn)
+                    .optional()?;
+                if matched.is_none() {
+                    let _ = update(live)
+                        .set(logins::attempts.eq(logins::attempts + 1))
+                        .execute(conn)?;
+                }
+                Ok(matched)
+            })?;
+
+            match user {
+                Some(user) => Ok(user),
+                None => user_bail!(BadRequest, "That code is wrong or has expired."),
+            }
+        })
+    }
+
+    /// Checks a login link, invalidating it. Returns the ID of the user it was sent to.
+    pub fn consume_login_link(&self, login: Uuid) -> impl Future<Item = i32, Error = Error> {
         self.async_query(move |conn| {
-            let token = Uuid::new_v4();
             update(
                 logins::table
                     .filter(logins::expires.gt(now))
@@ -58,142 +425,2112 @@ impl DB {
             .set(logins::used.eq(true))
             .returning(logins::userid)
             .get_result(conn)
-            .and_then(|user: i32| {
-                insert_into(auths::table)
-                    .values((auths::id.eq(token), auths::userid.eq(user)))
-                    .execute(conn)
+        })
+    }
+
+    /// Counts the invites sent by the team captained by the given user, and the invites sent to an
+    /// email address by any team, since the given time. Revoked invites count too.
+    pub fn count_invites(
+        &self,
+        captain: i32,
+        email: String,
+        since: DateTime<Utc>,
+    ) -> impl Future<Item = (i64, i64), Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let team = captained_team(conn, captain)?;
+            let team_invites = invites::table
+                .filter(invites::teamid.eq(team))
+                .filter(invites::created.gt(since))
+                .count()
+                .get_result(conn)?;
+            let email_invites = invites::table
+                .filter(lower(invites::email).eq(lower(&email)))
+                .filter(invites::created.gt(since))
+                .count()
+                .get_result(conn)?;
+            Ok((team_invites, email_invites))
+        })
+    }
+
+    /// Counts the login mails requested from an IP address since the given time.
+    pub fn count_login_requests(
+        &self,
+        ip: String,
+        since: DateTime<Utc>,
+    ) -> impl Future<Item = i64, Error = Error> {
+        self.async_query(move |conn| {
+            login_requests::table
+                .filter(login_requests::ip.eq(&ip))
+                .filter(login_requests::at.gt(since))
+                .count()
+                .get_result(conn)
+        })
+    }
+
+    /// Counts a user's unused recovery codes.
+    pub fn count_recovery_codes(&self, user: i32) -> impl Future<Item = i64, Error = Error> {
+        self.async_query(move |conn| {
+            recovery_codes::table
+                .filter(recovery_codes::userid.eq(user))
+                .filter(recovery_codes::used.eq(false))
+                .count()
+                .get_result(conn)
+        })
+    }
+
+    /// Counts the wrong flags the given user's team has submitted since the given time. A user
+    /// without a team hasn't submitted any.
+    pub fn count_wrong_flags(
+        &self,
+        user: i32,
+        since: DateTime<Utc>,
+    ) -> impl Future<Item = i64, Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let team = users::table
+                .find(user)
+                .select(users::teamid)
+                .get_result::<Option<Uuid>>(conn)?;
+            let count = match team {
+                Some(team) => wrong_flags::table
+                    .filter(wrong_flags::teamid.eq(team))
+                    .filter(wrong_flags::at.gt(since))
+                    .count()
+                    .get_result(conn)?,
+                None => 0,
+            };
+            Ok(count)
+        })
+    }
+
+    /// Posts an announcement, optionally about a problem, returning its ID and the name of the
+    /// problem. Used by admins.
+    pub fn create_announcement(
+        &self,
+        admin: i32,
+        title: String,
+        body: String,
+        problem: Option<i32>,
+    ) -> impl Future<Item = (i32, Option<String>), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let problem_name = match problem {
+                    Some(problem) => Some(
+                        problems::table
+                            .find(problem)
+                            .select(problems::name)
+                            .get_result::<String>(conn)?,
+                    ),
+                    None => None,
+                };
+                let id = insert_into(announcements::table)
+                    .values((
+                        announcements::title.eq(&title),
+                        announcements::body.eq(&body),
+                        announcements::problemid.eq(problem),
+                    ))
+                    .returning(announcements::id)
+                    .get_result(conn)?;
+                audit(conn, admin, format!("Posted announcement {}", title))?;
+                Ok((id, problem_name))
             })
-            .map(|_| token)
         })
     }
 
-    /// Creates a login link for the given user, returning the relevant UUID.
-    pub fn create_login_link(
+    /// Creates an API token for a user, given its hash.
+    pub fn create_api_token(
         &self,
         user: i32,
-        expires: DateTime<Utc>,
-    ) -> impl Future<Item = Uuid, Error = Error> {
+        name: String,
+        hash: Vec<u8>,
+    ) -> impl Future<Item = (), Error = Error> {
         self.async_query(move |conn| {
-            let login = Uuid::new_v4();
-            insert_into(logins::table)
+            insert_into(api_tokens::table)
                 .values((
-                    logins::id.eq(login),
-                    logins::userid.eq(user),
-                    logins::expires.eq(expires),
+                    api_tokens::userid.eq(user),
+                    api_tokens::hash.eq(&hash),
+                    api_tokens::name.eq(&name),
                 ))
                 .execute(conn)
-                .map(|_| login)
+                .map(|_| ())
         })
     }
 
-    /// Creates a team, adding the user to it, with the given name, returning its ID.
-    pub fn create_team(&self, user: i32, name: String) -> impl Future<Item = Uuid, Error = Error> {
+    /// Creates a division. Used by admins.
+    pub fn create_division(
+        &self,
+        admin: i32,
+        name: String,
+        email_domains: Vec<String>,
+    ) -> impl Future<Item = (), Error = Error> {
         self.async_query(move |conn| {
-            conn.transaction(|| {
-                let has_team = users::table
+            conn.transaction(|| -> Result<_, Error> {
+                let _ = insert_into(divisions::table)
+                    .values((
+                        divisions::name.eq(&name),
+                        divisions::email_domains.eq(&email_domains),
+                    ))
+                    .execute(conn)?;
+                audit(
+                    conn,
+                    admin,
+                    format!(
+                        "Created division {} for domains [{}]",
+                        name,
+                        email_domains.join(", ")
+                    ),
+                )?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Creates a pending change of the given user's email address, which replaces any other
+    /// pending change. Returns the change's ID and the user's current email address.
+    pub fn create_email_change(
+        &self,
+        user: i32,
+        email: String,
+        expires: DateTime<Utc>,
+    ) -> impl Future<Item = (Uuid, String), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let old_email = users::table
                     .find(user)
-                    .select(users::teamid.is_not_null())
+                    .select(users::email)
+                    .get_result::<String>(conn)?;
+                if old_email.to_lowercase() == email.to_lowercase() {
+                    user_bail!(BadRequest, "That's already your email address.");
+                }
+
+                let _ = update(
+                    email_changes::table
+                        .filter(email_changes::userid.eq(user))
+                        .filter(email_changes::used.eq(false)),
+                )
+                .set(email_changes::used.eq(true))
+                .execute(conn)?;
+                let id = Uuid::new_v4();
+                let _ = insert_into(email_changes::table)
+                    .values((
+                        email_changes::id.eq(id),
+                        email_changes::userid.eq(user),
+                        email_changes::email.eq(&email),
+                        email_changes::expires.eq(expires),
+                    ))
+                    .execute(conn)?;
+                Ok((id, old_email))
+            })
+        })
+    }
+
+    /// Creates an invite to the team captained by the given user. Returns the invite's secret
+    /// token, the team's name, and the captain's name.
+    pub fn create_invite(
+        &self,
+        captain: i32,
+        email: String,
+        expires: DateTime<Utc>,
+    ) -> impl Future<Item = (Uuid, String, String), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let team = captained_team(conn, captain)?;
+                let token = Uuid::new_v4();
+                let _ = insert_into(invites::table)
+                    .values((
+                        invites::id.eq(Uuid::new_v4()),
+                        invites::teamid.eq(team),
+                        invites::email.eq(&email),
+                        invites::expires.eq(expires),
+                        invites::token.eq(token),
+                    ))
+                    .execute(conn)?;
+                let team_name = teams::table
+                    .find(team)
+                    .select(teams::name)
+                    .get_result(conn)?;
+                let captain_name = users::table
+                    .find(captain)
+                    .select(users::name)
                     .get_result(conn)?;
-                if has_team {
-                    bail!("You already have a team!");
+                Ok((token, team_name, captain_name))
+            })
+        })
+    }
+
+    /// Creates a login link for the given user, returning the relevant UUID, the login code that
+    /// can be typed in instead, and when they expire. If the user has an unused login link that
+    /// expires after `reuse_after`, that's returned instead; otherwise, the user's older login
+    /// links stop working.
+    pub fn create_login_link(
+        &self,
+        user: i32,
+        expires: DateTime<Utc>,
+        reuse_after: DateTime<Utc>,
+    ) -> impl Future<Item = (Uuid, String, DateTime<Utc>), Error = Error> {
+        self.async_query(move |conn| {
+            let existing = logins::table
+                .filter(logins::userid.eq(user))
+                .filter(logins::expires.gt(reuse_after))
+                .filter(logins::used.eq(false))
+                .filter(logins::attempts.lt(MAX_LOGIN_CODE_ATTEMPTS))
+                .order(logins::expires.desc())
+                .select((logins::id, logins::code, logins::expires))
+                .first(conn)
+                .optional()?;
+            if let Some(existing) = existing {
+                return Ok(existing);
+            }
+
+            conn.transaction(|| {
+                let _ = update(
+                    logins::table
+                        .filter(logins::userid.eq(user))
+                        .filter(logins::used.eq(false)),
+                )
+                .set(logins::used.eq(true))
+                .execute(conn)?;
+
+                let login = Uuid::new_v4();
+                let code = random_digits(LOGIN_CODE_LEN);
+                insert_into(logins::table)
+                    .values((
+                        logins::id.eq(login),
+                        logins::userid.eq(user),
+                        logins::expires.eq(expires),
+                        logins::code.eq(&code),
+                    ))
+                    .execute(conn)
+                    .map(|_| (login, code, expires))
+            })
+        })
+    }
+
+    /// Registers a passkey for a user, using up the challenge it was created in response to. Fails
+    /// if the challenge wasn't for this user, or has expired.
+    pub fn create_passkey(
+        &self,
+        user: i32,
+        challenge: Uuid,
+        name: String,
+        credential_id: Vec<u8>,
+        algorithm: i32,
+        public_key: Vec<u8>,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let used = delete(
+                    webauthn_challenges::table
+                        .filter(webauthn_challenges::userid.eq(user))
+                        .filter(webauthn_challenges::expires.gt(now))
+                        .find(challenge),
+                )
+                .execute(conn)?;
+                if used == 0 {
+                    user_bail!(
+                        BadRequest,
+                        "That passkey took too long to set up. Try again?"
+                    );
                 }
 
+                let _ = insert_into(passkeys::table)
+                    .values((
+                        passkeys::userid.eq(user),
+                        passkeys::credential_id.eq(&credential_id),
+                        passkeys::algorithm.eq(algorithm),
+                        passkeys::public_key.eq(&public_key),
+                        passkeys::name.eq(&name),
+                    ))
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Creates a personal team for the user, unless they already have a team.
+    pub fn create_personal_team(&self, user: i32) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| conn.transaction(|| give_personal_team(conn, user)))
+    }
+
+    /// Creates a problem and its attachments. Used by admins.
+    pub fn create_problem(
+        &self,
+        admin: i32,
+        draft: ProblemDraft,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let problem = insert_into(problems::table)
+                    .values((
+                        problems::name.eq(&draft.name),
+                        problems::category.eq(&draft.category),
+                        problems::description.eq(&draft.description),
+                        problems::points.eq(draft.points),
+                        problems::flag.eq(&draft.flag),
+                        problems::visible.eq(draft.visible),
+                    ))
+                    .returning(problems::id)
+                    .get_result(conn)?;
+                insert_attachments(conn, problem, &draft.attachments)?;
+                audit(conn, admin, format!("Created problem {}", draft.name))?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Creates a session for a user, returning its authentication token.
+    pub fn create_session(
+        &self,
+        user: i32,
+        expires: DateTime<Utc>,
+        client: ClientInfo,
+    ) -> impl Future<Item = Uuid, Error = Error> {
+        self.async_query(move |conn| create_session(conn, user, expires, &client))
+    }
+
+    /// Creates a team, adding the user to it, with the given name, returning its ID.
+    pub fn create_team(&self, user: i32, name: String) -> impl Future<Item = Uuid, Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let personal = leave_personal_team(conn, user)?;
+
                 let id = Uuid::new_v4();
                 let _ = insert_into(teams::table)
-                    .values((teams::id.eq(id), teams::name.eq(&name)))
+                    .values((
+                        teams::id.eq(id),
+                        teams::name.eq(&name),
+                        teams::captain.eq(user),
+                        teams::join_code.eq(random_code(JOIN_CODE_LEN)),
+                    ))
                     .execute(conn)?;
                 let _ = update(users::table.find(user))
                     .set(users::teamid.eq(id))
                     .execute(conn)?;
+                dissolve_personal_team(conn, personal, id)?;
                 Ok(id)
             })
         })
     }
 
-    /// Creates a user, returning their ID.
+    /// Opens a ticket for the user's team, optionally about a problem players can see, returning
+    /// the ticket and the team's name.
+    pub fn create_ticket(
+        &self,
+        user: i32,
+        subject: String,
+        body: String,
+        problem: Option<i32>,
+    ) -> impl Future<Item = (Ticket, String), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let team = users::table
+                    .inner_join(teams::table)
+                    .filter(users::id.eq(user))
+                    .select((teams::id, teams::name))
+                    .get_result::<(Uuid, String)>(conn)
+                    .optional()?;
+                let (team, team_name) = match team {
+                    Some(team) => team,
+                    None => user_bail!(NotFound, "You don't have a team!"),
+                };
+
+                // Hidden problems can't be picked, so their names don't show up in tickets.
+                if let Some(problem) = problem {
+                    let visible = problems::table
+                        .find(problem)
+                        .filter(problems::visible.eq(true))
+                        .count()
+                        .get_result::<i64>(conn)?;
+                    if visible == 0 {
+                        user_bail!(NotFound, "That problem doesn't exist.");
+                    }
+                }
+
+                let id = insert_into(tickets::table)
+                    .values((
+                        tickets::teamid.eq(team),
+                        tickets::subject.eq(&subject),
+                        tickets::problemid.eq(problem),
+                    ))
+                    .returning(tickets::id)
+                    .get_result(conn)?;
+                let _ = insert_into(ticket_messages::table)
+                    .values((
+                        ticket_messages::ticketid.eq(id),
+                        ticket_messages::userid.eq(user),
+                        ticket_messages::from_mod.eq(false),
+                        ticket_messages::body.eq(&body),
+                    ))
+                    .execute(conn)?;
+                Ok((load_ticket(conn, id)?, team_name))
+            })
+        })
+    }
+
+    /// Creates a TOTP secret for a user, which only takes effect once `enable_totp` is called.
+    /// Replaces any secret whose setup wasn't finished. Fails if the user already has TOTP set up.
+    pub fn create_totp_secret(
+        &self,
+        user: i32,
+        secret: Vec<u8>,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let enabled = totp_secrets::table
+                    .find(user)
+                    .select(totp_secrets::enabled)
+                    .get_result::<bool>(conn)
+                    .optional()?;
+                if enabled == Some(true) {
+                    user_bail!(BadRequest, "Two-factor authentication is already set up.");
+                }
+
+                let _ = delete(totp_secrets::table.find(user)).execute(conn)?;
+                let _ = insert_into(totp_secrets::table)
+                    .values((
+                        totp_secrets::userid.eq(user),
+                        totp_secrets::secret.eq(&secret),
+                    ))
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Creates a user, returning their ID and whether they were created. If a user with the email
+    /// address already exists, they're returned instead. The username is checked first, so a taken
+    /// or invalid one is refused the same way whether or not the address has an account.
     pub fn create_user(
         &self,
         username: String,
         email: String,
-    ) -> impl Future<Item = i32, Error = Error> {
+    ) -> impl Future<Item = (i32, bool), Error = Error> {
         self.async_query(move |conn| {
-            insert_into(users::table)
-                .values((users::name.eq(&username), users::email.eq(&email)))
-                .returning(users::id)
-                .get_result(conn)
+            conn.transaction(|| -> Result<_, Error> {
+                let taken = users::table
+                    .filter(lower(users::name).eq(lower(&username)))
+                    .count()
+                    .get_result::<i64>(conn)?;
+                if taken != 0 {
+                    user_bail!(BadRequest, "This username is already taken.");
+                }
+
+                // Check constraints fail before unique ones, so an invalid username is refused
+                // here even if the address is taken. The insert is in a savepoint, so the user
+                // with the address can still be looked up if it is.
+                let inserted = conn.transaction(|| {
+                    insert_into(users::table)
+                        .values((users::name.eq(&username), users::email.eq(&email)))
+                        .returning(users::id)
+                        .get_result::<i32>(conn)
+                });
+                match inserted {
+                    Ok(user) => Ok((user, true)),
+                    Err(DieselError::DatabaseError(
+                        DatabaseErrorKind::UniqueViolation,
+                        ref info,
+                    )) if info.constraint_name() == Some("users_email_key") => {
+                        let user = users::table
+                            .filter(lower(users::email).eq(lower(&email)))
+                            .select(users::id)
+                            .get_result(conn)?;
+                        Ok((user, false))
+                    }
+                    Err(err) => Err(err.into()),
+                }
+            })
         })
     }
 
-    /// Looks up an authentication record, returning the ID of the user it corresponds to.
-    pub fn get_auth_user(&self, auth: Uuid) -> impl Future<Item = i32, Error = Error> {
+    /// Creates a challenge for a passkey to sign, returning its ID, whose bytes are the challenge.
+    /// Challenges for registering a passkey are for the user registering it; ones for logging in
+    /// aren't for anyone.
+    pub fn create_webauthn_challenge(
+        &self,
+        user: Option<i32>,
+        expires: DateTime<Utc>,
+    ) -> impl Future<Item = Uuid, Error = Error> {
         self.async_query(move |conn| {
-            auths::table
-                .find(auth)
-                .select(auths::userid)
-                .get_result(conn)
+            let id = Uuid::new_v4();
+            insert_into(webauthn_challenges::table)
+                .values((
+                    webauthn_challenges::id.eq(id),
+                    webauthn_challenges::userid.eq(user),
+                    webauthn_challenges::expires.eq(expires),
+                ))
+                .execute(conn)
+                .map(|_| id)
         })
     }
 
-    /// Gets a team by ID.
-    pub fn get_team(&self, team: Uuid) -> impl Future<Item = Team, Error = Error> {
-        self.async_query(move |conn| teams::table.find(team).get_result(conn))
+    /// Deletes one of a user's API tokens.
+    pub fn delete_api_token(&self, user: i32, token: i32) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            delete(
+                api_tokens::table
+                    .filter(api_tokens::userid.eq(user))
+                    .filter(api_tokens::id.eq(token)),
+            )
+            .execute(conn)
+            .map(|_| ())
+        })
     }
 
-    /// Gets a team's member's names.
-    pub fn get_team_members(&self, team: Uuid) -> impl Future<Item = Vec<String>, Error = Error> {
+    /// Deletes a division. Teams in it are left without a division. Used by admins.
+    pub fn delete_division(
+        &self,
+        admin: i32,
+        division: i32,
+    ) -> impl Future<Item = (), Error = Error> {
         self.async_query(move |conn| {
-            users::table
-                .filter(users::teamid.eq(team))
-                .select(users::name)
-                .get_results(conn)
+            conn.transaction(|| -> Result<_, Error> {
+                let name = delete(divisions::table.find(division))
+                    .returning(divisions::name)
+                    .get_result::<String>(conn)?;
+                audit(conn, admin, format!("Deleted division {}", name))?;
+                Ok(())
+            })
         })
     }
 
-    /// Gets a user by ID.
-    pub fn get_user(&self, user: i32) -> impl Future<Item = User, Error = Error> {
-        self.async_query(move |conn| users::table.find(user).get_result(conn))
+    /// Deletes one of a user's passkeys.
+    pub fn delete_passkey(&self, user: i32, passkey: i32) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            delete(
+                passkeys::table
+                    .filter(passkeys::userid.eq(user))
+                    .filter(passkeys::id.eq(passkey)),
+            )
+            .execute(conn)
+            .map(|_| ())
+        })
     }
 
-    /// Gets a user by username.
-    pub fn get_user_by_username(
-        &self,
-        username: String,
-    ) -> impl Future<Item = User, Error = Error> {
+    /// Deletes a session, logging it out.
+    pub fn delete_session(&self, auth: Uuid) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| delete(auths::table.find(auth)).execute(conn).map(|_| ()))
+    }
+
+    /// Turns off TOTP for a user, deleting their secret and recovery codes. Their sessions are no
+    /// longer stepped up.
+    pub fn disable_totp(&self, user: i32) -> impl Future<Item = (), Error = Error> {
         self.async_query(move |conn| {
-            users::table
-                .filter(users::name.eq(&username))
-                .get_result(conn)
+            conn.transaction(|| {
+                let _ = delete(totp_secrets::table.find(user)).execute(conn)?;
+                let _ = delete(recovery_codes::table.filter(recovery_codes::userid.eq(user)))
+                    .execute(conn)?;
+                let _ = update(auths::table.filter(auths::userid.eq(user)))
+                    .set(auths::stepped_up.eq(None::<DateTime<Utc>>))
+                    .execute(conn)?;
+                Ok(())
+            })
         })
     }
 
-    /// Adds a user to a team.
-    pub fn join_team(&self, user: i32, team: Uuid) -> impl Future<Item = (), Error = Error> {
+    /// Deletes a team, which must not have any members. Used by admins.
+    pub fn disband_team(&self, admin: i32, team: Uuid) -> impl Future<Item = (), Error = Error> {
         self.async_query(move |conn| {
             conn.transaction(|| {
-                let has_team = users::table
-                    .find(user)
-                    .select(users::teamid.is_not_null())
-                    .get_result(conn)?;
-                if has_team {
-                    bail!("You already have a team!");
-                }
-
                 let members = users::table
                     .filter(users::teamid.eq(team))
                     .count()
                     .get_result::<i64>(conn)?;
-                if members >= 4 {
-                    bail!("The team is full.");
+                if members != 0 {
+                    user_bail!(BadRequest, "Only empty teams can be disbanded.");
                 }
 
-                let _ = update(users::table.find(user))
-                    .set(users::teamid.eq(team))
-                    .execute(conn)?;
+                let name = delete(teams::table.find(team))
+                    .returning(teams::name)
+                    .get_result::<String>(conn)?;
+                audit(conn, admin, format!("Disbanded team {}", name))?;
                 Ok(())
             })
         })
     }
 
+    /// Edits an announcement, including which problem it's about. Used by admins.
+    pub fn edit_announcement(
+        &self,
+        admin: i32,
+        announcement: i32,
+        title: String,
+        body: String,
+        problem: Option<i32>,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let old_title = announcements::table
+                    .find(announcement)
+                    .select(announcements::title)
+                    .get_result::<String>(conn)?;
+                let _ = update(announcements::table.find(announcement))
+                    .set((
+                        announcements::title.eq(&title),
+                        announcements::body.eq(&body),
+                        announcements::edited.eq(Utc::now()),
+                        announcements::problemid.eq(problem),
+                    ))
+                    .execute(conn)?;
+                audit(conn, admin, format!("Edited announcement {}", old_title))?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Edits a problem, replacing its attachments. Used by admins.
+    pub fn edit_problem(
+        &self,
+        admin: i32,
+        problem: i32,
+        draft: ProblemDraft,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let old_name = problems::table
+                    .find(problem)
+                    .select(problems::name)
+                    .get_result::<String>(conn)?;
+                let _ = update(problems::table.find(problem))
+                    .set((
+                        problems::name.eq(&draft.name),
+                        problems::category.eq(&draft.category),
+                        problems::description.eq(&draft.description),
+                        problems::points.eq(draft.points),
+                        problems::flag.eq(&draft.flag),
+                        problems::visible.eq(draft.visible),
+                    ))
+                    .execute(conn)?;
+                let _ = delete(attachments::table.filter(attachments::problemid.eq(problem)))
+                    .execute(conn)?;
+                insert_attachments(conn, problem, &draft.attachments)?;
+                audit(conn, admin, format!("Edited problem {}", old_name))?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Finishes setting up TOTP for a user, recording the time step of the code that proved they
+    /// have the secret, and replacing their recovery codes with the given hashes.
+    pub fn enable_totp(
+        &self,
+        user: i32,
+        step: i64,
+        recovery_code_hashes: Vec<Vec<u8>>,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let enabled = update(
+                    totp_secrets::table
+                        .filter(totp_secrets::enabled.eq(false))
+                        .filter(totp_secrets::last_step.lt(step))
+                        .find(user),
+                )
+                .set((
+                    totp_secrets::enabled.eq(true),
+                    totp_secrets::last_step.eq(step),
+                ))
+                .execute(conn)?;
+                if enabled == 0 {
+                    user_bail!(
+                        BadRequest,
+                        "Start setting up two-factor authentication again."
+                    );
+                }
+
+                let _ = delete(recovery_codes::table.filter(recovery_codes::userid.eq(user)))
+                    .execute(conn)?;
+                let rows = recovery_code_hashes
+                    .iter()
+                    .map(|hash| {
+                        (
+                            recovery_codes::userid.eq(user),
+                            recovery_codes::hash.eq(hash),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let _ = insert_into(recovery_codes::table)
+                    .values(&rows)
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Gets the email addresses of the admins who aren't banned.
+    pub fn get_admin_emails(&self) -> impl Future<Item = Vec<String>, Error = Error> {
+        self.async_query(|conn| {
+            users::table
+                .filter(users::admin.eq(true))
+                .filter(users::banned.eq(false))
+                .select(users::email)
+                .load(conn)
+        })
+    }
+
+    /// Gets every problem, including hidden ones, along with its attachments, ordered by category
+    /// and name. Used by admins.
+    pub fn get_all_problems(
+        &self,
+    ) -> impl Future<Item = Vec<(Problem, Vec<Attachment>)>, Error = Error> {
+        self.async_query(|conn| -> Result<_, Error> {
+            let problems = problems::table
+                .order((problems::category, problems::name))
+                .load::<Problem>(conn)?;
+            let attachments = load_attachments(conn, &problems)?;
+            Ok(problems.into_iter().zip(attachments).collect())
+        })
+    }
+
+    /// Gets every announcement, newest first.
+    pub fn get_announcements(&self) -> impl Future<Item = Vec<Announcement>, Error = Error> {
+        self.async_query(|conn| {
+            announcements::table
+                .left_join(problems::table)
+                .order(announcements::created.desc())
+                .select((
+                    announcements::id,
+                    announcements::title,
+                    announcements::body,
+                    announcements::created,
+                    announcements::edited,
+                    problems::name.nullable(),
+                ))
+                .load(conn)
+        })
+    }
+
+    /// Looks up an API token by its hash, returning the ID of the user it belongs to, if it exists.
+    /// This also records that the token was used.
+    pub fn get_api_token_user(
+        &self,
+        hash: Vec<u8>,
+    ) -> impl Future<Item = Option<i32>, Error = Error> {
+        self.async_query(move |conn| {
+            update(api_tokens::table.filter(api_tokens::hash.eq(&hash)))
+                .set(api_tokens::last_used.eq(Utc::now()))
+                .returning(api_tokens::userid)
+                .get_result(conn)
+                .optional()
+        })
+    }
+
+    /// Gets a user's API tokens, oldest first.
+    pub fn get_api_tokens(&self, user: i32) -> impl Future<Item = Vec<ApiToken>, Error = Error> {
+        self.async_query(move |conn| {
+            api_tokens::table
+                .filter(api_tokens::userid.eq(user))
+                .order(api_tokens::created)
+                .select((
+                    api_tokens::id,
+                    api_tokens::name,
+                    api_tokens::created,
+                    api_tokens::last_used,
+                ))
+                .load(conn)
+        })
+    }
+
+    /// Looks up an unexpired session, returning the ID of the user it corresponds to and its CSRF
+    /// token. This also records that the session was used.
+    pub fn get_auth_user(&self, auth: Uuid) -> impl Future<Item = (i32, Uuid), Error = Error> {
+        self.async_query(move |conn| {
+            update(auths::table.filter(auths::expires.gt(now)).find(auth))
+                .set(auths::last_used.eq(now))
+                .returning((auths::userid, auths::csrf))
+                .get_result(conn)
+        })
+    }
+
+    /// Gets a division by name, along with the teams in it.
+    pub fn get_division_teams(
+        &self,
+        name: String,
+    ) -> impl Future<Item = (Division, Vec<Team>), Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let division = divisions::table
+                .filter(divisions::name.eq(&name))
+                .get_result::<Division>(conn)?;
+            let teams = teams::table
+                .filter(teams::divisionid.eq(division.id))
+                .order(teams::name)
+                .load::<Team>(conn)?;
+            Ok((division, teams))
+        })
+    }
+
+    /// Gets every division, ordered by name.
+    pub fn get_divisions(&self) -> impl Future<Item = Vec<Division>, Error = Error> {
+        self.async_query(|conn| divisions::table.order(divisions::name).load(conn))
+    }
+
+    /// Gets the new email address of an email change that can still be confirmed.
+    pub fn get_email_change(&self, change: Uuid) -> impl Future<Item = String, Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let email = email_changes::table
+                .filter(email_changes::expires.gt(now))
+                .filter(email_changes::used.eq(false))
+                .find(change)
+                .select(email_changes::email)
+                .get_result(conn)
+                .optional()?;
+            match email {
+                Some(email) => Ok(email),
+                None => user_bail!(NotFound, "That link is invalid or has expired."),
+            }
+        })
+    }
+
+    /// Gets the name of the team the invite with the given token is for, and whether a user with
+    /// the invite's email address already exists. Fails if the invite can no longer be used.
+    pub fn get_invite(&self, token: Uuid) -> impl Future<Item = (String, bool), Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let invite = invites::table
+                .inner_join(teams::table)
+                .filter(invites::expires.gt(now))
+                .filter(invites::used.eq(false))
+                .filter(invites::token.eq(token))
+                .select((teams::name, invites::email))
+                .get_result::<(String, String)>(conn)
+                .optional()?;
+            let (team, email) = match invite {
+                Some(invite) => invite,
+                None => user_bail!(NotFound, "That invite is invalid or has expired."),
+            };
+            let registered = users::table
+                .filter(lower(users::email).eq(lower(&email)))
+                .count()
+                .get_result::<i64>(conn)?;
+            Ok((team, registered != 0))
+        })
+    }
+
+    /// Gets the times login mails were sent to a user since the given time.
+    pub fn get_login_mail_times(
+        &self,
+        user: i32,
+        since: DateTime<Utc>,
+    ) -> impl Future<Item = Vec<DateTime<Utc>>, Error = Error> {
+        self.async_query(move |conn| {
+            login_requests::table
+                .filter(login_requests::userid.eq(user))
+                .filter(login_requests::mailed.eq(true))
+                .filter(login_requests::at.gt(since))
+                .select(login_requests::at)
+                .load(conn)
+        })
+    }
+
+    /// Gets the new email address and expiry time of a user's pending email change, if they have
+    /// one.
+    pub fn get_pending_email_change(
+        &self,
+        user: i32,
+    ) -> impl Future<Item = Option<(String, DateTime<Utc>)>, Error = Error> {
+        self.async_query(move |conn| {
+            email_changes::table
+                .filter(email_changes::userid.eq(user))
+                .filter(email_changes::expires.gt(now))
+                .filter(email_changes::used.eq(false))
+                .select((email_changes::email, email_changes::expires))
+                .first(conn)
+                .optional()
+        })
+    }
+
+    /// Gets the credential of the passkey with the given credential ID.
+    pub fn get_passkey(
+        &self,
+        credential_id: Vec<u8>,
+    ) -> impl Future<Item = PasskeyCredential, Error = Error> {
+        self.async_query(move |conn| {
+            passkeys::table
+                .filter(passkeys::credential_id.eq(&credential_id))
+                .select((
+                    passkeys::id,
+                    passkeys::userid,
+                    passkeys::algorithm,
+                    passkeys::public_key,
+                    passkeys::sign_count,
+                ))
+                .get_result(conn)
+        })
+    }
+
+    /// Gets the credential IDs of a user's passkeys.
+    pub fn get_passkey_credential_ids(
+        &self,
+        user: i32,
+    ) -> impl Future<Item = Vec<Vec<u8>>, Error = Error> {
+        self.async_query(move |conn| {
+            passkeys::table
+                .filter(passkeys::userid.eq(user))
+                .select(passkeys::credential_id)
+                .load(conn)
+        })
+    }
+
+    /// Gets a user's passkeys, oldest first.
+    pub fn get_passkeys(&self, user: i32) -> impl Future<Item = Vec<Passkey>, Error = Error> {
+        self.async_query(move |conn| {
+            passkeys::table
+                .filter(passkeys::userid.eq(user))
+                .order(passkeys::created)
+                .select((
+                    passkeys::id,
+                    passkeys::name,
+                    passkeys::created,
+                    passkeys::last_used,
+                ))
+                .load(conn)
+        })
+    }
+
+    /// Gets a problem players can see by name, as shown to the given team.
+    pub fn get_problem(
+        &self,
+        name: String,
+        team: Option<Uuid>,
+    ) -> impl Future<Item = ProblemListing, Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let problem = problems::table
+                .filter(problems::name.eq(&name))
+                .filter(problems::visible.eq(true))
+                .get_result::<Problem>(conn)
+                .optional()?;
+            let problem = match problem {
+                Some(problem) => problem,
+                None => user_bail!(NotFound, "That problem doesn't exist."),
+            };
+            let mut listings = problem_listings(conn, vec![problem], team)?;
+            Ok(listings.remove(0))
+        })
+    }
+
+    /// Gets the announcements about a problem, by the problem's name, newest first.
+    pub fn get_problem_announcements(
+        &self,
+        name: String,
+    ) -> impl Future<Item = Vec<Announcement>, Error = Error> {
+        self.async_query(move |conn| {
+            announcements::table
+                .inner_join(problems::table)
+                .filter(problems::name.eq(&name))
+                .order(announcements::created.desc())
+                .select((
+                    announcements::id,
+                    announcements::title,
+                    announcements::body,
+                    announcements::created,
+                    announcements::edited,
+                    problems::name.nullable(),
+                ))
+                .load(conn)
+        })
+    }
+
+    /// Gets the ID and flag of a problem players can see, by name.
+    pub fn get_problem_flag(
+        &self,
+        name: String,
+    ) -> impl Future<Item = (i32, String), Error = Error> {
+        self.async_query(move |conn| {
+            problems::table
+                .filter(problems::name.eq(&name))
+                .filter(problems::visible.eq(true))
+                .select((problems::id, problems::flag))
+                .get_result(conn)
+        })
+    }
+
+    /// Gets every problem players can see, as shown to the given team, ordered by category, then
+    /// points, then name.
+    pub fn get_problems(
+        &self,
+        team: Option<Uuid>,
+    ) -> impl Future<Item = Vec<ProblemListing>, Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let problems = problems::table
+                .filter(problems::visible.eq(true))
+                .order((problems::category, problems::points, problems::name))
+                .load::<Problem>(conn)?;
+            problem_listings(conn, problems, team)
+        })
+    }
+
+    /// Gets the scoreboard, either of every team or of the teams in the division with the given
+    /// name. Teams are ranked by points, then by who got their points first.
+    pub fn get_scoreboard(
+        &self,
+        division: Option<String>,
+    ) -> impl Future<Item = (Option<Division>, Vec<Standing>), Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let division = match division {
+                Some(name) => Some(
+                    divisions::table
+                        .filter(divisions::name.eq(&name))
+                        .get_result::<Division>(conn)?,
+                ),
+                None => None,
+            };
+            let teams = teams::table
+                .select((teams::id, teams::name, teams::divisionid))
+                .load::<(Uuid, String, Option<i32>)>(conn)?;
+            let solves = solves::table
+                .inner_join(problems::table)
+                .select((solves::teamid, problems::points, solves::solved))
+                .load::<(Uuid, i32, DateTime<Utc>)>(conn)?;
+
+            let mut standings = teams
+                .into_iter()
+                .filter(|(_, _, team_division)| match division {
+                    Some(ref division) => *team_division == Some(division.id),
+                    None => true,
+                })
+                .map(|(id, name, _)| {
+                    let solves = solves.iter().filter(|(team, _, _)| *team == id);
+                    Standing {
+                        place: 0,
+                        team: name,
+                        points: solves
+                            .clone()
+                            .map(|(_, points, _)| i64::from(*points))
+                            .sum(),
+                        last_solve: solves.map(|(_, _, solved)| *solved).max(),
+                    }
+                })
+                .collect::<Vec<_>>();
+            standings.sort_by(|a, b| {
+                b.points
+                    .cmp(&a.points)
+                    // Problems are worth some points, so teams with the same points either both
+                    // have solves or both don't.
+                    .then_with(|| a.last_solve.cmp(&b.last_solve))
+                    .then_with(|| a.team.cmp(&b.team))
+            });
+            for (i, standing) in standings.iter_mut().enumerate() {
+                standing.place = i + 1;
+            }
+            Ok((division, standings))
+        })
+    }
+
+    /// Gets when a second factor was last checked in a session, and how many wrong codes have been
+    /// tried in it.
+    pub fn get_session_second_factor(
+        &self,
+        auth: Uuid,
+    ) -> impl Future<Item = (Option<DateTime<Utc>>, i32), Error = Error> {
+        self.async_query(move |conn| {
+            auths::table
+                .filter(auths::expires.gt(now))
+                .find(auth)
+                .select((auths::stepped_up, auths::failed_codes))
+                .get_result(conn)
+        })
+    }
+
+    /// Gets a user's unexpired sessions, most recently used first.
+    pub fn get_sessions(&self, user: i32) -> impl Future<Item = Vec<Session>, Error = Error> {
+        self.async_query(move |conn| {
+            auths::table
+                .filter(auths::userid.eq(user))
+                .filter(auths::expires.gt(now))
+                .order(auths::last_used.desc())
+                .select((
+                    auths::id,
+                    auths::seq,
+                    auths::created,
+                    auths::last_used,
+                    auths::expires,
+                    auths::ip,
+                    auths::user_agent,
+                ))
+                .load(conn)
+        })
+    }
+
+    /// Gets a team by ID.
+    pub fn get_team(&self, team: Uuid) -> impl Future<Item = Team, Error = Error> {
+        self.async_query(move |conn| teams::table.find(team).get_result(conn))
+    }
+
+    /// Gets a team by name.
+    pub fn get_team_by_name(&self, name: String) -> impl Future<Item = Team, Error = Error> {
+        self.async_query(move |conn| teams::table.filter(teams::name.eq(&name)).get_result(conn))
+    }
+
+    /// Gets the email addresses of a team's members.
+    pub fn get_team_emails(&self, team: Uuid) -> impl Future<Item = Vec<String>, Error = Error> {
+        self.async_query(move |conn| {
+            users::table
+                .filter(users::teamid.eq(team))
+                .select(users::email)
+                .load(conn)
+        })
+    }
+
+    /// Gets a team's outstanding invites.
+    pub fn get_team_invites(&self, team: Uuid) -> impl Future<Item = Vec<Invite>, Error = Error> {
+        self.async_query(move |conn| {
+            invites::table
+                .filter(invites::teamid.eq(team))
+                .filter(invites::expires.gt(now))
+                .filter(invites::used.eq(false))
+                .order(invites::expires)
+                .select((invites::id, invites::email, invites::expires))
+                .load(conn)
+        })
+    }
+
+    /// Gets a team's members.
+    pub fn get_team_members(
+        &self,
+        team: Uuid,
+    ) -> impl Future<Item = Vec<TeamMember>, Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let captain = teams::table
+                .find(team)
+                .select(teams::captain)
+                .get_result::<Option<i32>>(conn)?;
+            let members = users::table
+                .filter(users::teamid.eq(team))
+                .order(users::name)
+                .select((users::id, users::name))
+                .load::<(i32, String)>(conn)?;
+            Ok(members
+                .into_iter()
+                .map(|(id, name)| TeamMember {
+                    name,
+                    captain: captain == Some(id),
+                })
+                .collect())
+        })
+    }
+
+    /// Gets the problems a team has solved, in the order it solved them.
+    pub fn get_team_solves(&self, team: Uuid) -> impl Future<Item = Vec<Solve>, Error = Error> {
+        self.async_query(move |conn| {
+            solves::table
+                .inner_join(problems::table)
+                .left_join(users::table)
+                .filter(solves::teamid.eq(team))
+                .order(solves::solved)
+                .select((
+                    problems::name,
+                    problems::category,
+                    problems::points,
+                    users::name.nullable(),
+                    solves::solved,
+                ))
+                .load(conn)
+        })
+    }
+
+    /// Gets a team's tickets, newest first.
+    pub fn get_team_tickets(&self, team: Uuid) -> impl Future<Item = Vec<Ticket>, Error = Error> {
+        self.async_query(move |conn| {
+            tickets::table
+                .left_join(problems::table)
+                .filter(tickets::teamid.eq(team))
+                .order(tickets::created.desc())
+                .select((
+                    tickets::id,
+                    tickets::teamid,
+                    tickets::subject,
+                    tickets::created,
+                    tickets::closed,
+                    problems::name.nullable(),
+                ))
+                .load(conn)
+        })
+    }
+
+    /// Gets every team along with its members' names, ordered by name.
+    pub fn get_teams(&self) -> impl Future<Item = Vec<(Team, Vec<String>)>, Error = Error> {
+        self.async_query(|conn| -> Result<_, Error> {
+            let teams = teams::table.order(teams::name).load::<Team>(conn)?;
+            let members = users::table
+                .filter(users::teamid.is_not_null())
+                .order(users::name)
+                .select((users::teamid, users::name))
+                .load::<(Option<Uuid>, String)>(conn)?;
+            Ok(teams
+                .into_iter()
+                .map(|team| {
+                    let names = members
+                        .iter()
+                        .filter(|(id, _)| *id == Some(team.id))
+                        .map(|(_, name)| name.clone())
+                        .collect();
+                    (team, names)
+                })
+                .collect())
+        })
+    }
+
+    /// Gets a ticket, the name of the team that opened it, and its messages, oldest first.
+    pub fn get_ticket(
+        &self,
+        ticket: i32,
+    ) -> impl Future<Item = (Ticket, Option<String>, Vec<TicketMessage>), Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let ticket = match load_ticket(conn, ticket).optional()? {
+                Some(ticket) => ticket,
+                None => user_bail!(NotFound, "That ticket doesn't exist."),
+            };
+            let team_name = match ticket.team {
+                Some(team) => Some(
+                    teams::table
+                        .find(team)
+                        .select(teams::name)
+                        .get_result::<String>(conn)?,
+                ),
+                None => None,
+            };
+            let messages = ticket_messages::table
+                .left_join(users::table)
+                .filter(ticket_messages::ticketid.eq(ticket.id))
+                .order(ticket_messages::id)
+                .select((
+                    ticket_messages::id,
+                    users::name.nullable(),
+                    ticket_messages::from_mod,
+                    ticket_messages::body,
+                    ticket_messages::sent,
+                    ticket_messages::announcementid,
+                ))
+                .load(conn)?;
+            Ok((ticket, team_name, messages))
+        })
+    }
+
+    /// Gets every ticket and the name of the team that opened it, open tickets first, then
+    /// newest first.
+    pub fn get_tickets(&self) -> impl Future<Item = Vec<(Ticket, Option<String>)>, Error = Error> {
+        self.async_query(|conn| {
+            tickets::table
+                .left_join(teams::table)
+                .left_join(problems::table)
+                .order((tickets::closed.is_not_null(), tickets::created.desc()))
+                .select((
+                    (
+                        tickets::id,
+                        tickets::teamid,
+                        tickets::subject,
+                        tickets::created,
+                        tickets::closed,
+                        problems::name.nullable(),
+                    ),
+                    teams::name.nullable(),
+                ))
+                .load(conn)
+        })
+    }
+
+    /// Gets a user's TOTP secret and whether its setup was finished, if they have one.
+    pub fn get_totp_secret(
+        &self,
+        user: i32,
+    ) -> impl Future<Item = Option<(Vec<u8>, bool)>, Error = Error> {
+        self.async_query(move |conn| {
+            totp_secrets::table
+                .find(user)
+                .select((totp_secrets::secret, totp_secrets::enabled))
+                .get_result(conn)
+                .optional()
+        })
+    }
+
+    /// Gets the mail that hasn't been delivered yet, including mail that's been given up on,
+    /// newest first.
+    pub fn get_undelivered_mail(&self) -> impl Future<Item = Vec<QueuedMail>, Error = Error> {
+        self.async_query(|conn| {
+            mail_outbox::table
+                .filter(mail_outbox::sent.is_null())
+                .order(mail_outbox::created.desc())
+                .load(conn)
+        })
+    }
+
+    /// Gets a user by ID.
+    pub fn get_user(&self, user: i32) -> impl Future<Item = User, Error = Error> {
+        self.async_query(move |conn| users::table.find(user).get_result(conn))
+    }
+
+    /// Gets a user by username or email address, ignoring case.
+    pub fn get_user_by_login(
+        &self,
+        login: String,
+    ) -> impl Future<Item = Option<User>, Error = Error> {
+        self.async_query(move |conn| {
+            users::table
+                .filter(
+                    lower(users::name)
+                        .eq(lower(&login))
+                        .or(lower(users::email).eq(lower(&login))),
+                )
+                .get_result(conn)
+                .optional()
+        })
+    }
+
+    /// Gets the ID of a user's team, if they have one.
+    pub fn get_user_team(&self, user: i32) -> impl Future<Item = Option<Uuid>, Error = Error> {
+        self.async_query(move |conn| {
+            users::table
+                .find(user)
+                .select(users::teamid)
+                .get_result(conn)
+        })
+    }
+
+    /// Adds a user to the team with the given join code, as long as that leaves the team with at
+    /// most `max_team_size` members.
+    pub fn join_team(
+        &self,
+        user: i32,
+        join_code: String,
+        max_team_size: i64,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let team = teams::table
+                    .filter(teams::join_code.eq(&join_code))
+                    .filter(
+                        teams::join_code_expires
+                            .is_null()
+                            .or(teams::join_code_expires.gt(now)),
+                    )
+                    .filter(
+                        teams::join_code_uses
+                            .is_null()
+                            .or(teams::join_code_uses.gt(0)),
+                    )
+                    .select(teams::id)
+                    .for_update()
+                    .get_result::<Uuid>(conn)
+                    .optional()?;
+                let team = match team {
+                    Some(team) => team,
+                    None => user_bail!(NotFound, "Your join code was invalid."),
+                };
+
+                add_to_team(conn, user, team, max_team_size)?;
+                let _ = update(teams::table.find(team))
+                    .set(teams::join_code_uses.eq(teams::join_code_uses - 1))
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Removes a member from the team captained by the given user.
+    pub fn kick_team_member(
+        &self,
+        captain: i32,
+        member: String,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let team = captained_team(conn, captain)?;
+                let member = team_member_by_name(conn, team, &member)?;
+                if member == captain {
+                    user_bail!(
+                        BadRequest,
+                        "You can't kick yourself; leave the team instead."
+                    );
+                }
+                let _ = remove_from_team(conn, member)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Removes a user from their team. The captain may only leave once they're the last member;
+    /// the team is deleted when that happens. Solves belong to the team, so they stay with it when
+    /// a member leaves.
+    ///
+    /// In solo mode, a personal team can't be left, and a user leaving any other team is given a
+    /// new personal team.
+    pub fn leave_team(&self, user: i32, solo: bool) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let team = users::table
+                    .find(user)
+                    .select(users::teamid)
+                    .get_result::<Option<Uuid>>(conn)?;
+                let team = match team {
+                    Some(team) => team,
+                    None => user_bail!(NotFound, "You don't have a team!"),
+                };
+                let (captain, personal) = teams::table
+                    .find(team)
+                    .select((teams::captain, teams::personal))
+                    .get_result::<(Option<i32>, bool)>(conn)?;
+                if solo && personal {
+                    user_bail!(
+                        BadRequest,
+                        "You can't leave your personal team. Join or create a team instead."
+                    );
+                }
+                let members = users::table
+                    .filter(users::teamid.eq(team))
+                    .count()
+                    .get_result::<i64>(conn)?;
+                if captain == Some(user) && members > 1 {
+                    user_bail!(
+                        BadRequest,
+                        "You're the captain; make someone else captain before leaving."
+                    );
+                }
+
+                let (team, empty) = remove_from_team(conn, user)?;
+                if empty {
+                    let _ = delete(teams::table.find(team)).execute(conn)?;
+                }
+                if solo {
+                    give_personal_team(conn, user)?;
+                }
+                Ok(())
+            })
+        })
+    }
+
+    /// Gets the user an identity at an OpenID Connect provider belongs to. An identity seen for the
+    /// first time is linked to the user with its email address, who is registered if they don't
+    /// exist yet; this only happens if the provider has verified the email address.
+    pub fn link_identity(
+        &self,
+        provider: String,
+        identity: OidcIdentity,
+    ) -> impl Future<Item = i32, Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let linked = identities::table
+                    .filter(identities::provider.eq(&provider))
+                    .filter(identities::subject.eq(&identity.subject))
+                    .select(identities::userid)
+                    .get_result::<i32>(conn)
+                    .optional()?;
+                if let Some(user) = linked {
+                    return Ok(user);
+                }
+
+                let email = match identity.email {
+                    Some(ref email) if identity.email_verified => email,
+                    _ => user_bail!(
+                        Forbidden,
+                        "Your identity provider hasn't verified your email address."
+                    ),
+                };
+                let existing = users::table
+                    .filter(lower(users::email).eq(lower(email)))
+                    .select(users::id)
+                    .get_result::<i32>(conn)
+                    .optional()?;
+                let user = match existing {
+                    Some(user) => user,
+                    None => {
+                        let hint = match identity.preferred_username {
+                            Some(ref name) => name,
+                            None => email.split('@').next().unwrap_or(""),
+                        };
+                        let name = unused_username(conn, hint)?;
+                        insert_into(users::table)
+                            .values((users::name.eq(&name), users::email.eq(email)))
+                            .returning(users::id)
+                            .get_result(conn)?
+                    }
+                };
+
+                let _ = insert_into(identities::table)
+                    .values((
+                        identities::provider.eq(&provider),
+                        identities::subject.eq(&identity.subject),
+                        identities::userid.eq(user),
+                    ))
+                    .execute(conn)?;
+                Ok(user)
+            })
+        })
+    }
+
+    /// Moves every member of one team into another, deleting the now-empty team. Used by admins.
+    ///
+    /// If the merged team would have more than `max_team_size` members, an override note must be
+    /// given, which is recorded in the audit log.
+    pub fn merge_teams(
+        &self,
+        admin: i32,
+        from: Uuid,
+        into: Uuid,
+        max_team_size: i64,
+        override_note: Option<String>,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                if from == into {
+                    user_bail!(BadRequest, "A team can't be merged into itself.");
+                }
+
+                // Lock both teams, in a consistent order so concurrent merges can't deadlock, so
+                // nobody can join either of them while the members are being counted.
+                let _ = teams::table
+                    .filter(teams::id.eq_any(vec![from, into]))
+                    .order(teams::id)
+                    .select(teams::id)
+                    .for_update()
+                    .load::<Uuid>(conn)?;
+                let from_name = teams::table
+                    .find(from)
+                    .select(teams::name)
+                    .get_result::<String>(conn)?;
+                let into_name = teams::table
+                    .find(into)
+                    .select(teams::name)
+                    .get_result::<String>(conn)?;
+                let members = users::table
+                    .filter(users::teamid.eq(from).or(users::teamid.eq(into)))
+                    .count()
+                    .get_result::<i64>(conn)?;
+
+                let mut note = format!("Merged team {} into team {}", from_name, into_name);
+                if members > max_team_size {
+                    match override_note {
+                        Some(ref reason) => {
+                            note += &format!(
+                                ", overriding the team size limit with {} members: {}",
+                                members, reason
+                            );
+                        }
+                        None => user_bail!(
+                            BadRequest,
+                            "The merged team would be too big; give a reason to override that."
+                        ),
+                    }
+                }
+
+                let from_captain = teams::table
+                    .find(from)
+                    .select(teams::captain)
+                    .get_result::<Option<i32>>(conn)?;
+                let _ = update(teams::table.find(into).filter(teams::captain.is_null()))
+                    .set(teams::captain.eq(from_captain))
+                    .execute(conn)?;
+                let _ = update(teams::table.find(into))
+                    .set(teams::personal.eq(false))
+                    .execute(conn)?;
+
+                move_solves(conn, from, into)?;
+                let _ = update(users::table.filter(users::teamid.eq(from)))
+                    .set(users::teamid.eq(into))
+                    .execute(conn)?;
+                let _ = update(tickets::table.filter(tickets::teamid.eq(from)))
+                    .set(tickets::teamid.eq(into))
+                    .execute(conn)?;
+                let _ = delete(teams::table.find(from)).execute(conn)?;
+                if !division_eligible(conn, into)? {
+                    let _ = update(teams::table.find(into))
+                        .set(teams::divisionid.eq(None::<i32>))
+                        .execute(conn)?;
+                    note += ", removing it from its division";
+                }
+                audit(conn, admin, note)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Adds a mail to the outbox, to be delivered by the mail worker.
+    pub fn queue_mail(
+        &self,
+        recipient: String,
+        subject: String,
+        body: String,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            insert_into(mail_outbox::table)
+                .values((
+                    mail_outbox::recipient.eq(&recipient),
+                    mail_outbox::subject.eq(&subject),
+                    mail_outbox::body.eq(&body),
+                ))
+                .execute(conn)
+                .map(|_| ())
+        })
+    }
+
+    /// Records that a wrong second factor code was tried in a session.
+    pub fn record_failed_code(&self, auth: Uuid) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            update(auths::table.find(auth))
+                .set(auths::failed_codes.eq(auths::failed_codes + 1))
+                .execute(conn)
+                .map(|_| ())
+        })
+    }
+
+    /// Records that a login mail was requested, and whether it was sent.
+    pub fn record_login_request(
+        &self,
+        ip: Option<String>,
+        user: Option<i32>,
+        mailed: bool,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            insert_into(login_requests::table)
+                .values((
+                    login_requests::ip.eq(&ip),
+                    login_requests::userid.eq(user),
+                    login_requests::mailed.eq(mailed),
+                ))
+                .execute(conn)
+                .map(|_| ())
+        })
+    }
+
+    /// Records a failed attempt to deliver a mail. It's tried again at `retry_at`, or given up on
+    /// if that's `None`.
+    pub fn record_mail_failure(
+        &self,
+        mail: i32,
+        error: String,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            update(mail_outbox::table.find(mail))
+                .set((
+                    mail_outbox::attempts.eq(mail_outbox::attempts + 1),
+                    mail_outbox::failed.eq(retry_at.is_none()),
+                    mail_outbox::last_error.eq(&error),
+                    mail_outbox::next_attempt.eq(retry_at.unwrap_or_else(Utc::now)),
+                ))
+                .execute(conn)
+                .map(|_| ())
+        })
+    }
+
+    /// Records that a mail was delivered.
+    pub fn record_mail_sent(&self, mail: i32) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            update(mail_outbox::table.find(mail))
+                .set((
+                    mail_outbox::attempts.eq(mail_outbox::attempts + 1),
+                    mail_outbox::sent.eq(Utc::now()),
+                ))
+                .execute(conn)
+                .map(|_| ())
+        })
+    }
+
+    /// Records that a passkey was used to log in, using up the login challenge it signed and
+    /// updating its signature counter. Returns the ID of the user the passkey belongs to. Fails if
+    /// the challenge has expired, or if the passkey was used again since its counter was read.
+    pub fn record_passkey_use(
+        &self,
+        challenge: Uuid,
+        passkey: i32,
+        old_sign_count: i64,
+        new_sign_count: i64,
+    ) -> impl Future<Item = i32, Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let used = delete(
+                    webauthn_challenges::table
+                        .filter(webauthn_challenges::userid.is_null())
+                        .filter(webauthn_challenges::expires.gt(now))
+                        .find(challenge),
+                )
+                .execute(conn)?;
+                if used == 0 {
+                    user_bail!(
+                        BadRequest,
+                        "Logging in with your passkey took too long. Try again?"
+                    );
+                }
+
+                let user = update(
+                    passkeys::table
+                        .filter(passkeys::sign_count.eq(old_sign_count))
+                        .find(passkey),
+                )
+                .set((
+                    passkeys::sign_count.eq(new_sign_count),
+                    passkeys::last_used.eq(Utc::now()),
+                ))
+                .returning(passkeys::userid)
+                .get_result::<i32>(conn)
+                .optional()?;
+                match user {
+                    Some(user) => Ok(user),
+                    None => user_bail!(
+                        BadRequest,
+                        "Logging in with your passkey didn't work. Try again?"
+                    ),
+                }
+            })
+        })
+    }
+
+    /// Records that the given user's team solved a problem. If the team hadn't already solved it,
+    /// returns the team's name and whether it's the first team to.
+    pub fn record_solve(
+        &self,
+        user: i32,
+        problem: i32,
+    ) -> impl Future<Item = Option<(String, bool)>, Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let team = user_team(conn, user)?;
+                // Locking the problem makes its solves happen one at a time, so only one of them
+                // can be the first.
+                let _ = problems::table
+                    .find(problem)
+                    .select(problems::id)
+                    .for_update()
+                    .get_result::<i32>(conn)?;
+                let solved = insert_into(solves::table)
+                    .values((
+                        solves::teamid.eq(team),
+                        solves::problemid.eq(problem),
+                        solves::userid.eq(user),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+                if solved == 0 {
+                    return Ok(None);
+                }
+
+                let solves = solves::table
+                    .filter(solves::problemid.eq(problem))
+                    .count()
+                    .get_result::<i64>(conn)?;
+                let name = teams::table
+                    .find(team)
+                    .select(teams::name)
+                    .get_result::<String>(conn)?;
+                Ok(Some((name, solves == 1)))
+            })
+        })
+    }
+
+    /// Records that a user's TOTP code for the given time step was used, so it can't be used
+    /// again. Returns false if that step or a later one was already used.
+    pub fn record_totp_step(
+        &self,
+        user: i32,
+        step: i64,
+    ) -> impl Future<Item = bool, Error = Error> {
+        self.async_query(move |conn| {
+            update(
+                totp_secrets::table
+                    .filter(totp_secrets::enabled.eq(true))
+                    .filter(totp_secrets::last_step.lt(step))
+                    .find(user),
+            )
+            .set(totp_secrets::last_step.eq(step))
+            .execute(conn)
+            .map(|n| n == 1)
+        })
+    }
+
+    /// Records that the given user's team submitted a wrong flag.
+    pub fn record_wrong_flag(&self, user: i32) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| -> Result<_, Error> {
+            let team = user_team(conn, user)?;
+            let _ = insert_into(wrong_flags::table)
+                .values(wrong_flags::teamid.eq(team))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Replaces the join code of the team captained by the given user, returning the new code.
+    pub fn regenerate_join_code(
+        &self,
+        captain: i32,
+        expires: Option<DateTime<Utc>>,
+        uses: Option<i32>,
+    ) -> impl Future<Item = String, Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let team = captained_team(conn, captain)?;
+                let join_code = random_code(JOIN_CODE_LEN);
+                let _ = update(teams::table.find(team))
+                    .set((
+                        teams::join_code.eq(&join_code),
+                        teams::join_code_expires.eq(expires),
+                        teams::join_code_uses.eq(uses),
+                    ))
+                    .execute(conn)?;
+                Ok(join_code)
+            })
+        })
+    }
+
+    /// Removes a user from their team, deleting it if it was their personal team. Used by admins.
+    pub fn remove_team_member(
+        &self,
+        admin: i32,
+        member: String,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let (id, member) = users::table
+                    .filter(lower(users::name).eq(lower(&member)))
+                    .select((users::id, users::name))
+                    .get_result::<(i32, String)>(conn)?;
+                let (team, empty) = remove_from_team(conn, id)?;
+                let (team_name, personal) = teams::table
+                    .find(team)
+                    .select((teams::name, teams::personal))
+                    .get_result::<(String, bool)>(conn)?;
+                if empty && personal {
+                    let _ = delete(teams::table.find(team)).execute(conn)?;
+                }
+                audit(
+                    conn,
+                    admin,
+                    format!("Removed {} from team {}", member, team_name),
+                )?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Renames a team. Used by admins.
+    pub fn rename_team(
+        &self,
+        admin: i32,
+        team: Uuid,
+        name: String,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let old_name = teams::table
+                    .find(team)
+                    .select(teams::name)
+                    .get_result::<String>(conn)?;
+                let _ = update(teams::table.find(team))
+                    .set(teams::name.eq(&name))
+                    .execute(conn)?;
+                audit(
+                    conn,
+                    admin,
+                    format!("Renamed team {} to {}", old_name, name),
+                )?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Replies to one of the user's team's tickets, reopening it if it was closed. Returns the
+    /// ticket and the team's name.
+    pub fn reply_to_ticket(
+        &self,
+        user: i32,
+        ticket: i32,
+        body: String,
+    ) -> impl Future<Item = (Ticket, String), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let ticket = tickets::table
+                    .inner_join(teams::table.inner_join(users::table))
+                    .filter(tickets::id.eq(ticket))
+                    .filter(users::id.eq(user))
+                    .select((tickets::id, teams::name))
+                    .get_result::<(i32, String)>(conn)
+                    .optional()?;
+                let (ticket, team_name) = match ticket {
+                    Some(ticket) => ticket,
+                    None => user_bail!(NotFound, "That ticket doesn't exist."),
+                };
+
+                let _ = insert_into(ticket_messages::table)
+                    .values((
+                        ticket_messages::ticketid.eq(ticket),
+                        ticket_messages::userid.eq(user),
+                        ticket_messages::from_mod.eq(false),
+                        ticket_messages::body.eq(&body),
+                    ))
+                    .execute(conn)?;
+                let _ = update(tickets::table.find(ticket))
+                    .set(tickets::closed.eq(None::<DateTime<Utc>>))
+                    .execute(conn)?;
+                Ok((load_ticket(conn, ticket)?, team_name))
+            })
+        })
+    }
+
+    /// Tries delivering an undelivered mail again right away, even if it's been given up on. Used
+    /// by admins.
+    pub fn retry_mail(&self, admin: i32, mail: i32) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let recipient = update(
+                    mail_outbox::table
+                        .find(mail)
+                        .filter(mail_outbox::sent.is_null()),
+                )
+                .set((
+                    mail_outbox::failed.eq(false),
+                    mail_outbox::next_attempt.eq(Utc::now()),
+                ))
+                .returning(mail_outbox::recipient)
+                .get_result::<String>(conn)
+                .optional()?;
+                match recipient {
+                    Some(recipient) => {
+                        audit(
+                            conn,
+                            admin,
+                            format!("Retried mail {} to {}", mail, recipient),
+                        )?;
+                        Ok(())
+                    }
+                    None => user_bail!(BadRequest, "That mail was already delivered."),
+                }
+            })
+        })
+    }
+
+    /// Revokes an invite to the team captained by the given user. The invite is expired rather
+    /// than deleted, so it still counts towards the team's invite limit.
+    pub fn revoke_invite(
+        &self,
+        captain: i32,
+        invite: Uuid,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let team = captained_team(conn, captain)?;
+                let _ = update(
+                    invites::table
+                        .filter(invites::teamid.eq(team))
+                        .filter(invites::id.eq(invite)),
+                )
+                .set(invites::expires.eq(now))
+                .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Deletes one of a user's sessions, logging it out.
+    pub fn revoke_session(&self, user: i32, seq: i32) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            delete(
+                auths::table
+                    .filter(auths::userid.eq(user))
+                    .filter(auths::seq.eq(seq)),
+            )
+            .execute(conn)
+            .map(|_| ())
+        })
+    }
+
+    /// Deletes all of a user's sessions, logging them out everywhere.
+    pub fn revoke_sessions(&self, user: i32) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            delete(auths::table.filter(auths::userid.eq(user)))
+                .execute(conn)
+                .map(|_| ())
+        })
+    }
+
+    /// Puts the team captained by the given user in a division, or takes it out of its division.
+    /// Every member of the team must be eligible for the division.
+    pub fn set_team_division(
+        &self,
+        captain: i32,
+        division: Option<i32>,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| {
+                let team = captained_team(conn, captain)?;
+                let _ = update(teams::table.find(team))
+                    .set(teams::divisionid.eq(division))
+                    .execute(conn)?;
+                if !division_eligible(conn, team)? {
+                    user_bail!(
+                        BadRequest,
+                        "Not every member of your team is eligible for that division."
+                    );
+                }
+                Ok(())
+            })
+        })
+    }
+
+    /// Records that a second factor was just checked in a session.
+    pub fn step_up_session(&self, auth: Uuid) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            update(auths::table.find(auth))
+                .set(auths::stepped_up.eq(Utc::now()))
+                .execute(conn)
+                .map(|_| ())
+        })
+    }
+
+    /// Makes another member of the team captained by the given user the captain.
+    pub fn transfer_captaincy(
+        &self,
+        captain: i32,
+        member: String,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let team = captained_team(conn, captain)?;
+                let member = team_member_by_name(conn, team, &member)?;
+                let _ = update(teams::table.find(team))
+                    .set(teams::captain.eq(member))
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Updates the profile of the team captained by the given user.
+    pub fn update_team_profile(
+        &self,
+        captain: i32,
+        profile: TeamProfile,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.async_query(move |conn| {
+            conn.transaction(|| -> Result<_, Error> {
+                let team = captained_team(conn, captain)?;
+                let _ = update(teams::table.find(team))
+                    .set((
+                        teams::affiliation.eq(&profile.affiliation),
+                        teams::country.eq(&profile.country),
+                        teams::website.eq(&profile.website),
+                        teams::bio.eq(&profile.bio),
+                    ))
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Uses up one of a user's recovery codes, given its hash. Returns false if it isn't one of
+    /// their unused recovery codes.
+    pub fn use_recovery_code(
+        &self,
+        user: i32,
+        hash: Vec<u8>,
+    ) -> impl Future<Item = bool, Error = Error> {
+        self.async_query(move |conn| {
+            update(
+                recovery_codes::table
+                    .filter(recovery_codes::userid.eq(user))
+                    .filter(recovery_codes::hash.eq(&hash))
+                    .filter(recovery_codes::used.eq(false)),
+            )
+            .set(recovery_codes::used.eq(true))
+            .execute(conn)
+            .map(|n| n == 1)
+        })
+    }
+
     /// Performs a query "asynchronously" (but not really). Diesel currently does not support
     /// async/futures, so we use `crate::util::blocking` so the database operations don't block
     /// the thread. This does, however, require the future to be run inside a threadpool.  
@@ -217,3 +2554,392 @@ impl DB {
         }
     }
 }
+
+/// Records an admin action in the audit log.
+fn audit(conn: &PgConnection, admin: i32, note: String) -> QueryResult<()> {
+    insert_into(audit_log::table)
+        .values((audit_log::adminid.eq(admin), audit_log::note.eq(note)))
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Adds a user to a team, as long as that leaves the team with at most `max_team_size` members.
+fn add_to_team(
+    conn: &PgConnection,
+    user: i32,
+    team: Uuid,
+    max_team_size: i64,
+) -> Result<(), Error> {
+    // Locking the team's row serializes concurrent joins, so they can't push the team over the
+    // size limit together.
+    let personal = teams::table
+        .find(team)
+        .select(teams::personal)
+        .for_update()
+        .get_result::<bool>(conn)?;
+    if personal {
+        user_bail!(BadRequest, "Personal teams can't be joined.");
+    }
+
+    let personal = leave_personal_team(conn, user)?;
+
+    let members = users::table
+        .filter(users::teamid.eq(team))
+        .count()
+        .get_result::<i64>(conn)?;
+    if members >= max_team_size {
+        user_bail!(BadRequest, "The team is full.");
+    }
+
+    let _ = update(users::table.find(user))
+        .set(users::teamid.eq(team))
+        .execute(conn)?;
+    if !division_eligible(conn, team)? {
+        user_bail!(
+            BadRequest,
+            "Your email address isn't eligible for this team's division."
+        );
+    }
+    dissolve_personal_team(conn, personal, team).map_err(Error::from)
+}
+
+/// Returns the team the given user is the captain of.
+fn captained_team(conn: &PgConnection, user: i32) -> Result<Uuid, Error> {
+    let team = users::table
+        .inner_join(teams::table)
+        .filter(users::id.eq(user))
+        .filter(teams::captain.eq(user))
+        .select(teams::id)
+        .get_result::<Uuid>(conn)
+        .optional()?;
+    match team {
+        Some(team) => Ok(team),
+        None => user_bail!(Forbidden, "Only the team captain can do that."),
+    }
+}
+
+/// Creates a session for a user, returning its authentication token.
+fn create_session(
+    conn: &PgConnection,
+    user: i32,
+    expires: DateTime<Utc>,
+    client: &ClientInfo,
+) -> QueryResult<Uuid> {
+    let token = Uuid::new_v4();
+    insert_into(auths::table)
+        .values((
+            auths::id.eq(token),
+            auths::userid.eq(user),
+            auths::expires.eq(expires),
+            auths::ip.eq(&client.ip),
+            auths::user_agent.eq(&client.user_agent),
+            auths::csrf.eq(Uuid::new_v4()),
+        ))
+        .execute(conn)
+        .map(|_| token)
+}
+
+/// Moves the solves of a personal team the user has left to their new team, then deletes it.
+fn dissolve_personal_team(
+    conn: &PgConnection,
+    personal: Option<Uuid>,
+    into: Uuid,
+) -> QueryResult<()> {
+    if let Some(personal) = personal {
+        move_solves(conn, personal, into)?;
+        let _ = delete(teams::table.find(personal)).execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Returns whether every member of a team is eligible for the team's division.
+fn division_eligible(conn: &PgConnection, team: Uuid) -> Result<bool, Error> {
+    let division = teams::table
+        .inner_join(divisions::table)
+        .filter(teams::id.eq(team))
+        .select(divisions::all_columns)
+        .get_result::<Division>(conn)
+        .optional()?;
+    let division = match division {
+        Some(division) => division,
+        None => return Ok(true),
+    };
+
+    let emails = users::table
+        .filter(users::teamid.eq(team))
+        .select(users::email)
+        .load::<String>(conn)?;
+    Ok(emails.iter().all(|email| division.admits(email)))
+}
+
+/// Gives the user a personal team, unless they already have a team.
+fn give_personal_team(conn: &PgConnection, user: i32) -> Result<(), Error> {
+    let (name, team) = users::table
+        .find(user)
+        .select((users::name, users::teamid))
+        .get_result::<(String, Option<Uuid>)>(conn)?;
+    if team.is_some() {
+        return Ok(());
+    }
+
+    // Personal teams are named after their user, unless someone already took that name, in any
+    // case, for a team of their own; then a number is added, cutting the name short if needed.
+    let mut team_name = name.clone();
+    let mut n = 1;
+    loop {
+        let taken = teams::table
+            .filter(lower(teams::name).eq(lower(&team_name)))
+            .count()
+            .get_result::<i64>(conn)?;
+        if taken == 0 {
+            break;
+        }
+        n += 1;
+        let suffix = n.to_string();
+        team_name = name
+            .chars()
+            .take(MAX_TEAM_NAME_LEN - suffix.len())
+            .chain(suffix.chars())
+            .collect();
+    }
+
+    let id = Uuid::new_v4();
+    let _ = insert_into(teams::table)
+        .values((
+            teams::id.eq(id),
+            teams::name.eq(&team_name),
+            teams::captain.eq(user),
+            teams::personal.eq(true),
+            teams::join_code.eq(random_code(JOIN_CODE_LEN)),
+        ))
+        .execute(conn)?;
+    let _ = update(users::table.find(user))
+        .set(users::teamid.eq(id))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Adds attachments to a problem.
+fn insert_attachments(conn: &PgConnection, problem: i32, files: &[Attachment]) -> QueryResult<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    let rows = files
+        .iter()
+        .map(|file| {
+            (
+                attachments::problemid.eq(problem),
+                attachments::name.eq(&file.name),
+                attachments::url.eq(&file.url),
+            )
+        })
+        .collect::<Vec<_>>();
+    insert_into(attachments::table)
+        .values(&rows)
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Checks that a user doesn't have a team, taking them off their personal team if they're on one.
+/// Returns the personal team, which should be passed to `dissolve_personal_team` once the user is
+/// on their new team.
+fn leave_personal_team(conn: &PgConnection, user: i32) -> Result<Option<Uuid>, Error> {
+    let team = users::table
+        .find(user)
+        .select(users::teamid)
+        .get_result::<Option<Uuid>>(conn)?;
+    if let Some(team) = team {
+        let personal = teams::table
+            .find(team)
+            .select(teams::personal)
+            .get_result::<bool>(conn)?;
+        if !personal {
+            user_bail!(BadRequest, "You already have a team!");
+        }
+
+        let (team, _) = remove_from_team(conn, user)?;
+        return Ok(Some(team));
+    }
+    Ok(None)
+}
+
+/// Loads the attachments of each of the given problems, ordered by name.
+fn load_attachments(
+    conn: &PgConnection,
+    problems: &[Problem],
+) -> QueryResult<Vec<Vec<Attachment>>> {
+    let ids = problems
+        .iter()
+        .map(|problem| problem.id)
+        .collect::<Vec<_>>();
+    let files = attachments::table
+        .filter(attachments::problemid.eq_any(ids))
+        .order(attachments::name)
+        .select((attachments::problemid, attachments::name, attachments::url))
+        .load::<(i32, String, String)>(conn)?;
+    Ok(problems
+        .iter()
+        .map(|problem| {
+            files
+                .iter()
+                .filter(|(id, _, _)| *id == problem.id)
+                .map(|(_, name, url)| Attachment {
+                    name: name.clone(),
+                    url: url.clone(),
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// Loads a ticket, along with the name of the problem it's about.
+fn load_ticket(conn: &PgConnection, ticket: i32) -> QueryResult<Ticket> {
+    tickets::table
+        .left_join(problems::table)
+        .filter(tickets::id.eq(ticket))
+        .select((
+            tickets::id,
+            tickets::teamid,
+            tickets::subject,
+            tickets::created,
+            tickets::closed,
+            problems::name.nullable(),
+        ))
+        .get_result(conn)
+}
+
+/// Moves one team's solves to another, keeping the earlier of any two solves of the same problem.
+fn move_solves(conn: &PgConnection, from: Uuid, into: Uuid) -> QueryResult<()> {
+    let into_solves = solves::table
+        .filter(solves::teamid.eq(into))
+        .select((solves::problemid, solves::solved))
+        .load::<(i32, DateTime<Utc>)>(conn)?;
+    let from_solves = solves::table
+        .filter(solves::teamid.eq(from))
+        .select((solves::problemid, solves::solved))
+        .load::<(i32, DateTime<Utc>)>(conn)?;
+    for (problem, solved) in from_solves {
+        let later = match into_solves.iter().find(|(id, _)| *id == problem) {
+            Some((_, kept)) if *kept <= solved => from,
+            Some(_) => into,
+            None => continue,
+        };
+        let _ = delete(solves::table.find((later, problem))).execute(conn)?;
+    }
+    update(solves::table.filter(solves::teamid.eq(from)))
+        .set(solves::teamid.eq(into))
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Adds the attachments of each of the given problems, and how they've been solved, as shown to
+/// the given team.
+fn problem_listings(
+    conn: &PgConnection,
+    problems: Vec<Problem>,
+    team: Option<Uuid>,
+) -> Result<Vec<ProblemListing>, Error> {
+    let attachments = load_attachments(conn, &problems)?;
+    let ids = problems
+        .iter()
+        .map(|problem| problem.id)
+        .collect::<Vec<_>>();
+    let solvers = solves::table
+        .filter(solves::problemid.eq_any(ids))
+        .select((solves::problemid, solves::teamid))
+        .load::<(i32, Uuid)>(conn)?;
+    Ok(problems
+        .into_iter()
+        .zip(attachments)
+        .map(|(problem, attachments)| {
+            let solvers = solvers.iter().filter(|(id, _)| *id == problem.id);
+            ProblemListing {
+                solves: solvers.clone().count() as i64,
+                solved: solvers.clone().any(|(_, solver)| Some(*solver) == team),
+                problem,
+                attachments,
+            }
+        })
+        .collect())
+}
+
+/// Removes a user from their team, passing the captaincy on to another member if needed. Returns
+/// the team the user was on, and whether it's now empty.
+fn remove_from_team(conn: &PgConnection, user: i32) -> Result<(Uuid, bool), Error> {
+    let team = users::table
+        .find(user)
+        .select(users::teamid)
+        .get_result::<Option<Uuid>>(conn)?;
+    let team = match team {
+        Some(team) => team,
+        None => user_bail!(BadRequest, "That user isn't on a team."),
+    };
+
+    let _ = update(users::table.find(user))
+        .set(users::teamid.eq(None::<Uuid>))
+        .execute(conn)?;
+    let next = users::table
+        .filter(users::teamid.eq(team))
+        .order(users::id)
+        .select(users::id)
+        .first::<i32>(conn)
+        .optional()?;
+    let _ = update(teams::table.find(team).filter(teams::captain.eq(user)))
+        .set(teams::captain.eq(next))
+        .execute(conn)?;
+    Ok((team, next.is_none()))
+}
+
+/// Looks up a member of the given team by name, returning their ID.
+fn team_member_by_name(conn: &PgConnection, team: Uuid, name: &str) -> Result<i32, Error> {
+    let member = users::table
+        .filter(lower(users::name).eq(lower(name)))
+        .filter(users::teamid.eq(team))
+        .select(users::id)
+        .get_result::<i32>(conn)
+        .optional()?;
+    match member {
+        Some(member) => Ok(member),
+        None => user_bail!(BadRequest, "That user isn't on your team."),
+    }
+}
+
+/// Picks a username for a new user that isn't taken and satisfies the constraints on usernames,
+/// based on the given one.
+fn unused_username(conn: &PgConnection, hint: &str) -> Result<String, Error> {
+    let mut base = hint
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(32)
+        .collect::<String>();
+    if base.is_empty() {
+        base.push_str("player");
+    }
+
+    let mut name = base.clone();
+    loop {
+        // Usernames must be longer than 3 characters.
+        if name.len() > 3 {
+            let taken = users::table
+                .filter(lower(users::name).eq(lower(&name)))
+                .count()
+                .get_result::<i64>(conn)?;
+            if taken == 0 {
+                return Ok(name);
+            }
+        }
+        name = format!("{}{}", base, random_digits(4));
+    }
+}
+
+/// Returns the team the given user is on.
+fn user_team(conn: &PgConnection, user: i32) -> Result<Uuid, Error> {
+    let team = users::table
+        .find(user)
+        .select(users::teamid)
+        .get_result::<Option<Uuid>>(conn)?;
+    match team {
+        Some(team) => Ok(team),
+        None => user_bail!(NotFound, "You don't have a team!"),
+    }
+}
@@ -0,0 +1,128 @@
+use crate::schema::{Jwk, OidcConfig};
+use failure::{bail, Error};
+use futures::Future;
+use reqwest::{r#async::Client, Url};
+use serde_derive::Deserialize;
+use std::sync::Arc;
+
+/// A client for an OpenID Connect provider.
+#[allow(missing_debug_implementations)]
+#[derive(Clone)]
+pub struct Oidc {
+    inner: Arc<OidcInner>,
+}
+
+impl Oidc {
+    /// Looks up a provider's endpoints from its discovery document.
+    pub fn discover(config: OidcConfig) -> impl Future<Item = Oidc, Error = Error> {
+        #[derive(Deserialize)]
+        struct Discovery {
+            issuer: String,
+            authorization_endpoint: String,
+            token_endpoint: String,
+            jwks_uri: String,
+        }
+
+        let client = Client::new();
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer.trim_end_matches('/')
+        );
+        client
+            .get(&url)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|mut res| res.json::<Discovery>())
+            .map_err(Error::from)
+            .and_then(move |discovery| {
+                if discovery.issuer != config.issuer {
+                    bail!(
+                        "The OpenID Connect provider at {} claims to be {}",
+                        config.issuer,
+                        discovery.issuer
+                    );
+                }
+
+                let authorization_endpoint = Url::parse(&discovery.authorization_endpoint)?;
+                Ok(Oidc {
+                    inner: Arc::new(OidcInner {
+                        authorization_endpoint,
+                        client,
+                        config,
+                        jwks_uri: discovery.jwks_uri,
+                        token_endpoint: discovery.token_endpoint,
+                    }),
+                })
+            })
+    }
+
+    /// Returns the URL to send the user to so they can log in at the provider.
+    pub fn authorization_url(&self, state: &str, nonce: &str) -> String {
+        let mut url = self.inner.authorization_endpoint.clone();
+        let _ = url
+            .query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.inner.config.client_id)
+            .append_pair("redirect_uri", &self.inner.config.redirect_url)
+            .append_pair("scope", "openid email profile")
+            .append_pair("state", state)
+            .append_pair("nonce", nonce);
+        url.into_string()
+    }
+
+    /// Returns the settings the provider was discovered with.
+    pub fn config(&self) -> &OidcConfig {
+        &self.inner.config
+    }
+
+    /// Exchanges an authorization code, which the provider sends the user back with, for an ID
+    /// token.
+    pub fn exchange_code(&self, code: &str) -> impl Future<Item = String, Error = Error> {
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            id_token: String,
+        }
+
+        let config = &self.inner.config;
+        self.inner
+            .client
+            .post(&self.inner.token_endpoint)
+            .basic_auth(&config.client_id, Some(&config.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", config.redirect_url.as_str()),
+            ])
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|mut res| res.json::<TokenResponse>())
+            .map(|res| res.id_token)
+            .map_err(Error::from)
+    }
+
+    /// Gets the keys the provider currently signs ID tokens with.
+    pub fn get_keys(&self) -> impl Future<Item = Vec<Jwk>, Error = Error> {
+        #[derive(Deserialize)]
+        struct Jwks {
+            keys: Vec<Jwk>,
+        }
+
+        self.inner
+            .client
+            .get(&self.inner.jwks_uri)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|mut res| res.json::<Jwks>())
+            .map(|jwks| jwks.keys)
+            .map_err(Error::from)
+    }
+}
+
+#[allow(missing_debug_implementations)]
+struct OidcInner {
+    authorization_endpoint: Url,
+    client: Client,
+    config: OidcConfig,
+    jwks_uri: String,
+    token_endpoint: String,
+}
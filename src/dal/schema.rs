@@ -1,7 +1,104 @@
+table! {
+    announcements (id) {
+        id -> Int4,
+        title -> Varchar,
+        body -> Varchar,
+        created -> Timestamptz,
+        edited -> Nullable<Timestamptz>,
+        problemid -> Nullable<Int4>,
+    }
+}
+
+table! {
+    api_tokens (id) {
+        id -> Int4,
+        userid -> Int4,
+        hash -> Bytea,
+        name -> Varchar,
+        created -> Timestamptz,
+        last_used -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    attachments (id) {
+        id -> Int4,
+        problemid -> Int4,
+        name -> Varchar,
+        url -> Varchar,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Int4,
+        adminid -> Nullable<Int4>,
+        at -> Timestamptz,
+        note -> Text,
+    }
+}
+
 table! {
     auths (id) {
         id -> Uuid,
         userid -> Int4,
+        seq -> Int4,
+        created -> Timestamptz,
+        last_used -> Timestamptz,
+        expires -> Timestamptz,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        csrf -> Uuid,
+        stepped_up -> Nullable<Timestamptz>,
+        failed_codes -> Int4,
+    }
+}
+
+table! {
+    divisions (id) {
+        id -> Int4,
+        name -> Varchar,
+        email_domains -> Array<Text>,
+    }
+}
+
+table! {
+    email_changes (id) {
+        id -> Uuid,
+        userid -> Int4,
+        email -> Varchar,
+        expires -> Timestamptz,
+        used -> Bool,
+    }
+}
+
+table! {
+    identities (provider, subject) {
+        provider -> Varchar,
+        subject -> Varchar,
+        userid -> Int4,
+    }
+}
+
+table! {
+    invites (id) {
+        id -> Uuid,
+        teamid -> Uuid,
+        email -> Varchar,
+        token -> Uuid,
+        created -> Timestamptz,
+        expires -> Timestamptz,
+        used -> Bool,
+    }
+}
+
+table! {
+    login_requests (id) {
+        id -> Int4,
+        ip -> Nullable<Varchar>,
+        userid -> Nullable<Int4>,
+        mailed -> Bool,
+        at -> Timestamptz,
     }
 }
 
@@ -11,6 +108,67 @@ table! {
         userid -> Int4,
         expires -> Timestamptz,
         used -> Bool,
+        code -> Varchar,
+        attempts -> Int4,
+    }
+}
+
+table! {
+    mail_outbox (id) {
+        id -> Int4,
+        recipient -> Varchar,
+        subject -> Varchar,
+        body -> Text,
+        created -> Timestamptz,
+        attempts -> Int4,
+        next_attempt -> Timestamptz,
+        sent -> Nullable<Timestamptz>,
+        failed -> Bool,
+        last_error -> Nullable<Text>,
+    }
+}
+
+table! {
+    passkeys (id) {
+        id -> Int4,
+        userid -> Int4,
+        credential_id -> Bytea,
+        algorithm -> Int4,
+        public_key -> Bytea,
+        sign_count -> Int8,
+        name -> Varchar,
+        created -> Timestamptz,
+        last_used -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    problems (id) {
+        id -> Int4,
+        name -> Varchar,
+        category -> Varchar,
+        description -> Varchar,
+        points -> Int4,
+        flag -> Varchar,
+        visible -> Bool,
+        created -> Timestamptz,
+    }
+}
+
+table! {
+    recovery_codes (userid, hash) {
+        userid -> Int4,
+        hash -> Bytea,
+        used -> Bool,
+    }
+}
+
+table! {
+    solves (teamid, problemid) {
+        teamid -> Uuid,
+        problemid -> Int4,
+        userid -> Nullable<Int4>,
+        solved -> Timestamptz,
     }
 }
 
@@ -18,6 +176,49 @@ table! {
     teams (id) {
         id -> Uuid,
         name -> Varchar,
+        captain -> Nullable<Int4>,
+        personal -> Bool,
+        join_code -> Varchar,
+        join_code_expires -> Nullable<Timestamptz>,
+        join_code_uses -> Nullable<Int4>,
+        affiliation -> Varchar,
+        country -> Varchar,
+        website -> Varchar,
+        bio -> Varchar,
+        divisionid -> Nullable<Int4>,
+    }
+}
+
+table! {
+    ticket_messages (id) {
+        id -> Int4,
+        ticketid -> Int4,
+        userid -> Nullable<Int4>,
+        from_mod -> Bool,
+        body -> Varchar,
+        sent -> Timestamptz,
+        announcementid -> Nullable<Int4>,
+    }
+}
+
+table! {
+    tickets (id) {
+        id -> Int4,
+        teamid -> Nullable<Uuid>,
+        subject -> Varchar,
+        created -> Timestamptz,
+        closed -> Nullable<Timestamptz>,
+        problemid -> Nullable<Int4>,
+    }
+}
+
+table! {
+    totp_secrets (userid) {
+        userid -> Int4,
+        secret -> Bytea,
+        enabled -> Bool,
+        last_step -> Int8,
+        created -> Timestamptz,
     }
 }
 
@@ -27,16 +228,75 @@ table! {
         name -> Varchar,
         email -> Varchar,
         teamid -> Nullable<Uuid>,
+        admin -> Bool,
+        banned -> Bool,
+    }
+}
+
+table! {
+    webauthn_challenges (id) {
+        id -> Uuid,
+        userid -> Nullable<Int4>,
+        expires -> Timestamptz,
+    }
+}
+
+table! {
+    wrong_flags (id) {
+        id -> Int4,
+        teamid -> Uuid,
+        at -> Timestamptz,
     }
 }
 
+joinable!(announcements -> problems (problemid));
+joinable!(api_tokens -> users (userid));
+joinable!(attachments -> problems (problemid));
+joinable!(audit_log -> users (adminid));
 joinable!(auths -> users (userid));
+joinable!(email_changes -> users (userid));
+joinable!(identities -> users (userid));
+joinable!(invites -> teams (teamid));
+joinable!(login_requests -> users (userid));
 joinable!(logins -> users (userid));
+joinable!(passkeys -> users (userid));
+joinable!(recovery_codes -> users (userid));
+joinable!(solves -> problems (problemid));
+joinable!(solves -> teams (teamid));
+joinable!(solves -> users (userid));
+joinable!(teams -> divisions (divisionid));
+joinable!(ticket_messages -> announcements (announcementid));
+joinable!(ticket_messages -> tickets (ticketid));
+joinable!(ticket_messages -> users (userid));
+joinable!(tickets -> problems (problemid));
+joinable!(tickets -> teams (teamid));
+joinable!(totp_secrets -> users (userid));
 joinable!(users -> teams (teamid));
+joinable!(webauthn_challenges -> users (userid));
+joinable!(wrong_flags -> teams (teamid));
 
 allow_tables_to_appear_in_same_query!(
+    announcements,
+    api_tokens,
+    attachments,
+    audit_log,
     auths,
+    divisions,
+    email_changes,
+    identities,
+    invites,
+    login_requests,
     logins,
+    mail_outbox,
+    passkeys,
+    problems,
+    recovery_codes,
+    solves,
     teams,
+    ticket_messages,
+    tickets,
+    totp_secrets,
     users,
+    webauthn_challenges,
+    wrong_flags,
 );
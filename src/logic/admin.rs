@@ -0,0 +1,305 @@
+//! Administrative actions. Callers are responsible for checking that the acting user is an admin.
+
+use crate::{
+    dal::{Events, Mailer, DB},
+    logic::notify,
+    schema::{
+        Attachment, Config, Division, Event, Problem, ProblemDraft, QueuedMail, Team, Ticket,
+        TicketMessage,
+    },
+    view::render,
+};
+use failure::Error;
+use futures::{
+    future::{err, ok, Either},
+    Future,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+/// Replies to a ticket, mailing the team the reply. If `public` is set, the reply is also posted
+/// as an announcement.
+pub fn answer_ticket(
+    db: DB,
+    events: Events,
+    mailer: Mailer,
+    admin: i32,
+    ticket: i32,
+    body: String,
+    public: bool,
+) -> impl Future<Item = (), Error = Error> {
+    let body = body.trim().to_string();
+    if body.is_empty() {
+        return Either::B(err(user_err!(BadRequest, "Your message is empty.")));
+    }
+
+    Either::A(
+        db.answer_ticket(admin, ticket, body.clone(), public)
+            .and_then(move |(ticket, announcement)| {
+                events.publish(Event::TicketsChanged);
+                if let Some(id) = announcement {
+                    events.publish(Event::AnnouncementPosted {
+                        id,
+                        title: ticket.subject.clone(),
+                        problem: ticket.problem.clone(),
+                    });
+                }
+                notify_team(db, mailer, ticket, Some(body))
+            }),
+    )
+}
+
+/// Closes a ticket, mailing the team that it was closed.
+pub fn close_ticket(
+    db: DB,
+    events: Events,
+    mailer: Mailer,
+    admin: i32,
+    ticket: i32,
+) -> impl Future<Item = (), Error = Error> {
+    db.close_ticket(admin, ticket).and_then(move |ticket| {
+        events.publish(Event::TicketsChanged);
+        notify_team(db, mailer, ticket, None)
+    })
+}
+
+/// Creates a division. `email_domains` is a comma- or space-separated list of the email domains
+/// members of teams in the division must have addresses at; if it's empty, anyone is eligible.
+pub fn create_division(
+    db: DB,
+    admin: i32,
+    name: String,
+    email_domains: &str,
+) -> impl Future<Item = (), Error = Error> {
+    let email_domains = email_domains
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(|domain| domain.trim_start_matches('@').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect();
+    db.create_division(admin, name.trim().to_string(), email_domains)
+}
+
+/// Creates a problem.
+pub fn create_problem(
+    db: DB,
+    events: Events,
+    admin: i32,
+    draft: ProblemDraft,
+) -> impl Future<Item = (), Error = Error> {
+    db.create_problem(admin, clean_draft(draft))
+        .map(move |()| events.publish(Event::ProblemsChanged))
+}
+
+/// Deletes a division.
+pub fn delete_division(
+    db: DB,
+    events: Events,
+    admin: i32,
+    division: i32,
+) -> impl Future<Item = (), Error = Error> {
+    db.delete_division(admin, division)
+        .map(move |()| events.publish(Event::TeamsChanged))
+}
+
+/// Deletes an empty team.
+pub fn disband_team(
+    db: DB,
+    events: Events,
+    admin: i32,
+    team: Uuid,
+) -> impl Future<Item = (), Error = Error> {
+    db.disband_team(admin, team)
+        .map(move |()| events.publish(Event::TeamsChanged))
+}
+
+/// Edits an announcement.
+pub fn edit_announcement(
+    db: DB,
+    events: Events,
+    admin: i32,
+    announcement: i32,
+    title: String,
+    body: String,
+    problem: Option<i32>,
+) -> impl Future<Item = (), Error = Error> {
+    let title = title.trim().to_string();
+    if title.is_empty() {
+        return Either::B(err(user_err!(BadRequest, "Announcements need a title.")));
+    }
+    Either::A(
+        db.edit_announcement(admin, announcement, title, body.trim().to_string(), problem)
+            .map(move |()| events.publish(Event::AnnouncementEdited { id: announcement })),
+    )
+}
+
+/// Edits a problem.
+pub fn edit_problem(
+    db: DB,
+    events: Events,
+    admin: i32,
+    problem: i32,
+    draft: ProblemDraft,
+) -> impl Future<Item = (), Error = Error> {
+    db.edit_problem(admin, problem, clean_draft(draft))
+        .map(move |()| {
+            events.publish(Event::ProblemsChanged);
+            events.publish(Event::ScoreboardChanged);
+        })
+}
+
+/// Gets a ticket, the name of the team that opened it, and its messages.
+pub fn get_ticket(
+    db: DB,
+    ticket: i32,
+) -> impl Future<Item = (Ticket, Option<String>, Vec<TicketMessage>), Error = Error> {
+    db.get_ticket(ticket)
+}
+
+/// Merges one team into another. If the merged team would be too large, a note explaining why
+/// the limit is being overridden must be given.
+pub fn merge_teams(
+    db: DB,
+    events: Events,
+    config: &Config,
+    admin: i32,
+    from: Uuid,
+    into: Uuid,
+    override_note: String,
+) -> impl Future<Item = (), Error = Error> {
+    let override_note = Some(override_note.trim().to_string()).filter(|s| !s.is_empty());
+    db.merge_teams(admin, from, into, config.max_team_size, override_note)
+        .map(move |()| events.publish(Event::TeamsChanged))
+}
+
+/// Parses a problem's attachments, as admins write them: a line for each, with the file name and
+/// then the link to it, separated by whitespace. Blank lines are skipped.
+pub fn parse_attachments(text: &str) -> Result<Vec<Attachment>, Error> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some(url), None) => Ok(Attachment {
+                    name: name.to_string(),
+                    url: url.to_string(),
+                }),
+                _ => user_bail!(
+                    BadRequest,
+                    "Each attachment needs a file name and a link, on a line of its own."
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Posts an announcement, optionally about a problem, which is pushed to everyone with the site
+/// open.
+pub fn post_announcement(
+    db: DB,
+    events: Events,
+    admin: i32,
+    title: String,
+    body: String,
+    problem: Option<i32>,
+) -> impl Future<Item = (), Error = Error> {
+    let title = title.trim().to_string();
+    if title.is_empty() {
+        return Either::B(err(user_err!(BadRequest, "Announcements need a title.")));
+    }
+    Either::A(
+        db.create_announcement(admin, title.clone(), body.trim().to_string(), problem)
+            .map(move |(id, problem)| {
+                events.publish(Event::AnnouncementPosted { id, title, problem })
+            }),
+    )
+}
+
+/// Lists every problem, including hidden ones, along with its attachments.
+pub fn problems(db: DB) -> impl Future<Item = Vec<(Problem, Vec<Attachment>)>, Error = Error> {
+    db.get_all_problems()
+}
+
+/// Removes a user from their team.
+pub fn remove_team_member(
+    db: DB,
+    events: Events,
+    admin: i32,
+    member: String,
+) -> impl Future<Item = (), Error = Error> {
+    db.remove_team_member(admin, member)
+        .map(move |()| events.publish(Event::TeamsChanged))
+}
+
+/// Renames a team.
+pub fn rename_team(
+    db: DB,
+    events: Events,
+    admin: i32,
+    team: Uuid,
+    name: String,
+) -> impl Future<Item = (), Error = Error> {
+    db.rename_team(admin, team, name)
+        .map(move |()| events.publish(Event::TeamsChanged))
+}
+
+/// Tries delivering an undelivered mail again right away, even if it's been given up on.
+pub fn retry_mail(db: DB, admin: i32, mail: i32) -> impl Future<Item = (), Error = Error> {
+    db.retry_mail(admin, mail)
+}
+
+/// Lists every team, along with its members' names, and every division.
+pub fn teams(
+    db: DB,
+) -> impl Future<Item = (Vec<(Team, Vec<String>)>, Vec<Division>), Error = Error> {
+    db.get_teams().join(db.get_divisions())
+}
+
+/// Lists every ticket, along with the name of the team that opened it, open tickets first.
+pub fn tickets(db: DB) -> impl Future<Item = Vec<(Ticket, Option<String>)>, Error = Error> {
+    db.get_tickets()
+}
+
+/// Lists the mail that hasn't been delivered yet, newest first.
+pub fn undelivered_mail(db: DB) -> impl Future<Item = Vec<QueuedMail>, Error = Error> {
+    db.get_undelivered_mail()
+}
+
+/// Trims the text fields of a problem and lowercases its category, so categories group
+/// consistently. The flag is trimmed too, since flags submitted by players are.
+fn clean_draft(draft: ProblemDraft) -> ProblemDraft {
+    ProblemDraft {
+        name: draft.name.trim().to_string(),
+        category: draft.category.trim().to_lowercase(),
+        description: draft.description.trim().to_string(),
+        flag: draft.flag.trim().to_string(),
+        ..draft
+    }
+}
+
+/// Mails a ticket's team a mod's reply to it, or that it was closed if there's no reply.
+fn notify_team(
+    db: DB,
+    mailer: Mailer,
+    ticket: Ticket,
+    reply: Option<String>,
+) -> impl Future<Item = (), Error = Error> {
+    let team = match ticket.team {
+        Some(team) => team,
+        None => return Either::B(ok(())),
+    };
+
+    let subject = format!("[Ticket #{}] {}", ticket.id, ticket.subject);
+    Either::A(
+        db.get_team_emails(team)
+            .and_then(move |to| {
+                let vars = json!({
+                    "reply": reply,
+                    "subject": ticket.subject,
+                    "ticket": ticket.id
+                });
+                render("ticket-team-mail.txt", vars).map(|text| (to, text))
+            })
+            .and_then(move |(to, text)| notify(mailer, to, subject, text)),
+    )
+}
@@ -0,0 +1,490 @@
+//! Authentication and authorization-related logic.
+//!
+//! This whole thing is fairly inefficient, wrt refetching things from the DB we "already know."
+//! Stuff here only runs on login/register though, so it shouldn't be a hot path.
+
+pub mod oidc;
+pub mod passkey;
+pub mod totp;
+
+use crate::{
+    dal::{Mailer, DB},
+    schema::{ApiToken, ClientInfo, Config, Session, User},
+    util::random_code,
+    view::render,
+};
+use chrono::{DateTime, Duration, Utc};
+use chrono_humanize::{Accuracy, HumanTime, Tense};
+use failure::{format_err, Error};
+use futures::{
+    future::{err, ok, Either},
+    Future,
+};
+use jsonwebtoken::{decode, decode_header, encode, Header, Validation};
+use log::info;
+use ring::digest;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+/// The length of an API token, not counting its prefix.
+const API_TOKEN_LEN: usize = 32;
+
+/// The prefix of API tokens, which makes them easy to search for if they're leaked.
+const API_TOKEN_PREFIX: &str = "ctf_";
+
+/// The error for an IP address that has requested too many login mails.
+const TOO_MANY_LOGIN_MAILS: &str =
+    "Too many login emails have been requested from your network. Try again in a little while.";
+
+/// The claims carried by an access token.
+#[derive(Debug, Deserialize, Serialize)]
+struct AccessClaims {
+    sub: i32,
+    name: String,
+    email: String,
+    admin: bool,
+    csrf: Uuid,
+    exp: i64,
+}
+
+/// A way for users to prove who they are when logging in. How logging in starts differs between
+/// methods; for example, `login_1` mails a login link, `oidc::start` sends the user to their
+/// identity provider, and `passkey::start_login` gives the browser a challenge to sign.
+pub trait LoginMethod {
+    /// What the user presents to finish logging in.
+    type Proof;
+
+    /// Checks a proof, returning the database ID of the user it identifies.
+    fn verify(
+        &self,
+        db: DB,
+        proof: Self::Proof,
+    ) -> Box<dyn Future<Item = i32, Error = Error> + Send>;
+}
+
+/// Logging in with the code from a login mail. The proof is the username or email address the
+/// mail was requested for, and the code.
+#[derive(Clone, Copy, Debug)]
+pub struct LoginCode;
+
+impl LoginMethod for LoginCode {
+    type Proof = (String, String);
+
+    fn verify(
+        &self,
+        db: DB,
+        (login, code): (String, String),
+    ) -> Box<dyn Future<Item = i32, Error = Error> + Send> {
+        let code = code.trim().to_string();
+        if code.is_empty() || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return Box::new(err(user_err!(
+                BadRequest,
+                "That code is wrong or has expired."
+            )));
+        }
+        Box::new(db.consume_login_code(login.trim().to_string(), code))
+    }
+}
+
+/// Logging in with the link from a login mail. The proof is the UUID in the link.
+#[derive(Clone, Copy, Debug)]
+pub struct LoginLink;
+
+impl LoginMethod for LoginLink {
+    type Proof = Uuid;
+
+    fn verify(&self, db: DB, login: Uuid) -> Box<dyn Future<Item = i32, Error = Error> + Send> {
+        Box::new(db.consume_login_link(login))
+    }
+}
+
+/// Returns the user an API token belongs to. Banned users' tokens don't work.
+pub fn authed_api_user(db: DB, token: &str) -> impl Future<Item = User, Error = Error> {
+    db.get_api_token_user(hash_api_token(token))
+        .and_then(move |user| match user {
+            Some(user) => Either::A(db.get_user(user)),
+            None => Either::B(err(user_err!(Unauthorized, "That API token is invalid."))),
+        })
+        .and_then(|user| {
+            if user.banned {
+                user_bail!(Forbidden, "This account has been banned.");
+            }
+            Ok(user)
+        })
+}
+
+/// Returns the user authenticated by the given token, if any, along with the CSRF token of their
+/// session. Banned users aren't authenticated.
+pub fn authed_user(db: DB, token: &str) -> impl Future<Item = (User, Uuid), Error = Error> {
+    match token.parse() {
+        Ok(token) => Either::A(db.get_auth_user(token).and_then(move |(id, csrf)| {
+            db.get_user(id).and_then(move |user| {
+                if user.banned {
+                    user_bail!(Forbidden, "This account has been banned.");
+                }
+                Ok((user, csrf))
+            })
+        })),
+        Err(e) => Either::B(err(e.into())),
+    }
+}
+
+/// Cancels a user's pending email change, if they have one.
+pub fn cancel_email_change(db: DB, user: i32) -> impl Future<Item = (), Error = Error> {
+    db.cancel_email_change(user)
+}
+
+/// Deletes expired and used login links and email changes, expired sessions and passkey
+/// challenges, the sessions of banned users, delivered mail, and login requests and expired
+/// invites too old to count towards rate limits.
+pub fn clean_up(db: DB) -> impl Future<Item = (), Error = Error> {
+    db.clean_up(Utc::now() - Duration::hours(1))
+        .map(|(logins, auths)| {
+            info!("Cleaned up {} login links and {} sessions", logins, auths);
+        })
+}
+
+/// Creates an API token for a user, returning it. Only its hash is kept, so it can't be shown
+/// again.
+pub fn create_api_token(
+    db: DB,
+    user: i32,
+    name: String,
+) -> impl Future<Item = String, Error = Error> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Either::B(err(user_err!(BadRequest, "Your API token needs a name.")));
+    }
+    let token = format!("{}{}", API_TOKEN_PREFIX, random_code(API_TOKEN_LEN));
+    Either::A(
+        db.create_api_token(user, name, hash_api_token(&token))
+            .map(move |()| token),
+    )
+}
+
+/// Changes a user's email address to the one an email change was requested for.
+pub fn confirm_email_change(db: DB, change: Uuid) -> impl Future<Item = (), Error = Error> {
+    db.confirm_email_change(change)
+}
+
+/// Gets a user's API tokens.
+pub fn get_api_tokens(db: DB, user: i32) -> impl Future<Item = Vec<ApiToken>, Error = Error> {
+    db.get_api_tokens(user)
+}
+
+/// Gets the new email address of an email change that can still be confirmed.
+pub fn get_email_change(db: DB, change: Uuid) -> impl Future<Item = String, Error = Error> {
+    db.get_email_change(change)
+}
+
+/// Gets the new email address and expiry time of a user's pending email change, if any.
+pub fn get_pending_email_change(
+    db: DB,
+    user: i32,
+) -> impl Future<Item = Option<(String, DateTime<Utc>)>, Error = Error> {
+    db.get_pending_email_change(user)
+}
+
+/// Gets a user's sessions.
+pub fn get_sessions(db: DB, user: i32) -> impl Future<Item = Vec<Session>, Error = Error> {
+    db.get_sessions(user)
+}
+
+/// Signs an access token carrying the given user and their session's CSRF token, using the first
+/// configured key. Returns `None` if access tokens are turned off.
+pub fn issue_access_token(
+    config: &Config,
+    user: &User,
+    csrf: Uuid,
+) -> Result<Option<String>, Error> {
+    let key = match config.jwt_keys.first() {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    let claims = AccessClaims {
+        sub: user.id,
+        name: user.name.clone(),
+        email: user.email.clone(),
+        admin: user.admin,
+        csrf,
+        exp: (Utc::now() + config.access_token_lifetime).timestamp(),
+    };
+    let mut header = Header::default();
+    header.kid = Some(key.id.clone());
+    encode(&header, &claims, &key.secret)
+        .map(Some)
+        .map_err(|err| format_err!("Couldn't sign an access token: {}", err))
+}
+
+/// Logs a user in with the given method, returning the authentication token of their new session.
+/// In solo mode, this also gives the user a personal team if they don't have a team yet.
+pub fn log_in<M: LoginMethod>(
+    db: DB,
+    config: &Config,
+    method: &M,
+    proof: M::Proof,
+    client: ClientInfo,
+) -> impl Future<Item = Uuid, Error = Error> {
+    let solo = config.solo;
+    let expires = Utc::now() + config.session_lifetime;
+    method
+        .verify(db.clone(), proof)
+        .and_then({
+            let db = db.clone();
+            move |user| db.get_user(user)
+        })
+        .and_then(|user| {
+            if user.banned {
+                user_bail!(Forbidden, "This account has been banned.");
+            }
+            Ok(user.id)
+        })
+        .and_then({
+            let db = db.clone();
+            move |user| {
+                db.create_session(user, expires, client)
+                    .map(move |token| (token, user))
+            }
+        })
+        .and_then(move |(token, user)| {
+            if solo {
+                Either::A(db.create_personal_team(user).map(move |()| token))
+            } else {
+                Either::B(ok(token))
+            }
+        })
+}
+
+/// Creates a new login token and mails it to the user with the given username or email address.
+/// To avoid revealing who has an account, this succeeds even if no such user exists.
+pub fn login_1(
+    db: DB,
+    mailer: Mailer,
+    config: &Config,
+    login: String,
+    client: ClientInfo,
+) -> impl Future<Item = (), Error = Error> {
+    let config = config.clone();
+    let ip = client.ip;
+    check_login_request_ip(db.clone(), &config, ip.clone())
+        .and_then({
+            let db = db.clone();
+            move |()| db.get_user_by_login(login.trim().to_string())
+        })
+        .and_then(move |user| match user {
+            Some(ref user) if !user.banned => {
+                Either::A(send_login_mail(db, mailer, &config, false, user.id, ip))
+            }
+            _ => Either::B(db.record_login_request(ip, None, false)),
+        })
+}
+
+/// Logs out the session with the given token, if it's valid.
+pub fn logout(db: DB, token: &str) -> impl Future<Item = (), Error = Error> {
+    match token.parse() {
+        Ok(token) => Either::A(db.delete_session(token)),
+        Err(_) => Either::B(ok(())),
+    }
+}
+
+/// Creates a new user and mails them a login link. If the email address is already registered,
+/// its user is mailed a login link instead, so registering doesn't reveal who has an account.
+pub fn register(
+    db: DB,
+    mailer: Mailer,
+    config: &Config,
+    username: String,
+    email: String,
+    client: ClientInfo,
+) -> impl Future<Item = (), Error = Error> {
+    let config = config.clone();
+    let ip = client.ip;
+    check_login_request_ip(db.clone(), &config, ip.clone())
+        .and_then({
+            let db = db.clone();
+            move |()| db.create_user(username, email.trim().to_string())
+        })
+        .and_then(move |(id, created)| send_login_mail(db, mailer, &config, created, id, ip))
+}
+
+/// Starts changing a user's email address, mailing a confirmation link to the new address and a
+/// notice to the old one. The address only changes once the link is followed.
+pub fn request_email_change(
+    db: DB,
+    mailer: Mailer,
+    user: i32,
+    email: String,
+) -> impl Future<Item = (), Error = Error> {
+    let expire_duration = Duration::days(1);
+
+    let email = email.trim().to_string();
+    let expires = Utc::now() + expire_duration;
+    db.create_email_change(user, email.clone(), expires)
+        .and_then({
+            let email = email.clone();
+            move |(change, old_email)| {
+                let vars = json!({
+                    "change": change,
+                    "duration": HumanTime::from(expire_duration).to_text_en(Accuracy::Rough, Tense::Future),
+                    "email": email,
+                    "expires": expires.to_rfc2822(),
+                });
+                let confirm = render("email-change-mail.txt", vars)?;
+                // The notice mustn't contain the link, since the old address might not be the
+                // user's anymore.
+                let notice = render("email-change-notice.txt", json!({ "email": email }))?;
+                Ok((old_email, confirm, notice))
+            }
+        })
+        .and_then(move |(old_email, confirm, notice)| {
+            mailer
+                .send(&email, "Confirm your new email address for ACM CTF 2", &confirm)
+                .and_then(move |()| {
+                    mailer.send(&old_email, "Your ACM CTF 2 email address is changing", &notice)
+                })
+        })
+}
+
+/// Deletes one of a user's API tokens, so it stops working.
+pub fn revoke_api_token(db: DB, user: i32, token: i32) -> impl Future<Item = (), Error = Error> {
+    db.delete_api_token(user, token)
+}
+
+/// Logs out one of a user's sessions.
+pub fn revoke_session(db: DB, user: i32, seq: i32) -> impl Future<Item = (), Error = Error> {
+    db.revoke_session(user, seq)
+}
+
+/// Logs out all of a user's sessions.
+pub fn revoke_sessions(db: DB, user: i32) -> impl Future<Item = (), Error = Error> {
+    db.revoke_sessions(user)
+}
+
+/// Checks an access token's signature and expiry, returning the user and CSRF token it carries, or
+/// `None` if the token isn't valid. Any configured key is accepted, so keys can be rotated without
+/// logging everyone out.
+///
+/// The user's team isn't carried by the token, since captains and admins can change it; it's
+/// looked up from the database instead. Access tokens aren't issued to banned users, but a user
+/// banned since theirs was issued still shows up as unbanned here; only trust it where that's
+/// harmless, i.e. for viewing pages.
+pub fn verify_access_token(
+    db: DB,
+    config: &Config,
+    token: &str,
+) -> impl Future<Item = Option<(User, Uuid)>, Error = Error> {
+    let claims = match decode_access_token(config, token) {
+        Ok(claims) => claims,
+        Err(_) => return Either::A(ok(None)),
+    };
+    Either::B(db.get_user_team(claims.sub).map(move |team| {
+        let user = User {
+            id: claims.sub,
+            name: claims.name,
+            email: claims.email,
+            team,
+            admin: claims.admin,
+            banned: false,
+        };
+        Some((user, claims.csrf))
+    }))
+}
+
+/// Hashes an API token for storage or lookup.
+fn hash_api_token(token: &str) -> Vec<u8> {
+    digest::digest(&digest::SHA256, token.trim().as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+/// Fails if too many login mails have been requested from the given IP address recently.
+fn check_login_request_ip(
+    db: DB,
+    config: &Config,
+    ip: Option<String>,
+) -> impl Future<Item = (), Error = Error> {
+    let limit = config.login_mails_per_ip;
+    match ip {
+        Some(ip) => Either::A(
+            db.count_login_requests(ip, Utc::now() - Duration::hours(1))
+                .and_then(move |count| {
+                    if count >= limit {
+                        Err(user_err!(TooManyRequests, "{}", TOO_MANY_LOGIN_MAILS))
+                    } else {
+                        Ok(())
+                    }
+                }),
+        ),
+        None => Either::B(ok(())),
+    }
+}
+
+/// Checks an access token's signature and expiry, returning its claims.
+fn decode_access_token(config: &Config, token: &str) -> Result<AccessClaims, Error> {
+    let header =
+        decode_header(token).map_err(|err| format_err!("Invalid access token: {}", err))?;
+    let key = config
+        .jwt_keys
+        .iter()
+        .find(|key| header.kid.as_ref() == Some(&key.id))
+        .ok_or_else(|| format_err!("Access token signed with an unknown key"))?;
+    decode::<AccessClaims>(token, &key.secret, &Validation::default())
+        .map(|token| token.claims)
+        .map_err(|err| format_err!("Invalid access token: {}", err))
+}
+
+/// Mails a login link to a user, unless they were sent one within the cooldown or have been sent
+/// too many in the last hour, in which case it's quietly skipped.
+fn send_login_mail(
+    db: DB,
+    mailer: Mailer,
+    config: &Config,
+    register: bool,
+    id: i32,
+    ip: Option<String>,
+) -> impl Future<Item = (), Error = Error> {
+    let expire_duration = config.login_link_lifetime;
+    let cooldown = config.login_mail_cooldown;
+    let per_user = config.login_mails_per_user;
+    let now = Utc::now();
+    db.get_login_mail_times(id, now - Duration::hours(1))
+        .and_then(move |times| {
+            let cooling_down = times.iter().any(|&at| at > now - cooldown);
+            // The login links already sent still work, so within the cooldown or once the hourly
+            // limit is reached, further mails are quietly skipped. Refusing them would reveal that
+            // the account exists, since no mail is ever refused for an address without one.
+            let mailed = !cooling_down && (times.len() as i64) < per_user;
+            db.record_login_request(ip, Some(id), mailed)
+                .map(move |()| (db, mailed))
+        })
+        .and_then(move |(db, mailed)| {
+            if !mailed {
+                return Either::B(ok(()));
+            }
+
+            let mail = db
+                .get_user(id)
+                .and_then(move |user| {
+                    // Links with less than a quarter of their lifetime left aren't worth resending.
+                    let reuse_after = now + expire_duration / 4;
+                    db.create_login_link(id, now + expire_duration, reuse_after)
+                        .join(Ok(user))
+                })
+                .and_then(move |((token, code, expires), user)| {
+                    let vars = json!({
+                        "code": code,
+                        "duration": HumanTime::from(expires - now).to_text_en(Accuracy::Rough, Tense::Future),
+                        "expires": expires.to_rfc2822(),
+                        "register": register,
+                        "token": token
+                    });
+                    let text = render("login-mail.txt", vars)?;
+                    Ok((user, text))
+                })
+                .and_then(move |(user, text)| {
+                    mailer.send(&user.email, "Log in to ACM CTF 2", &text)
+                });
+            Either::A(mail)
+        })
+}
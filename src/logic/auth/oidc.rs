@@ -0,0 +1,153 @@
+//! Logging in with an OpenID Connect provider, such as a university's single sign-on.
+//!
+//! Only the authorization code flow is supported. The ID token's signature is checked against the
+//! provider's published RSA keys, even though it comes straight from the provider's token
+//! endpoint.
+
+use crate::{
+    dal::{Oidc, DB},
+    logic::auth::LoginMethod,
+    schema::{Jwk, OidcConfig, OidcIdentity},
+    util::{random_code, rsa_public_key_der},
+};
+use failure::{bail, format_err, Error};
+use futures::{future::err, Future};
+use jsonwebtoken::{decode, decode_header, Algorithm, Validation};
+use log::warn;
+use serde_derive::Deserialize;
+
+/// The error for a login at the identity provider that couldn't be finished.
+const OIDC_LOGIN_FAILED: &str = "Logging in with your identity provider didn't work. Try again?";
+
+/// The claims of an ID token that are used.
+#[derive(Debug, Deserialize)]
+struct IdClaims {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    preferred_username: Option<String>,
+    nonce: Option<String>,
+}
+
+/// What the identity provider sends the user back with, along with what was saved when the login
+/// started.
+#[derive(Clone, Debug)]
+pub struct OidcCallback {
+    /// The authorization code, if the user logged in at the provider.
+    pub code: Option<String>,
+
+    /// The state the provider was sent, which it sends back.
+    pub state: Option<String>,
+
+    /// The value returned by `start`, if it was saved.
+    pub saved: Option<String>,
+}
+
+/// Logging in with an OpenID Connect provider. The proof is what the provider sends the user back
+/// with. Identities are linked to users by their verified email address, and users are registered
+/// the first time they log in.
+#[derive(Clone)]
+#[allow(missing_debug_implementations)]
+pub struct OidcLogin(pub Oidc);
+
+impl LoginMethod for OidcLogin {
+    type Proof = OidcCallback;
+
+    fn verify(
+        &self,
+        db: DB,
+        callback: OidcCallback,
+    ) -> Box<dyn Future<Item = i32, Error = Error> + Send> {
+        let (code, nonce) = match check_state(callback) {
+            Ok(code_and_nonce) => code_and_nonce,
+            Err(e) => return Box::new(err(e)),
+        };
+
+        let oidc = self.0.clone();
+        let provider = oidc.config().issuer.clone();
+        Box::new(
+            oidc.exchange_code(&code)
+                .join(oidc.get_keys())
+                .and_then(move |(token, keys)| {
+                    verify_id_token(oidc.config(), &token, &keys, &nonce)
+                })
+                .and_then(move |identity| db.link_identity(provider, identity)),
+        )
+    }
+}
+
+/// Starts logging in with the provider. Returns the URL to send the user to, and a value that must
+/// be saved until they come back, to be passed in the `OidcCallback`.
+pub fn start(oidc: &Oidc) -> (String, String) {
+    let state = random_code(32);
+    let nonce = random_code(32);
+    let url = oidc.authorization_url(&state, &nonce);
+    (url, format!("{}.{}", state, nonce))
+}
+
+/// Checks that the provider sent the user back with the state their login started with, returning
+/// the authorization code and the nonce the ID token must carry.
+fn check_state(callback: OidcCallback) -> Result<(String, String), Error> {
+    let saved = callback.saved.unwrap_or_default();
+    let mut parts = saved.splitn(2, '.');
+    match (callback.code, callback.state, parts.next(), parts.next()) {
+        (Some(code), Some(ref state), Some(saved_state), Some(nonce)) if state == saved_state => {
+            Ok((code, nonce.to_string()))
+        }
+        _ => user_bail!(BadRequest, "{}", OIDC_LOGIN_FAILED),
+    }
+}
+
+/// Checks an ID token's signature, issuer, audience, expiry, and nonce, returning the identity it
+/// vouches for.
+fn verify_id_token(
+    config: &OidcConfig,
+    token: &str,
+    keys: &[Jwk],
+    nonce: &str,
+) -> Result<OidcIdentity, Error> {
+    let header =
+        decode_header(token).map_err(|e| format_err!("Couldn't decode an ID token: {}", e))?;
+    let key = keys
+        .iter()
+        .filter(|key| key.kty == "RSA")
+        .find(|key| header.kid.is_none() || key.kid == header.kid)
+        .ok_or_else(|| format_err!("The identity provider's signing key wasn't found"))?;
+    let key = match (&key.n, &key.e) {
+        (Some(n), Some(e)) => rsa_public_key_der(&decode_base64(n)?, &decode_base64(e)?),
+        _ => bail!("The identity provider's signing key is incomplete"),
+    };
+
+    let mut validation = Validation {
+        algorithms: vec![Algorithm::RS256],
+        iss: Some(config.issuer.clone()),
+        leeway: 60,
+        ..Validation::default()
+    };
+    validation.set_audience(&config.client_id);
+    let claims = match decode::<IdClaims>(token, &key, &validation) {
+        Ok(data) => data.claims,
+        Err(e) => {
+            warn!("Rejected an ID token: {}", e);
+            user_bail!(BadRequest, "{}", OIDC_LOGIN_FAILED);
+        }
+    };
+    if claims.nonce.as_ref().map(String::as_str) != Some(nonce) {
+        warn!("Rejected an ID token with the wrong nonce");
+        user_bail!(BadRequest, "{}", OIDC_LOGIN_FAILED);
+    }
+
+    Ok(OidcIdentity {
+        subject: claims.sub,
+        email: claims.email,
+        email_verified: claims.email_verified,
+        preferred_username: claims.preferred_username,
+    })
+}
+
+/// Decodes the unpadded URL-safe base64 JSON Web Keys use.
+fn decode_base64(s: &str) -> Result<Vec<u8>, Error> {
+    base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+        .map_err(|e| format_err!("The identity provider's signing key is invalid: {}", e))
+}
@@ -0,0 +1,408 @@
+//! Logging in with passkeys, which are WebAuthn credentials stored on the user's device.
+//!
+//! Only what passkeys need is implemented. The browser is asked not to send an attestation, so
+//! any authenticator is trusted, including a software one. Only ES256 and RS256 keys are
+//! supported, which every common authenticator can make.
+
+use crate::{
+    dal::DB,
+    logic::auth::LoginMethod,
+    schema::{Config, Passkey, PasskeyAssertion, PasskeyCredential, PasskeyRegistration, User},
+    util::rsa_public_key_der,
+};
+use chrono::{Duration, Utc};
+use failure::{bail, format_err, Error};
+use futures::{
+    future::{err, result},
+    Future,
+};
+use log::warn;
+use ring::{digest, signature};
+use serde::de::IgnoredAny;
+use serde_bytes::ByteBuf;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use untrusted::Input;
+use uuid::Uuid;
+
+/// The COSE identifier of ECDSA over P-256 with SHA-256.
+const ALG_ES256: i32 = -7;
+
+/// The COSE identifier of RSASSA-PKCS1-v1_5 with SHA-256.
+const ALG_RS256: i32 = -257;
+
+/// How many minutes the browser has to finish registering or logging in with a passkey.
+const CHALLENGE_MINUTES: i64 = 5;
+
+/// The authenticator data flag set when the user was present.
+const FLAG_USER_PRESENT: u8 = 0x01;
+
+/// The authenticator data flag set when a new credential is included.
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// The authenticator data flag set when extension outputs are included.
+const FLAG_EXTENSIONS: u8 = 0x80;
+
+/// The error for a passkey login that couldn't be checked.
+const PASSKEY_LOGIN_FAILED: &str = "Logging in with your passkey didn't work. Try again?";
+
+/// The error for registering a passkey without a name.
+pub const PASSKEY_NAME_MISSING: &str = "Your passkey needs a name.";
+
+/// The error for a passkey that couldn't be registered.
+const PASSKEY_SETUP_FAILED: &str = "That passkey couldn't be set up. Try again?";
+
+/// The parts of an attestation object that are used.
+#[derive(Debug, Deserialize)]
+struct AttestationObject {
+    #[serde(rename = "authData")]
+    auth_data: ByteBuf,
+}
+
+/// The parts of the data parsed from an authenticator that are used.
+#[derive(Debug)]
+struct AuthenticatorData<'a> {
+    sign_count: u32,
+    credential: Option<(&'a [u8], &'a [u8])>,
+}
+
+/// The parts of the client data JSON that are used.
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ty: String,
+    challenge: String,
+    origin: String,
+}
+
+/// A value in a COSE key.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum CoseValue {
+    Int(i64),
+    Bytes(ByteBuf),
+    Other(IgnoredAny),
+}
+
+/// Logging in with a passkey. The proof is what the browser sends back after the user picks a
+/// passkey, which identifies the user on its own, so no username is needed.
+#[derive(Clone, Debug)]
+pub struct PasskeyLogin {
+    origin: String,
+    rp_id: String,
+}
+
+impl PasskeyLogin {
+    /// Creates the login method for the origin and relying party ID in the config.
+    pub fn new(config: &Config) -> PasskeyLogin {
+        PasskeyLogin {
+            origin: config.webauthn_origin.clone(),
+            rp_id: config.webauthn_rp_id.clone(),
+        }
+    }
+
+    /// Checks an assertion made with a passkey, returning the challenge it signed and the
+    /// authenticator's new signature counter.
+    fn check_assertion(
+        &self,
+        credential: &PasskeyCredential,
+        client_data: &[u8],
+        auth_data: &[u8],
+        sig: &[u8],
+    ) -> Result<(Uuid, i64), Error> {
+        let challenge = check_client_data(&self.origin, client_data, "webauthn.get")?;
+        let parsed = parse_auth_data(&self.rp_id, auth_data)?;
+
+        let mut signed = auth_data.to_vec();
+        signed.extend_from_slice(digest::digest(&digest::SHA256, client_data).as_ref());
+        let algorithm: &dyn signature::VerificationAlgorithm = match credential.algorithm {
+            ALG_ES256 => &signature::ECDSA_P256_SHA256_ASN1,
+            ALG_RS256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+            alg => bail!("The passkey uses an unsupported algorithm, {}", alg),
+        };
+        signature::verify(
+            algorithm,
+            Input::from(&credential.public_key[..]),
+            Input::from(&signed[..]),
+            Input::from(sig),
+        )
+        .map_err(|_| format_err!("The signature is invalid"))?;
+
+        // Authenticators that don't count signatures always send zero. Otherwise, a counter that
+        // didn't go up means the passkey may have been cloned.
+        let sign_count = i64::from(parsed.sign_count);
+        if (credential.sign_count != 0 || sign_count != 0) && sign_count <= credential.sign_count {
+            bail!(
+                "The signature counter of passkey {} went from {} to {}; it may have been cloned",
+                credential.id,
+                credential.sign_count,
+                sign_count
+            );
+        }
+        Ok((challenge, sign_count))
+    }
+}
+
+impl LoginMethod for PasskeyLogin {
+    type Proof = PasskeyAssertion;
+
+    fn verify(
+        &self,
+        db: DB,
+        assertion: PasskeyAssertion,
+    ) -> Box<dyn Future<Item = i32, Error = Error> + Send> {
+        let decoded = decode_base64(&assertion.credential_id)
+            .and_then(|id| Ok((id, decode_base64(&assertion.client_data)?)))
+            .and_then(|(id, client_data)| {
+                let auth_data = decode_base64(&assertion.authenticator_data)?;
+                let signature = decode_base64(&assertion.signature)?;
+                Ok((id, client_data, auth_data, signature))
+            });
+        let (credential_id, client_data, auth_data, signature) = match decoded {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                warn!("Rejected a passkey login: {}", e);
+                return Box::new(err(user_err!(BadRequest, "{}", PASSKEY_LOGIN_FAILED)));
+            }
+        };
+
+        let this = self.clone();
+        Box::new(
+            db.get_passkey(credential_id)
+                .map_err(|_| user_err!(BadRequest, "That passkey isn't registered here."))
+                .and_then(move |credential| {
+                    let checked =
+                        this.check_assertion(&credential, &client_data, &auth_data, &signature);
+                    match checked {
+                        Ok((challenge, sign_count)) => Ok((credential, challenge, sign_count)),
+                        Err(e) => {
+                            warn!("Rejected a passkey login: {}", e);
+                            user_bail!(BadRequest, "{}", PASSKEY_LOGIN_FAILED)
+                        }
+                    }
+                })
+                .and_then(move |(credential, challenge, sign_count)| {
+                    db.record_passkey_use(
+                        challenge,
+                        credential.id,
+                        credential.sign_count,
+                        sign_count,
+                    )
+                }),
+        )
+    }
+}
+
+/// Deletes one of a user's passkeys.
+pub fn delete_passkey(db: DB, user: i32, passkey: i32) -> impl Future<Item = (), Error = Error> {
+    db.delete_passkey(user, passkey)
+}
+
+/// Finishes registering a passkey for a user, checking what the browser sent back against the
+/// challenge `start_registration` gave it.
+pub fn finish_registration(
+    db: DB,
+    config: &Config,
+    user: i32,
+    registration: PasskeyRegistration,
+) -> impl Future<Item = (), Error = Error> {
+    let name = registration.name.trim().to_string();
+    let checked = if name.is_empty() {
+        Err(user_err!(BadRequest, "{}", PASSKEY_NAME_MISSING))
+    } else {
+        check_registration(config, &registration).map_err(|e| {
+            warn!("Rejected a passkey registration: {}", e);
+            user_err!(BadRequest, "{}", PASSKEY_SETUP_FAILED)
+        })
+    };
+    result(checked).and_then(move |(challenge, credential_id, algorithm, public_key)| {
+        db.create_passkey(user, challenge, name, credential_id, algorithm, public_key)
+    })
+}
+
+/// Gets a user's passkeys.
+pub fn get_passkeys(db: DB, user: i32) -> impl Future<Item = Vec<Passkey>, Error = Error> {
+    db.get_passkeys(user)
+}
+
+/// Starts logging in with a passkey, returning the options to pass to
+/// `navigator.credentials.get`, with binary values as unpadded URL-safe base64.
+pub fn start_login(db: DB, config: &Config) -> impl Future<Item = Value, Error = Error> {
+    let rp_id = config.webauthn_rp_id.clone();
+    let expires = Utc::now() + Duration::minutes(CHALLENGE_MINUTES);
+    db.create_webauthn_challenge(None, expires)
+        .map(move |challenge| {
+            json!({
+                "challenge": encode_base64(challenge.as_bytes()),
+                "rpId": rp_id,
+                "timeout": CHALLENGE_MINUTES * 60 * 1000,
+                "userVerification": "preferred",
+            })
+        })
+}
+
+/// Starts registering a passkey for a user, returning the options to pass to
+/// `navigator.credentials.create`, with binary values as unpadded URL-safe base64. The user's
+/// existing passkeys are excluded, so the same authenticator isn't registered twice.
+pub fn start_registration(
+    db: DB,
+    config: &Config,
+    user: User,
+) -> impl Future<Item = Value, Error = Error> {
+    let rp_id = config.webauthn_rp_id.clone();
+    let expires = Utc::now() + Duration::minutes(CHALLENGE_MINUTES);
+    db.get_passkey_credential_ids(user.id)
+        .join(db.create_webauthn_challenge(Some(user.id), expires))
+        .map(move |(credential_ids, challenge)| {
+            let exclude = credential_ids
+                .iter()
+                .map(|id| json!({ "type": "public-key", "id": encode_base64(id) }))
+                .collect::<Vec<_>>();
+            json!({
+                "challenge": encode_base64(challenge.as_bytes()),
+                "rp": { "id": rp_id, "name": "ACM CTF 2" },
+                "user": {
+                    "id": encode_base64(&user.id.to_be_bytes()),
+                    "name": user.name,
+                    "displayName": user.name,
+                },
+                "pubKeyCredParams": [
+                    { "type": "public-key", "alg": ALG_ES256 },
+                    { "type": "public-key", "alg": ALG_RS256 },
+                ],
+                "timeout": CHALLENGE_MINUTES * 60 * 1000,
+                "attestation": "none",
+                "authenticatorSelection": {
+                    "residentKey": "required",
+                    "requireResidentKey": true,
+                    "userVerification": "preferred",
+                },
+                "excludeCredentials": exclude,
+            })
+        })
+}
+
+/// Checks the client data the browser had the authenticator sign, returning the challenge in it.
+fn check_client_data(origin: &str, client_data: &[u8], ty: &str) -> Result<Uuid, Error> {
+    let client_data: ClientData = serde_json::from_slice(client_data)?;
+    if client_data.ty != ty {
+        bail!(
+            "Expected client data of type {}, not {}",
+            ty,
+            client_data.ty
+        );
+    }
+    if client_data.origin != origin {
+        bail!(
+            "The client data is from {}, not {}",
+            client_data.origin,
+            origin
+        );
+    }
+    let challenge = decode_base64(&client_data.challenge)?;
+    Uuid::from_bytes(&challenge).map_err(|_| format_err!("The challenge is malformed"))
+}
+
+/// Checks a new passkey, returning the challenge it signed, its credential ID, and its algorithm
+/// and public key.
+fn check_registration(
+    config: &Config,
+    registration: &PasskeyRegistration,
+) -> Result<(Uuid, Vec<u8>, i32, Vec<u8>), Error> {
+    let client_data = decode_base64(&registration.client_data)?;
+    let challenge = check_client_data(&config.webauthn_origin, &client_data, "webauthn.create")?;
+
+    let attestation = decode_base64(&registration.attestation_object)?;
+    let attestation: AttestationObject = serde_cbor::from_slice(&attestation)?;
+    let auth_data = parse_auth_data(&config.webauthn_rp_id, &attestation.auth_data)?;
+    let (credential_id, key) = auth_data
+        .credential
+        .ok_or_else(|| format_err!("The authenticator data has no credential"))?;
+    let (algorithm, public_key) = parse_cose_key(key)?;
+    Ok((challenge, credential_id.to_vec(), algorithm, public_key))
+}
+
+/// Parses authenticator data, checking that it's for the right relying party and that the user
+/// was present. Extensions aren't requested, so authenticator data with them is rejected.
+fn parse_auth_data<'a>(rp_id: &str, data: &'a [u8]) -> Result<AuthenticatorData<'a>, Error> {
+    if data.len() < 37 {
+        bail!("The authenticator data is too short");
+    }
+    if &data[..32] != digest::digest(&digest::SHA256, rp_id.as_bytes()).as_ref() {
+        bail!("The authenticator data is for another relying party");
+    }
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        bail!("The user wasn't present");
+    }
+    if flags & FLAG_EXTENSIONS != 0 {
+        bail!("The authenticator data has extensions");
+    }
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let rest = &data[37..];
+    let credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // The credential starts with a 16-byte AAGUID and a 2-byte length, then has the ID and
+        // public key.
+        if rest.len() < 18 {
+            bail!("The attested credential data is too short");
+        }
+        let len = usize::from(u16::from_be_bytes([rest[16], rest[17]]));
+        if len > 1023 || rest.len() < 18 + len {
+            bail!("The credential ID has an invalid length");
+        }
+        Some((&rest[18..18 + len], &rest[18 + len..]))
+    } else if rest.is_empty() {
+        None
+    } else {
+        bail!("The authenticator data has trailing bytes");
+    };
+    Ok(AuthenticatorData {
+        sign_count,
+        credential,
+    })
+}
+
+/// Parses a COSE public key, returning its algorithm and the key in the form `ring` checks
+/// signatures with.
+fn parse_cose_key(key: &[u8]) -> Result<(i32, Vec<u8>), Error> {
+    let key: BTreeMap<i64, CoseValue> = serde_cbor::from_slice(key)?;
+    let int = |label| match key.get(&label) {
+        Some(CoseValue::Int(n)) => Some(*n),
+        _ => None,
+    };
+    let bytes = |label| match key.get(&label) {
+        Some(CoseValue::Bytes(b)) => Some(&b[..]),
+        _ => None,
+    };
+
+    // Label 1 is the key type, and label 3 is the algorithm; the rest depend on the key type.
+    match (int(1), int(3)) {
+        (Some(2), Some(-7)) => match (int(-1), bytes(-2), bytes(-3)) {
+            (Some(1), Some(x), Some(y)) if x.len() == 32 && y.len() == 32 => {
+                let mut public_key = vec![0x04];
+                public_key.extend_from_slice(x);
+                public_key.extend_from_slice(y);
+                Ok((ALG_ES256, public_key))
+            }
+            _ => bail!("The ES256 key is malformed"),
+        },
+        (Some(3), Some(-257)) => match (bytes(-1), bytes(-2)) {
+            (Some(n), Some(e)) => Ok((ALG_RS256, rsa_public_key_der(n, e))),
+            _ => bail!("The RS256 key is malformed"),
+        },
+        (kty, alg) => bail!("Unsupported key type {:?} with algorithm {:?}", kty, alg),
+    }
+}
+
+/// Decodes the unpadded URL-safe base64 WebAuthn values are sent as.
+fn decode_base64(s: &str) -> Result<Vec<u8>, Error> {
+    base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+        .map_err(|e| format_err!("Invalid base64: {}", e))
+}
+
+/// Encodes bytes as unpadded URL-safe base64.
+fn encode_base64(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
@@ -0,0 +1,334 @@
+//! Two-factor authentication with time-based one-time passwords (RFC 6238), as shown by
+//! authenticator apps.
+//!
+//! Codes are six digits, change every 30 seconds, and are accepted one step early or late to allow
+//! for clock drift. Each code only works once. Recovery codes can be used instead of a code, once
+//! each, for when the user's phone is lost.
+
+use crate::{
+    dal::DB,
+    schema::User,
+    util::{base32, random_code},
+};
+use chrono::{DateTime, Duration, Utc};
+use failure::{format_err, Error};
+use futures::{
+    future::{err, ok, Either},
+    Future,
+};
+use ring::{
+    constant_time, digest, hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use uuid::Uuid;
+
+/// How many wrong codes can be tried in a session before it can't try any more.
+const MAX_CODE_ATTEMPTS: i32 = 5;
+
+/// How many recovery codes a user is given.
+const RECOVERY_CODES: usize = 10;
+
+/// The length of a recovery code, not counting the dash in the middle.
+const RECOVERY_CODE_LEN: usize = 10;
+
+/// The length of a TOTP secret, in bytes.
+const SECRET_LEN: usize = 20;
+
+/// How many seconds each TOTP code is valid for.
+const STEP_SECONDS: i64 = 30;
+
+/// How long a session stays stepped up after a second factor is checked in it.
+const STEP_UP_MINUTES: i64 = 30;
+
+/// The error for a wrong code.
+pub const WRONG_CODE: &str = "That code is wrong.";
+
+/// How far a session has gotten with two-factor authentication.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StepUp {
+    /// The user hasn't set up two-factor authentication.
+    NotEnrolled,
+
+    /// The user has set up two-factor authentication, but hasn't entered a code in this session
+    /// recently.
+    Required,
+
+    /// The user entered a code in this session recently.
+    Done,
+}
+
+/// Turns off two-factor authentication for a user, which takes a current code or a recovery code.
+pub fn disable(
+    db: DB,
+    user: i32,
+    auth: &str,
+    code: String,
+) -> impl Future<Item = (), Error = Error> {
+    check_code(db.clone(), user, auth, code).and_then(move |_| db.disable_totp(user))
+}
+
+/// Finishes setting up two-factor authentication for a user, with a code from their
+/// authenticator app proving it has the secret. The session is stepped up. Returns the user's
+/// recovery codes, which can't be shown again.
+pub fn enable(
+    db: DB,
+    user: i32,
+    auth: &str,
+    code: String,
+) -> impl Future<Item = Vec<String>, Error = Error> {
+    let auth = match auth.parse::<Uuid>() {
+        Ok(auth) => auth,
+        Err(e) => return Either::B(err(e.into())),
+    };
+    let code = normalize_code(&code);
+    let codes = (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = random_code(RECOVERY_CODE_LEN);
+            let (a, b) = code.split_at(RECOVERY_CODE_LEN / 2);
+            format!("{}-{}", a, b)
+        })
+        .collect::<Vec<_>>();
+    let hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+
+    let setup = db.get_totp_secret(user).and_then({
+        let db = db.clone();
+        move |secret| {
+            let step = match secret {
+                Some((ref secret, false)) => find_step(secret, &code, Utc::now()),
+                _ => user_bail!(
+                    BadRequest,
+                    "Start setting up two-factor authentication again."
+                ),
+            };
+            match step {
+                Some(step) => Ok((db, step)),
+                None => user_bail!(BadRequest, "{}", WRONG_CODE),
+            }
+        }
+    });
+    Either::A(
+        setup
+            .and_then(move |(db, step)| db.enable_totp(user, step, hashes).map(|()| db))
+            .and_then(move |db| db.step_up_session(auth))
+            .map(|()| codes),
+    )
+}
+
+/// Gets the pending TOTP secret of a user who has started setting up two-factor authentication,
+/// as base32 and as an `otpauth://` URI for authenticator apps to scan. Returns `None` if there
+/// isn't one.
+pub fn get_setup(
+    db: DB,
+    user: User,
+) -> impl Future<Item = Option<(String, String)>, Error = Error> {
+    db.get_totp_secret(user.id).map(move |secret| match secret {
+        Some((secret, false)) => {
+            let secret = base32(&secret);
+            let uri = format!(
+                "otpauth://totp/ACM%20CTF%202:{}?secret={}&issuer=ACM%20CTF%202",
+                user.name, secret
+            );
+            Some((secret, uri))
+        }
+        _ => None,
+    })
+}
+
+/// Returns how many recovery codes a user has left, or `None` if they haven't set up two-factor
+/// authentication.
+pub fn get_status(db: DB, user: i32) -> impl Future<Item = Option<i64>, Error = Error> {
+    db.get_totp_secret(user)
+        .and_then(move |secret| match secret {
+            Some((_, true)) => Either::A(db.count_recovery_codes(user).map(Some)),
+            _ => Either::B(ok(None)),
+        })
+}
+
+/// Checks how far a session has gotten with two-factor authentication.
+pub fn get_step_up(db: DB, user: i32, auth: &str) -> impl Future<Item = StepUp, Error = Error> {
+    let auth = match auth.parse::<Uuid>() {
+        Ok(auth) => auth,
+        Err(e) => return Either::B(err(e.into())),
+    };
+    Either::A(
+        db.get_totp_secret(user)
+            .join(db.get_session_second_factor(auth))
+            .map(|(secret, (stepped_up, _))| match secret {
+                Some((_, true)) if is_recent(stepped_up) => StepUp::Done,
+                Some((_, true)) => StepUp::Required,
+                _ => StepUp::NotEnrolled,
+            }),
+    )
+}
+
+/// Starts setting up two-factor authentication for a user, creating a secret for them to add to
+/// their authenticator app.
+pub fn start_setup(db: DB, user: i32) -> impl Future<Item = (), Error = Error> {
+    let mut secret = vec![0; SECRET_LEN];
+    match SystemRandom::new().fill(&mut secret) {
+        Ok(()) => Either::A(db.create_totp_secret(user, secret)),
+        Err(_) => Either::B(err(format_err!("Couldn't generate a TOTP secret"))),
+    }
+}
+
+/// Steps up a session with a current code or a recovery code.
+pub fn step_up(
+    db: DB,
+    user: i32,
+    auth: &str,
+    code: String,
+) -> impl Future<Item = (), Error = Error> {
+    check_code(db.clone(), user, auth, code).and_then(move |auth| db.step_up_session(auth))
+}
+
+/// Checks a current code or a recovery code, typed in during the given session, returning the
+/// session's ID. Each session can only try `MAX_CODE_ATTEMPTS` wrong codes.
+fn check_code(
+    db: DB,
+    user: i32,
+    auth: &str,
+    code: String,
+) -> impl Future<Item = Uuid, Error = Error> {
+    let auth = match auth.parse::<Uuid>() {
+        Ok(auth) => auth,
+        Err(e) => return Either::B(err(e.into())),
+    };
+    let code = normalize_code(&code);
+
+    let checked = db
+        .get_session_second_factor(auth)
+        .join(db.get_totp_secret(user))
+        .and_then({
+            let db = db.clone();
+            move |((_, failed_codes), secret)| {
+                if failed_codes >= MAX_CODE_ATTEMPTS {
+                    user_bail!(
+                        TooManyRequests,
+                        "Too many wrong codes have been tried. Log in again to keep trying."
+                    );
+                }
+                let secret = match secret {
+                    Some((secret, true)) => secret,
+                    _ => user_bail!(BadRequest, "Two-factor authentication isn't set up."),
+                };
+
+                if code.len() == 6 && code.bytes().all(|b| b.is_ascii_digit()) {
+                    let used = match find_step(&secret, &code, Utc::now()) {
+                        Some(step) => Either::A(db.record_totp_step(user, step)),
+                        None => Either::B(ok(false)),
+                    };
+                    Ok(Either::A(used))
+                } else {
+                    Ok(Either::B(
+                        db.use_recovery_code(user, hash_recovery_code(&code)),
+                    ))
+                }
+            }
+        })
+        .flatten();
+    Either::A(checked.and_then(move |valid| {
+        if valid {
+            Either::A(ok(auth))
+        } else {
+            Either::B(
+                db.record_failed_code(auth)
+                    .and_then(|()| Err(user_err!(BadRequest, "{}", WRONG_CODE))),
+            )
+        }
+    }))
+}
+
+/// Finds the time step, within one of the current one, that a code is for. Codes are compared in
+/// constant time, so how long checking takes doesn't give away how much of a code was right.
+fn find_step(secret: &[u8], code: &str, now: DateTime<Utc>) -> Option<i64> {
+    let current = now.timestamp() / STEP_SECONDS;
+    (current - 1..=current + 1).find(|&step| {
+        let expected = totp_code(secret, step);
+        constant_time::verify_slices_are_equal(expected.as_bytes(), code.as_bytes()).is_ok()
+    })
+}
+
+/// Hashes a recovery code for storage. Case, spaces, and dashes are ignored.
+fn hash_recovery_code(code: &str) -> Vec<u8> {
+    let code = normalize_code(code).to_uppercase();
+    digest::digest(&digest::SHA256, code.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+/// Returns whether a session was stepped up recently enough to still count.
+fn is_recent(stepped_up: Option<DateTime<Utc>>) -> bool {
+    stepped_up.map_or(false, |at| {
+        at > Utc::now() - Duration::minutes(STEP_UP_MINUTES)
+    })
+}
+
+/// Removes the spaces and dashes people type into codes.
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect()
+}
+
+/// Computes the TOTP code for a time step.
+fn totp_code(secret: &[u8], step: i64) -> String {
+    let key = hmac::SigningKey::new(&digest::SHA1, secret);
+    let mac = hmac::sign(&key, &step.to_be_bytes());
+    let mac = mac.as_ref();
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let bin = u32::from_be_bytes([
+        mac[offset],
+        mac[offset + 1],
+        mac[offset + 2],
+        mac[offset + 3],
+    ]) & 0x7fff_ffff;
+    format!("{:06}", bin % 1_000_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// The SHA-1 secret from the test vectors in RFC 6238, appendix B.
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn totp_code_matches_rfc_6238() {
+        // The RFC's codes are eight digits; ours are the last six of them.
+        let vectors = [
+            (59, "287082"),
+            (1_111_111_109, "081804"),
+            (1_111_111_111, "050471"),
+            (1_234_567_890, "005924"),
+            (2_000_000_000, "279037"),
+            (20_000_000_000, "353130"),
+        ];
+        for &(time, code) in &vectors {
+            assert_eq!(totp_code(SECRET, time / STEP_SECONDS), code, "at {}", time);
+        }
+    }
+
+    #[test]
+    fn find_step_allows_one_step_of_drift() {
+        let now = Utc.timestamp(1_111_111_111, 0);
+        let current = now.timestamp() / STEP_SECONDS;
+        for step in current - 1..=current + 1 {
+            let code = totp_code(SECRET, step);
+            assert_eq!(find_step(SECRET, &code, now), Some(step));
+        }
+        for &step in &[current - 2, current + 2] {
+            let code = totp_code(SECRET, step);
+            assert_eq!(find_step(SECRET, &code, now), None);
+        }
+    }
+
+    #[test]
+    fn find_step_rejects_wrong_codes() {
+        let now = Utc.timestamp(59, 0);
+        assert_eq!(find_step(SECRET, "287082", now), Some(1));
+        assert_eq!(find_step(SECRET, "287083", now), None);
+        assert_eq!(find_step(SECRET, "28708", now), None);
+        assert_eq!(find_step(SECRET, "", now), None);
+    }
+}
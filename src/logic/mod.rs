@@ -4,19 +4,502 @@
 //! > necessary information out of the HTTP request, and call into this module as quickly as
 //! > possible to do all the actual work.
 
+pub mod admin;
 pub mod auth;
 
-use crate::dal::DB;
+use crate::{
+    dal::{Events, Mailer, DB},
+    schema::{
+        Announcement, ClientInfo, Config, Division, Event, Invite, ProblemListing, Solve, Standing,
+        Team, TeamMember, TeamProfile, Ticket, TicketMessage,
+    },
+    view::render,
+};
+use chrono::{Duration, Utc};
+use chrono_humanize::{Accuracy, HumanTime, Tense};
 use failure::Error;
-use futures::Future;
+use futures::{
+    future::{err, join_all, Either},
+    stream::iter_ok,
+    Future, Stream,
+};
+use log::{error, warn};
+use ring::constant_time;
+use serde_json::json;
 use uuid::Uuid;
 
+/// How many invites an email address can be sent per hour, by all teams together.
+const INVITES_PER_EMAIL: i64 = 3;
+
+/// How many invites a team can send per hour.
+const INVITES_PER_TEAM: i64 = 10;
+
+/// How many times delivering a mail is tried before it's given up on.
+const MAIL_ATTEMPTS: i32 = 10;
+
+/// How many wrong flags a team can submit per minute.
+const WRONG_FLAGS_PER_MINUTE: i64 = 10;
+
+/// Accepts an invite to a team with the token from the invite mail. If `user` isn't given, the
+/// invitee must not have registered yet; they're registered with `username`, and the
+/// authentication token they're logged in with is returned.
+pub fn accept_invite(
+    db: DB,
+    events: Events,
+    config: &Config,
+    token: Uuid,
+    user: Option<i32>,
+    username: Option<String>,
+    client: ClientInfo,
+) -> impl Future<Item = Option<Uuid>, Error = Error> {
+    let session_expires = Utc::now() + config.session_lifetime;
+    db.accept_invite(
+        token,
+        user,
+        username,
+        config.max_team_size,
+        session_expires,
+        client,
+    )
+    .map(move |token| {
+        events.publish(Event::TeamsChanged);
+        token
+    })
+}
+
 /// Creates a team.
-pub fn create_team(db: DB, user: i32, name: String) -> impl Future<Item = (), Error = Error> {
-    db.create_team(user, name).map(|_| ())
+pub fn create_team(
+    db: DB,
+    events: Events,
+    user: i32,
+    name: String,
+) -> impl Future<Item = (), Error = Error> {
+    db.create_team(user, name)
+        .map(move |_| events.publish(Event::TeamsChanged))
+}
+
+/// Delivers the mail in the outbox that's due. A mail that can't be delivered is tried again
+/// after 30 seconds, then twice as long after each further failure, until it's been tried
+/// `MAIL_ATTEMPTS` times; then it's left in the outbox for an admin to look at.
+pub fn deliver_mail(db: DB, mailer: Mailer) -> impl Future<Item = (), Error = Error> {
+    // The claimed mail isn't claimed again for a while, so other servers don't deliver it too.
+    let lease_until = Utc::now() + Duration::minutes(5);
+    db.claim_due_mail(32, lease_until).and_then(move |mail| {
+        iter_ok(mail).for_each(move |mail| {
+            let db = db.clone();
+            mailer.deliver(&mail).then(move |r| match r {
+                Ok(()) => Either::A(db.record_mail_sent(mail.id)),
+                Err(e) => {
+                    let attempts = mail.attempts + 1;
+                    let retry_at = if attempts < MAIL_ATTEMPTS {
+                        warn!("Couldn't mail {}, will retry: {}", mail.recipient, e);
+                        let backoff = Duration::seconds(30 * 2i64.pow(attempts as u32 - 1));
+                        Some(Utc::now() + backoff)
+                    } else {
+                        error!("Couldn't mail {}, giving up: {}", mail.recipient, e);
+                        None
+                    };
+                    Either::B(db.record_mail_failure(mail.id, e.to_string(), retry_at))
+                }
+            })
+        })
+    })
+}
+
+/// Gets the name of the team the invite with the given token is for, and whether the invitee has
+/// already registered.
+pub fn get_invite(db: DB, token: Uuid) -> impl Future<Item = (String, bool), Error = Error> {
+    db.get_invite(token)
+}
+
+/// Gets a problem players can see by name, as shown to the given team.
+pub fn get_problem(
+    db: DB,
+    name: String,
+    team: Option<Uuid>,
+) -> impl Future<Item = ProblemListing, Error = Error> {
+    db.get_problem(name, team)
+}
+
+/// Gets a division by name, along with the teams in it.
+pub fn get_division_teams(
+    db: DB,
+    name: String,
+) -> impl Future<Item = (Division, Vec<Team>), Error = Error> {
+    db.get_division_teams(name)
+}
+
+/// Gets a team's public profile, its members, its division, and the problems it's solved, in the
+/// order it solved them.
+pub fn get_public_team(
+    db: DB,
+    name: String,
+) -> impl Future<Item = (Team, Vec<TeamMember>, Option<Division>, Vec<Solve>), Error = Error> {
+    db.get_team_by_name(name).and_then(move |team| {
+        db.get_team_members(team.id)
+            .join3(db.get_divisions(), db.get_team_solves(team.id))
+            .map(|(members, divisions, solves)| {
+                let division = divisions
+                    .into_iter()
+                    .find(|division| Some(division.id) == team.division);
+                (team, members, division, solves)
+            })
+    })
+}
+
+/// Gets the scoreboard, either of every team or of the teams in the division with the given name.
+pub fn get_scoreboard(
+    db: DB,
+    division: Option<String>,
+) -> impl Future<Item = (Option<Division>, Vec<Standing>), Error = Error> {
+    db.get_scoreboard(division)
+}
+
+/// Gets a team and its members.
+pub fn get_team(db: DB, team: Uuid) -> impl Future<Item = (Team, Vec<TeamMember>), Error = Error> {
+    db.get_team(team).join(db.get_team_members(team))
+}
+
+/// Gets one of a team's tickets and its messages.
+pub fn get_team_ticket(
+    db: DB,
+    team: Uuid,
+    ticket: i32,
+) -> impl Future<Item = (Ticket, Vec<TicketMessage>), Error = Error> {
+    db.get_ticket(ticket)
+        .and_then(move |(ticket, _, messages)| {
+            if ticket.team == Some(team) {
+                Ok((ticket, messages))
+            } else {
+                Err(user_err!(NotFound, "That ticket doesn't exist."))
+            }
+        })
+}
+
+/// Invites the given email address to the team captained by `captain`, mailing them a link to
+/// accept the invite. Both the team and the address can only be sent so many invites per hour.
+pub fn invite_to_team(
+    db: DB,
+    mailer: Mailer,
+    captain: i32,
+    email: String,
+) -> impl Future<Item = (), Error = Error> {
+    let expire_duration = Duration::weeks(1);
+
+    let email = email.trim().to_string();
+    let now = Utc::now();
+    let expires = now + expire_duration;
+    db.count_invites(captain, email.clone(), now - Duration::hours(1))
+        .and_then(|(team_invites, email_invites)| {
+            if team_invites >= INVITES_PER_TEAM {
+                user_bail!(TooManyRequests, "Your team has sent too many invites. Try again in a little while.");
+            }
+            if email_invites >= INVITES_PER_EMAIL {
+                user_bail!(TooManyRequests, "That address has been sent too many invites. Try again in a little while.");
+            }
+            Ok(())
+        })
+        .and_then({
+            let db = db.clone();
+            let email = email.clone();
+            move |()| db.create_invite(captain, email, expires)
+        })
+        .and_then(move |(token, team, captain)| {
+            let vars = json!({
+                "captain": captain,
+                "duration": HumanTime::from(expire_duration).to_text_en(Accuracy::Rough, Tense::Future),
+                "expires": expires.to_rfc2822(),
+                "team": team,
+                "token": token
+            });
+            render("invite-mail.txt", vars)
+        })
+        .and_then(move |text| mailer.send(&email, "Join a team on ACM CTF 2", &text))
+}
+
+/// Joins the team with the given join code.
+pub fn join_team(
+    db: DB,
+    events: Events,
+    config: &Config,
+    user: i32,
+    join_code: String,
+) -> impl Future<Item = (), Error = Error> {
+    db.join_team(user, join_code.trim().to_uppercase(), config.max_team_size)
+        .map(move |()| events.publish(Event::TeamsChanged))
+}
+
+/// Removes a member from the team captained by `captain`.
+pub fn kick_team_member(
+    db: DB,
+    events: Events,
+    captain: i32,
+    member: String,
+) -> impl Future<Item = (), Error = Error> {
+    db.kick_team_member(captain, member)
+        .map(move |()| events.publish(Event::TeamsChanged))
+}
+
+/// Leaves the user's team.
+pub fn leave_team(
+    db: DB,
+    events: Events,
+    config: &Config,
+    user: i32,
+) -> impl Future<Item = (), Error = Error> {
+    db.leave_team(user, config.solo)
+        .map(move |()| events.publish(Event::TeamsChanged))
+}
+
+/// Lists every announcement, newest first.
+pub fn list_announcements(db: DB) -> impl Future<Item = Vec<Announcement>, Error = Error> {
+    db.get_announcements()
+}
+
+/// Lists every division.
+pub fn list_divisions(db: DB) -> impl Future<Item = Vec<Division>, Error = Error> {
+    db.get_divisions()
+}
+
+/// Lists the announcements about a problem, newest first.
+pub fn list_problem_announcements(
+    db: DB,
+    name: String,
+) -> impl Future<Item = Vec<Announcement>, Error = Error> {
+    db.get_problem_announcements(name)
+}
+
+/// Lists the problems players can see, as shown to the given team.
+pub fn list_problems(
+    db: DB,
+    team: Option<Uuid>,
+) -> impl Future<Item = Vec<ProblemListing>, Error = Error> {
+    db.get_problems(team)
+}
+
+/// Lists the problems a team has solved, in the order it solved them.
+pub fn list_team_solves(db: DB, team: Uuid) -> impl Future<Item = Vec<Solve>, Error = Error> {
+    db.get_team_solves(team)
+}
+
+/// Lists a team's tickets, newest first.
+pub fn list_team_tickets(db: DB, team: Uuid) -> impl Future<Item = Vec<Ticket>, Error = Error> {
+    db.get_team_tickets(team)
+}
+
+/// Lists every team, along with its members' names.
+pub fn list_teams(db: DB) -> impl Future<Item = Vec<(Team, Vec<String>)>, Error = Error> {
+    db.get_teams()
+}
+
+/// Opens a ticket for the user's team, optionally about a problem, mailing the mods about it.
+pub fn open_ticket(
+    db: DB,
+    events: Events,
+    mailer: Mailer,
+    user: i32,
+    subject: String,
+    body: String,
+    problem: Option<i32>,
+) -> impl Future<Item = i32, Error = Error> {
+    let subject = subject.trim().to_string();
+    let body = body.trim().to_string();
+    if subject.is_empty() || body.is_empty() {
+        return Either::B(err(user_err!(
+            BadRequest,
+            "Tickets need a subject and a message."
+        )));
+    }
+
+    Either::A(
+        db.create_ticket(user, subject, body.clone(), problem)
+            .and_then(move |(ticket, team)| {
+                events.publish(Event::TicketsChanged);
+                let id = ticket.id;
+                notify_mods(db, mailer, ticket, team, body, true).map(move |()| id)
+            }),
+    )
+}
+
+/// Replaces the join code of the team captained by `captain`, optionally making the new code
+/// expire after a while or after a number of uses.
+pub fn regenerate_join_code(
+    db: DB,
+    captain: i32,
+    expires_in: Option<Duration>,
+    uses: Option<i32>,
+) -> impl Future<Item = String, Error = Error> {
+    let expires = expires_in.map(|d| Utc::now() + d);
+    db.regenerate_join_code(captain, expires, uses)
+}
+
+/// Replies to one of the user's team's tickets, reopening it if it was closed, and mails the mods
+/// about it.
+pub fn reply_to_ticket(
+    db: DB,
+    events: Events,
+    mailer: Mailer,
+    user: i32,
+    ticket: i32,
+    body: String,
+) -> impl Future<Item = (), Error = Error> {
+    let body = body.trim().to_string();
+    if body.is_empty() {
+        return Either::B(err(user_err!(BadRequest, "Your message is empty.")));
+    }
+
+    Either::A(
+        db.reply_to_ticket(user, ticket, body.clone())
+            .and_then(move |(ticket, team)| {
+                events.publish(Event::TicketsChanged);
+                notify_mods(db, mailer, ticket, team, body, false)
+            }),
+    )
+}
+
+/// Revokes an invite to the team captained by `captain`.
+pub fn revoke_invite(db: DB, captain: i32, invite: Uuid) -> impl Future<Item = (), Error = Error> {
+    db.revoke_invite(captain, invite)
+}
+
+/// Puts the team captained by `captain` in a division, or takes it out of its division.
+pub fn set_team_division(
+    db: DB,
+    events: Events,
+    captain: i32,
+    division: Option<i32>,
+) -> impl Future<Item = (), Error = Error> {
+    db.set_team_division(captain, division)
+        .map(move |()| events.publish(Event::TeamsChanged))
+}
+
+/// Submits a flag for a problem for the user's team, returning whether the team hadn't already
+/// solved it. Flags are compared in constant time, and a team can only submit
+/// `WRONG_FLAGS_PER_MINUTE` wrong flags a minute, so they can't be guessed. New solves are
+/// published, along with whether they're the problem's first.
+pub fn submit_flag(
+    db: DB,
+    events: Events,
+    user: i32,
+    problem: String,
+    flag: String,
+) -> impl Future<Item = bool, Error = Error> {
+    let flag = flag.trim().to_string();
+    db.count_wrong_flags(user, Utc::now() - Duration::minutes(1))
+        .and_then({
+            let db = db.clone();
+            move |wrong| {
+                if wrong >= WRONG_FLAGS_PER_MINUTE {
+                    user_bail!(
+                        TooManyRequests,
+                        "Your team has submitted too many wrong flags. Try again in a minute."
+                    );
+                }
+                Ok(db
+                    .get_problem_flag(problem.clone())
+                    .map(move |flag| (problem, flag)))
+            }
+        })
+        .flatten()
+        .and_then(move |(name, (problem, expected))| {
+            if constant_time::verify_slices_are_equal(expected.as_bytes(), flag.as_bytes()).is_ok()
+            {
+                Either::A(
+                    db.record_solve(user, problem)
+                        .map(move |solve| match solve {
+                            Some((team, first_blood)) => {
+                                if first_blood {
+                                    events.publish(Event::FirstBlood {
+                                        problem: name.clone(),
+                                        team: team.clone(),
+                                    });
+                                }
+                                events.publish(Event::ProblemSolved {
+                                    problem: name,
+                                    team,
+                                });
+                                events.publish(Event::ScoreboardChanged);
+                                true
+                            }
+                            None => false,
+                        }),
+                )
+            } else {
+                Either::B(
+                    db.record_wrong_flag(user)
+                        .and_then(|()| Err(user_err!(BadRequest, "That flag is wrong."))),
+                )
+            }
+        })
+}
+
+/// Lists a team's outstanding invites.
+pub fn team_invites(db: DB, team: Uuid) -> impl Future<Item = Vec<Invite>, Error = Error> {
+    db.get_team_invites(team)
+}
+
+/// Makes another member of the team captained by `captain` the captain.
+pub fn transfer_captaincy(
+    db: DB,
+    captain: i32,
+    member: String,
+) -> impl Future<Item = (), Error = Error> {
+    db.transfer_captaincy(captain, member)
+}
+
+/// Updates the public profile of the team captained by `captain`.
+pub fn update_team_profile(
+    db: DB,
+    captain: i32,
+    profile: TeamProfile,
+) -> impl Future<Item = (), Error = Error> {
+    let profile = TeamProfile {
+        affiliation: profile.affiliation.trim().to_string(),
+        country: profile.country.trim().to_string(),
+        website: profile.website.trim().to_string(),
+        bio: profile.bio.trim().to_string(),
+    };
+    db.update_team_profile(captain, profile)
+}
+
+/// Mails each of the given addresses. Failures to queue the mail are logged rather than returned,
+/// since the mail is only a notification of something that's already been done.
+fn notify(
+    mailer: Mailer,
+    to: Vec<String>,
+    subject: String,
+    text: String,
+) -> impl Future<Item = (), Error = Error> {
+    join_all(to.into_iter().map(move |to| {
+        mailer.send(&to, &subject, &text).or_else(move |e| {
+            warn!("Couldn't mail {}: {}", to, e);
+            Ok::<_, Error>(())
+        })
+    }))
+    .map(|_: Vec<()>| ())
 }
 
-/// Joins a team.
-pub fn join_team(db: DB, user: i32, team: Uuid) -> impl Future<Item = (), Error = Error> {
-    db.join_team(user, team).map(|_| ())
+/// Mails the mods about a new ticket or a team's reply to one.
+fn notify_mods(
+    db: DB,
+    mailer: Mailer,
+    ticket: Ticket,
+    team: String,
+    body: String,
+    opened: bool,
+) -> impl Future<Item = (), Error = Error> {
+    let mail_subject = format!("[Ticket #{}] {}", ticket.id, ticket.subject);
+    db.get_admin_emails()
+        .and_then(move |to| {
+            let vars = json!({
+                "body": body,
+                "opened": opened,
+                "problem": ticket.problem,
+                "subject": ticket.subject,
+                "team": team,
+                "ticket": ticket.id
+            });
+            render("ticket-mod-mail.txt", vars).map(|text| (to, text))
+        })
+        .and_then(move |(to, text)| notify(mailer, to, mail_subject, text))
 }
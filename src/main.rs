@@ -1,16 +1,22 @@
-use failure::{bail, Fallible};
+use chrono::Duration;
+use failure::{bail, Error, Fallible};
+use futures::{Future, Stream};
 use log::warn;
 use nihctfplat::{
-    dal::{Mailer, DB},
+    dal::{Mailer, Oidc, DB},
+    logic::{auth::clean_up, deliver_mail},
     router::serve_on,
+    schema::{Config, JwtKey, OidcConfig},
     util::log_err,
 };
+use reqwest::Url;
 use std::{
-    net::{SocketAddr, ToSocketAddrs},
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
     process::exit,
+    time::{Duration as StdDuration, Instant},
 };
 use structopt::StructOpt;
-use tokio::runtime::Builder;
+use tokio::{runtime::Builder, timer::Interval};
 
 fn main() {
     dotenv::dotenv().ok();
@@ -28,6 +34,7 @@ fn main() {
 
 fn run(options: Options) -> Fallible<()> {
     let serve_addr = options.serve_addr()?;
+    let config = options.config()?;
     let mut runtime = Builder::new().build()?;
     let db = DB::connect(&options.database_url)?;
     let smtp_from = options
@@ -36,13 +43,46 @@ fn run(options: Options) -> Fallible<()> {
         .unwrap_or(&options.smtp_user)
         .clone();
     let mailer = Mailer::connect(
+        db.clone(),
         &options.smtp_host,
         !options.smtp_insecure,
         options.smtp_user,
         options.smtp_pass,
         smtp_from,
     )?;
-    runtime.block_on(serve_on(serve_addr, db, mailer))
+
+    let cleanup_db = db.clone();
+    runtime.spawn(
+        Interval::new(Instant::now(), StdDuration::from_secs(60 * 60))
+            .map_err(Error::from)
+            .for_each(move |_| {
+                clean_up(cleanup_db.clone()).or_else(|err| {
+                    log_err(&err);
+                    Ok(())
+                })
+            })
+            .map_err(|err| log_err(&err)),
+    );
+
+    let outbox_db = db.clone();
+    let outbox_mailer = mailer.clone();
+    runtime.spawn(
+        Interval::new(Instant::now(), StdDuration::from_secs(5))
+            .map_err(Error::from)
+            .for_each(move |_| {
+                deliver_mail(outbox_db.clone(), outbox_mailer.clone()).or_else(|err| {
+                    log_err(&err);
+                    Ok(())
+                })
+            })
+            .map_err(|err| log_err(&err)),
+    );
+
+    let oidc = match config.oidc.clone() {
+        Some(oidc) => Some(runtime.block_on(Oidc::discover(oidc))?),
+        None => None,
+    };
+    runtime.block_on(serve_on(serve_addr, db, mailer, oidc, config))
 }
 
 #[derive(Debug, StructOpt)]
@@ -70,6 +110,118 @@ pub struct Options {
     #[structopt(short = "P", long = "port", env = "PORT", default_value = "8080")]
     port: u16,
 
+    /// The maximum number of members a team may have.
+    #[structopt(long = "max-team-size", env = "MAX_TEAM_SIZE", default_value = "4")]
+    pub max_team_size: i64,
+
+    /// How many minutes an access token is trusted for before the session is checked again.
+    #[structopt(
+        long = "access-token-minutes",
+        env = "ACCESS_TOKEN_MINUTES",
+        default_value = "5"
+    )]
+    pub access_token_minutes: i64,
+
+    /// Keys to sign access tokens with, as `ID=SECRET`. New tokens are signed with the first key;
+    /// tokens signed with any of them are accepted. If none are given, every request checks the
+    /// session in the database instead.
+    #[structopt(
+        long = "jwt-key",
+        env = "JWT_KEYS",
+        raw(use_delimiter = "true", number_of_values = "1")
+    )]
+    pub jwt_keys: Vec<String>,
+
+    /// Lets the authentication cookie be sent over plain HTTP. Only use this for local
+    /// development.
+    #[structopt(long = "insecure-cookies")]
+    pub insecure_cookies: bool,
+
+    /// How many minutes login links and codes work for.
+    #[structopt(
+        long = "login-link-minutes",
+        env = "LOGIN_LINK_MINUTES",
+        default_value = "60"
+    )]
+    pub login_link_minutes: i64,
+
+    /// How many seconds a user must wait after being sent a login mail before another can be
+    /// sent.
+    #[structopt(
+        long = "login-mail-cooldown-seconds",
+        env = "LOGIN_MAIL_COOLDOWN_SECONDS",
+        default_value = "60"
+    )]
+    pub login_mail_cooldown_seconds: i64,
+
+    /// How many login mails can be requested from one IP address per hour.
+    #[structopt(
+        long = "login-mails-per-ip",
+        env = "LOGIN_MAILS_PER_IP",
+        default_value = "20"
+    )]
+    pub login_mails_per_ip: i64,
+
+    /// How many login mails a user can be sent per hour.
+    #[structopt(
+        long = "login-mails-per-user",
+        env = "LOGIN_MAILS_PER_USER",
+        default_value = "5"
+    )]
+    pub login_mails_per_user: i64,
+
+    /// How many days a user stays logged in for.
+    #[structopt(
+        long = "session-lifetime-days",
+        env = "SESSION_LIFETIME_DAYS",
+        default_value = "14"
+    )]
+    pub session_lifetime_days: i64,
+
+    /// The issuer URL of an OpenID Connect provider to let users log in with, such as a
+    /// university's single sign-on.
+    #[structopt(long = "oidc-issuer", env = "OIDC_ISSUER")]
+    pub oidc_issuer: Option<String>,
+
+    /// The client ID registered with the OpenID Connect provider.
+    #[structopt(long = "oidc-client-id", env = "OIDC_CLIENT_ID")]
+    pub oidc_client_id: Option<String>,
+
+    /// The client secret registered with the OpenID Connect provider.
+    #[structopt(long = "oidc-client-secret", env = "OIDC_CLIENT_SECRET")]
+    pub oidc_client_secret: Option<String>,
+
+    /// The URL the OpenID Connect provider sends users back to, which must end in
+    /// `/login/oidc/callback`.
+    #[structopt(long = "oidc-redirect-url", env = "OIDC_REDIRECT_URL")]
+    pub oidc_redirect_url: Option<String>,
+
+    /// The name of the OpenID Connect provider, as shown on the login page.
+    #[structopt(long = "oidc-name", env = "OIDC_NAME", default_value = "SSO")]
+    pub oidc_name: String,
+
+    /// Lets users play without creating or joining a team, by giving them a personal team when
+    /// they log in.
+    #[structopt(long = "solo")]
+    pub solo: bool,
+
+    /// The addresses of reverse proxies in front of the server. The `X-Forwarded-For` header is
+    /// only trusted on requests coming from one of them.
+    #[structopt(
+        long = "trusted-proxy",
+        env = "TRUSTED_PROXIES",
+        raw(use_delimiter = "true", number_of_values = "1")
+    )]
+    pub trusted_proxies: Vec<IpAddr>,
+
+    /// The origin users reach the site at, which passkeys are tied to.
+    #[structopt(
+        long = "webauthn-origin",
+        env = "WEBAUTHN_ORIGIN",
+        default_value = "https://ctf.acm.umn.edu"
+    )]
+    pub webauthn_origin: String,
+
     /// The SMTP server's hostname.
     #[structopt(long = "smtp-host", env = "SMTP_HOST")]
     pub smtp_host: String,
@@ -97,6 +249,85 @@ pub struct Options {
 }
 
 impl Options {
+    /// Get the settings for the event.
+    pub fn config(&self) -> Fallible<Config> {
+        if self.max_team_size < 1 {
+            bail!("The maximum team size must be at least 1");
+        }
+        if self.session_lifetime_days < 1 {
+            bail!("The session lifetime must be at least 1 day");
+        }
+        if self.login_link_minutes < 1 {
+            bail!("The login link lifetime must be at least 1 minute");
+        }
+        if self.access_token_minutes < 1 {
+            bail!("The access token lifetime must be at least 1 minute");
+        }
+        if self.login_mail_cooldown_seconds < 0 {
+            bail!("The login mail cooldown can't be negative");
+        }
+        if self.login_mails_per_ip < 1 || self.login_mails_per_user < 1 {
+            bail!("At least 1 login mail must be allowed per hour");
+        }
+        let jwt_keys = self
+            .jwt_keys
+            .iter()
+            .map(|key| {
+                let mut parts = key.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(id), Some(secret)) if !id.is_empty() && secret.len() >= 32 => {
+                        Ok(JwtKey {
+                            id: id.to_string(),
+                            secret: secret.as_bytes().to_vec(),
+                        })
+                    }
+                    _ => bail!(
+                        "JWT keys must be of the form ID=SECRET, with at least 32 bytes of secret"
+                    ),
+                }
+            })
+            .collect::<Fallible<_>>()?;
+        let oidc = match self.oidc_issuer {
+            Some(ref issuer) => match (
+                &self.oidc_client_id,
+                &self.oidc_client_secret,
+                &self.oidc_redirect_url,
+            ) {
+                (Some(client_id), Some(client_secret), Some(redirect_url)) => Some(OidcConfig {
+                    issuer: issuer.clone(),
+                    client_id: client_id.clone(),
+                    client_secret: client_secret.clone(),
+                    redirect_url: redirect_url.clone(),
+                    name: self.oidc_name.clone(),
+                }),
+                _ => bail!(
+                    "An OpenID Connect provider needs a client ID, client secret, and redirect URL"
+                ),
+            },
+            None => None,
+        };
+        let webauthn_rp_id = match Url::parse(&self.webauthn_origin)?.host_str() {
+            Some(host) => host.to_string(),
+            None => bail!("The WebAuthn origin must have a host"),
+        };
+        Ok(Config {
+            access_token_lifetime: Duration::minutes(self.access_token_minutes),
+            jwt_keys,
+            login_link_lifetime: Duration::minutes(self.login_link_minutes),
+            login_mail_cooldown: Duration::seconds(self.login_mail_cooldown_seconds),
+            login_mails_per_ip: self.login_mails_per_ip,
+            login_mails_per_user: self.login_mails_per_user,
+            max_team_size: self.max_team_size,
+            oidc,
+            secure_cookies: !self.insecure_cookies,
+            session_lifetime: Duration::days(self.session_lifetime_days),
+            solo: self.solo,
+            trusted_proxies: self.trusted_proxies.clone(),
+            webauthn_origin: self.webauthn_origin.trim_end_matches('/').to_string(),
+            webauthn_rp_id,
+        })
+    }
+
     /// Get the address to serve on.
     pub fn serve_addr(&self) -> Fallible<SocketAddr> {
         let addrs = (&self.host as &str, self.port)
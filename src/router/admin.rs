@@ -0,0 +1,553 @@
+use crate::{
+    dal::{Events, Mailer, DB},
+    logic::{self, auth::totp::StepUp},
+    router::{
+        auth::{self, CsrfToken},
+        tickets::{message_json, ticket_json},
+        util::{parse_optional_id, redirect, FilterExt, FutureExt},
+    },
+    schema::{Config, ProblemDraft, User},
+    view::render_html,
+};
+use futures::{
+    future::{err, result, Either},
+    Future,
+};
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+use uuid::Uuid;
+use warp::{path, reject::custom, Filter, Rejection};
+
+/// The error for an admin whose session hasn't been stepped up with a second factor recently.
+#[derive(Debug)]
+pub struct StepUpRequired {
+    /// The CSRF token of the admin's session.
+    pub csrf: Option<CsrfToken>,
+
+    /// Whether the admin has set up two-factor authentication at all.
+    pub enrolled: bool,
+
+    /// The admin.
+    pub me: User,
+}
+
+impl Display for StepUpRequired {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.write_str("Two-factor authentication required")
+    }
+}
+
+impl StdError for StepUpRequired {}
+
+/// A filter that only lets admins through, extracting them. Admins must have set up two-factor
+/// authentication, and entered a code in their current session recently. The `parse_auth_cookie`
+/// filter must have already been run.
+pub fn admin() -> impl Clone + Filter<Extract = (User,), Error = Rejection> {
+    warp::ext::get::<User>()
+        .and(warp::cookie("auth"))
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and_then(|user: User, token: String, csrf, db| {
+            if !user.admin {
+                return Either::B(err(warp::reject::not_found()));
+            }
+            Either::A(
+                logic::auth::totp::get_step_up(db, user.id, &token)
+                    .err_to_rejection()
+                    .and_then(move |step_up| match step_up {
+                        StepUp::Done => Ok(user),
+                        step_up => Err(custom(StepUpRequired {
+                            csrf,
+                            enrolled: step_up == StepUp::Required,
+                            me: user,
+                        })),
+                    }),
+            )
+        })
+}
+
+/// The admin dashboard.
+pub fn page() -> Resp!() {
+    warp::path::end()
+        .and(admin())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, csrf, db: DB| {
+            logic::admin::teams(db.clone())
+                .join(logic::list_announcements(db.clone()))
+                .join(logic::admin::problems(db))
+                .err_to_rejection()
+                .and_then(move |(((teams, divisions), announcements), problems)| {
+                    let teams = teams
+                        .into_iter()
+                        .map(|(team, members)| json!({ "team": team, "members": members }))
+                        .collect::<Vec<_>>();
+                    // The flag isn't serialized with the problem, since players mustn't see it.
+                    let problems = problems
+                        .into_iter()
+                        .map(|(problem, attachments)| {
+                            let attachments = attachments
+                                .iter()
+                                .map(|file| format!("{} {}", file.name, file.url))
+                                .collect::<Vec<_>>()
+                                .join("\n");
+                            json!({
+                                "attachments": attachments,
+                                "flag": problem.flag,
+                                "problem": problem
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    let data = json!({
+                        "announcements": announcements,
+                        "csrf": csrf,
+                        "divisions": divisions,
+                        "me": me.for_page(),
+                        "problems": problems,
+                        "teams": teams
+                    });
+                    render_html("admin.html", data)
+                })
+        })
+        .boxed()
+}
+
+/// The mail that hasn't been delivered yet, with why, so failures can be retried.
+pub fn mail_page() -> Resp!() {
+    warp::path::end()
+        .and(admin())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, csrf, db| {
+            logic::admin::undelivered_mail(db)
+                .err_to_rejection()
+                .and_then(move |mail| {
+                    let mail = mail
+                        .into_iter()
+                        .map(|mail| {
+                            json!({
+                                "attempts": mail.attempts,
+                                "created": mail.created.to_rfc2822(),
+                                "failed": mail.failed,
+                                "id": mail.id,
+                                "last_error": mail.last_error,
+                                "next_attempt": mail.next_attempt.to_rfc2822(),
+                                "recipient": mail.recipient,
+                                "subject": mail.subject
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    let data = json!({ "csrf": csrf, "mail": mail, "me": me.for_page() });
+                    render_html("admin-mail.html", data)
+                })
+        })
+        .boxed()
+}
+
+/// The queue of tickets, open ones first.
+pub fn tickets_page() -> Resp!() {
+    warp::path::end()
+        .and(admin())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, csrf, db| {
+            logic::admin::tickets(db)
+                .err_to_rejection()
+                .and_then(move |tickets| {
+                    let tickets = tickets
+                        .iter()
+                        .map(
+                            |(ticket, team)| json!({ "team": team, "ticket": ticket_json(ticket) }),
+                        )
+                        .collect::<Vec<_>>();
+                    let data = json!({ "csrf": csrf, "me": me.for_page(), "tickets": tickets });
+                    render_html("admin-tickets.html", data)
+                })
+        })
+        .boxed()
+}
+
+/// The page for a ticket, where the mods reply to it.
+pub fn ticket_page() -> Resp!() {
+    path!(i32)
+        .and(warp::path::end())
+        .and(admin())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and_then(|ticket, me: User, csrf, db| {
+            logic::admin::get_ticket(db, ticket)
+                .err_to_rejection()
+                .and_then(move |(ticket, team, messages)| {
+                    let data = json!({
+                        "as_mod": true,
+                        "csrf": csrf,
+                        "me": me.for_page(),
+                        "messages": messages.iter().map(message_json).collect::<Vec<_>>(),
+                        "team": team,
+                        "ticket": ticket_json(&ticket)
+                    });
+                    render_html("ticket.html", data)
+                })
+        })
+        .boxed()
+}
+
+/// The route for replying to a ticket.
+pub fn answer_ticket() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        ticket: i32,
+        body: String,
+        public: Option<String>,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(8 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::ext::get::<Mailer>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, events, mailer, form: Form| {
+            let location = format!("/admin/tickets/{}", form.ticket);
+            let public = form.public.is_some();
+            logic::admin::answer_ticket(
+                db,
+                events,
+                mailer,
+                admin.id,
+                form.ticket,
+                form.body,
+                public,
+            )
+            .and_then(move |()| redirect(&location))
+            .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for closing a ticket.
+pub fn close_ticket() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        ticket: i32,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::ext::get::<Mailer>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, events, mailer, form: Form| {
+            logic::admin::close_ticket(db, events, mailer, admin.id, form.ticket)
+                .and_then(|()| redirect("/admin/tickets"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for creating a division.
+pub fn create_division() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        name: String,
+        email_domains: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, form: Form| {
+            logic::admin::create_division(db, admin.id, form.name, &form.email_domains)
+                .and_then(|()| redirect("/admin"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for creating a problem.
+pub fn create_problem() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        name: String,
+        category: String,
+        description: String,
+        points: i32,
+        flag: String,
+        visible: Option<String>,
+        attachments: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, events, form: Form| {
+            result(logic::admin::parse_attachments(&form.attachments))
+                .and_then(move |attachments| {
+                    let draft = ProblemDraft {
+                        name: form.name,
+                        category: form.category,
+                        description: form.description,
+                        points: form.points,
+                        flag: form.flag,
+                        visible: form.visible.is_some(),
+                        attachments,
+                    };
+                    logic::admin::create_problem(db, events, admin.id, draft)
+                })
+                .and_then(|()| redirect("/admin"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for deleting a division.
+pub fn delete_division() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        division: i32,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, events, form: Form| {
+            logic::admin::delete_division(db, events, admin.id, form.division)
+                .and_then(|()| redirect("/admin"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for disbanding an empty team.
+pub fn disband_team() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        team: Uuid,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, events, form: Form| {
+            logic::admin::disband_team(db, events, admin.id, form.team)
+                .and_then(|()| redirect("/admin"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for editing an announcement.
+pub fn edit_announcement() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        announcement: i32,
+        title: String,
+        body: String,
+        problem: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(8 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, events, form: Form| {
+            result(parse_optional_id(&form.problem))
+                .and_then(move |problem| {
+                    logic::admin::edit_announcement(
+                        db,
+                        events,
+                        admin.id,
+                        form.announcement,
+                        form.title,
+                        form.body,
+                        problem,
+                    )
+                })
+                .and_then(|()| redirect("/admin"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for editing a problem.
+pub fn edit_problem() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        problem: i32,
+        name: String,
+        category: String,
+        description: String,
+        points: i32,
+        flag: String,
+        visible: Option<String>,
+        attachments: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, events, form: Form| {
+            result(logic::admin::parse_attachments(&form.attachments))
+                .and_then(move |attachments| {
+                    let draft = ProblemDraft {
+                        name: form.name,
+                        category: form.category,
+                        description: form.description,
+                        points: form.points,
+                        flag: form.flag,
+                        visible: form.visible.is_some(),
+                        attachments,
+                    };
+                    logic::admin::edit_problem(db, events, admin.id, form.problem, draft)
+                })
+                .and_then(|()| redirect("/admin"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for merging two teams.
+pub fn merge_teams() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        from: Uuid,
+        into: Uuid,
+        override_note: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, config: Config, db: DB, events, form: Form| {
+            logic::admin::merge_teams(
+                db,
+                events,
+                &config,
+                admin.id,
+                form.from,
+                form.into,
+                form.override_note,
+            )
+            .and_then(|()| redirect("/admin"))
+            .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for posting an announcement.
+pub fn post_announcement() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        title: String,
+        body: String,
+        problem: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(8 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, events, form: Form| {
+            result(parse_optional_id(&form.problem))
+                .and_then(move |problem| {
+                    logic::admin::post_announcement(
+                        db, events, admin.id, form.title, form.body, problem,
+                    )
+                })
+                .and_then(|()| redirect("/admin"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for removing a user from their team.
+pub fn remove_team_member() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        member: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, events, form: Form| {
+            logic::admin::remove_team_member(db, events, admin.id, form.member)
+                .and_then(|()| redirect("/admin"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for renaming a team.
+pub fn rename_team() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        team: Uuid,
+        name: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, events, form: Form| {
+            logic::admin::rename_team(db, events, admin.id, form.team, form.name)
+                .and_then(|()| redirect("/admin"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
+
+/// The route for retrying an undelivered mail.
+pub fn retry_mail() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        mail: i32,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(admin())
+        .and(warp::ext::get::<DB>())
+        .and(auth::csrf_form())
+        .and_then(|admin: User, db: DB, form: Form| {
+            logic::admin::retry_mail(db, admin.id, form.mail)
+                .and_then(|()| redirect("/admin/mail"))
+                .err_to_rejection()
+        })
+        .recover_with_template("admin-error.html")
+}
@@ -0,0 +1,56 @@
+//! The announcements the admins post during the competition.
+
+use crate::{
+    dal::DB,
+    logic,
+    router::{
+        auth,
+        util::{format, FutureExt},
+    },
+    schema::User,
+    view::render_as,
+};
+use futures::Future;
+use serde_json::json;
+use warp::Filter;
+
+/// The list of announcements. The latest announcement's ID is put on the page, so the banner for
+/// unseen announcements can be hidden once it's been seen.
+pub fn page() -> Resp!() {
+    warp::path::end()
+        .and(auth::opt_auth())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|me: Option<User>, csrf, db, format| {
+            logic::list_announcements(db)
+                .err_to_rejection()
+                .and_then(move |announcements| {
+                    let latest = announcements.first().map(|a| a.id);
+                    let announcements = announcements
+                        .into_iter()
+                        .map(|announcement| {
+                            json!({
+                                "body": announcement.body,
+                                "created": announcement.created.to_rfc2822(),
+                                "edited": announcement.edited.map(|at| at.to_rfc2822()),
+                                "id": announcement.id,
+                                "problem": announcement.problem,
+                                "title": announcement.title
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    render_as(
+                        format,
+                        "announcements.html",
+                        json!({
+                            "announcements": announcements,
+                            "csrf": csrf,
+                            "latest": latest,
+                            "me": me.as_ref().map(User::for_page)
+                        }),
+                    )
+                })
+        })
+        .boxed()
+}
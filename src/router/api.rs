@@ -0,0 +1,829 @@
+//! The JSON API under `/api/v1`, for players' scripts.
+//!
+//! Requests are authenticated with an API token from the settings page, sent as
+//! `Authorization: Bearer <token>`. The authentication cookie isn't accepted here, so API requests
+//! don't need CSRF tokens. Request bodies are JSON, and errors are returned as
+//! `{"error": "..."}` with an appropriate status, rather than as rendered pages. The API is
+//! described by the OpenAPI document at `/api/openapi.json`.
+
+use crate::{
+    dal::{Events, Mailer, DB},
+    logic,
+    router::{
+        auth::client_info,
+        util::{error_status, FutureExt},
+    },
+    schema::{ClientInfo, Config, TeamProfile, User},
+    view::render_json,
+};
+use chrono::Duration;
+use failure::{Compat, Error};
+use futures::{
+    future::{err, Either},
+    Future,
+};
+use log::error;
+use serde_derive::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+use warp::{
+    http::{header::CONTENT_TYPE, Response, StatusCode},
+    path,
+    reject::custom,
+    Filter, Rejection,
+};
+
+/// The OpenAPI document describing the API.
+const OPENAPI: &str = include_str!("openapi.json");
+
+/// The routes of the API.
+pub fn routes() -> Resp!() {
+    path!("api" / "openapi.json")
+        .and(warp::path::end())
+        .and(warp::get2())
+        .map(|| warp::reply::with_header(OPENAPI, CONTENT_TYPE, "application/json"))
+        .or(path!("api" / "v1").and(
+            route_any! {
+                GET("announcements") => announcements(),
+                GET("divisions") => divisions(),
+                GET("divisions") => division(),
+                GET("me") => me(),
+                GET("problems") => problems(),
+                GET("problems") => problem(),
+                POST("problems") => submit_flag(),
+                POST("register") => register(),
+                GET("scoreboard") => scoreboard(),
+                GET("scoreboard") => division_scoreboard(),
+                GET("settings" / "email") => pending_email_change(),
+                POST("settings" / "email") => change_email(),
+                POST("settings" / "email" / "cancel") => cancel_email_change(),
+                GET("team") => team(),
+                GET("team" / "invites") => team_invites(),
+                GET("team" / "solves") => team_solves(),
+                POST("team") => create_team(),
+                POST("team" / "captain") => transfer_captaincy(),
+                POST("team" / "division") => set_team_division(),
+                POST("team" / "invite") => invite(),
+                POST("team" / "invite" / "revoke") => revoke_invite(),
+                POST("team" / "join") => join_team(),
+                POST("team" / "join-code") => regenerate_join_code(),
+                POST("team" / "kick") => kick_team_member(),
+                POST("team" / "leave") => leave_team(),
+                POST("team" / "profile") => update_team_profile(),
+                GET("teams") => teams(),
+                GET("teams") => public_team(),
+                GET("tickets") => tickets(),
+                GET("tickets") => ticket(),
+                POST("tickets") => open_ticket(),
+                POST("tickets") => reply_to_ticket(),
+            }
+            .recover(error),
+        ))
+        .boxed()
+}
+
+/// Lists every announcement, newest first.
+fn announcements() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|_: User, db| {
+            logic::list_announcements(db)
+                .err_to_rejection()
+                .and_then(render_json)
+        })
+        .boxed()
+}
+
+/// A filter that authenticates the user with the API token in the `Authorization` header.
+fn bearer() -> impl Clone + Filter<Extract = (User,), Error = Rejection> {
+    warp::header::optional::<String>("authorization")
+        .and(warp::ext::get::<DB>())
+        .and_then(|header: Option<String>, db| {
+            let token = header.as_ref().and_then(|header| {
+                if header.starts_with("Bearer ") {
+                    Some(header["Bearer ".len()..].trim().to_string())
+                } else {
+                    None
+                }
+            });
+            match token {
+                Some(token) => {
+                    Either::A(logic::auth::authed_api_user(db, &token).err_to_rejection())
+                }
+                None => Either::B(err(custom(
+                    user_err!(Unauthorized, "An API token is required.").compat(),
+                ))),
+            }
+        })
+}
+
+/// Cancels the user's pending email change, if they have one.
+fn cancel_email_change() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, db| {
+            logic::auth::cancel_email_change(db, me.id)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Starts changing the user's email address, mailing a confirmation link to the new address.
+fn change_email() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        email: String,
+    }
+
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Mailer>())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, db, mailer, body: Body| {
+            logic::auth::request_email_change(db, mailer, me.id, body.email)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Creates a team, with the user as its captain.
+fn create_team() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        name: String,
+    }
+
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, db, events, body: Body| {
+            logic::create_team(db, events, me.id, body.name)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Gets a division and the teams in it.
+fn division() -> Resp!() {
+    path!(String)
+        .and(warp::path::end())
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|name, _: User, db| {
+            logic::get_division_teams(db, name)
+                .err_to_rejection()
+                .and_then(|(division, teams)| {
+                    render_json(json!({ "division": division, "teams": teams }))
+                })
+        })
+        .boxed()
+}
+
+/// Gets the scoreboard of the teams in a division.
+fn division_scoreboard() -> Resp!() {
+    path!(String)
+        .and(warp::path::end())
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|name, _: User, db| {
+            logic::get_scoreboard(db, Some(name))
+                .err_to_rejection()
+                .and_then(|(division, standings)| {
+                    render_json(json!({ "division": division, "standings": standings }))
+                })
+        })
+        .boxed()
+}
+
+/// Lists every division.
+fn divisions() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|_: User, db| {
+            logic::list_divisions(db)
+                .err_to_rejection()
+                .and_then(render_json)
+        })
+        .boxed()
+}
+
+/// Invites an email address to the user's team, mailing it a link to join. Only the captain can do
+/// this.
+fn invite() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        email: String,
+    }
+
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Mailer>())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, db, mailer, body: Body| {
+            logic::invite_to_team(db, mailer, me.id, body.email)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Joins a team with its join code.
+fn join_team() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        join_code: String,
+    }
+
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, config: Config, db, events, body: Body| {
+            logic::join_team(db, events, &config, me.id, body.join_code)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Removes a member from the user's team. Only the captain can do this.
+fn kick_team_member() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        member: String,
+    }
+
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, db, events, body: Body| {
+            logic::kick_team_member(db, events, me.id, body.member)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Leaves the user's team.
+fn leave_team() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and_then(|me: User, config: Config, db, events| {
+            logic::leave_team(db, events, &config, me.id)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Gets the user the API token belongs to.
+fn me() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and_then(render_json)
+        .boxed()
+}
+
+/// Opens a ticket for the user's team, returning its ID.
+fn open_ticket() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        subject: String,
+        body: String,
+        problem: Option<i32>,
+    }
+
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::ext::get::<Mailer>())
+        .and(warp::body::content_length_limit(8 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, db, events, mailer, body: Body| {
+            logic::open_ticket(
+                db,
+                events,
+                mailer,
+                me.id,
+                body.subject,
+                body.body,
+                body.problem,
+            )
+            .err_to_rejection()
+            .and_then(|ticket| render_json(json!({ "id": ticket })))
+        })
+        .boxed()
+}
+
+/// Gets the address the user is changing their email address to, if they've asked to.
+fn pending_email_change() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, db| {
+            logic::auth::get_pending_email_change(db, me.id)
+                .err_to_rejection()
+                .and_then(|pending| {
+                    let pending = pending
+                        .map(|(email, expires)| json!({ "email": email, "expires": expires }));
+                    render_json(pending)
+                })
+        })
+        .boxed()
+}
+
+/// Gets a problem, as shown to the user's team.
+fn problem() -> Resp!() {
+    path!(String)
+        .and(warp::path::end())
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|name, me: User, db| {
+            logic::get_problem(db, name, me.team)
+                .err_to_rejection()
+                .and_then(render_json)
+        })
+        .boxed()
+}
+
+/// Lists the problems, as shown to the user's team.
+fn problems() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, db| {
+            logic::list_problems(db, me.team)
+                .err_to_rejection()
+                .and_then(render_json)
+        })
+        .boxed()
+}
+
+/// Gets a team's public profile, its members, its division, and its solves.
+fn public_team() -> Resp!() {
+    path!(String)
+        .and(warp::path::end())
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|name, _: User, db| {
+            logic::get_public_team(db, name)
+                .err_to_rejection()
+                .and_then(|(team, members, division, solves)| {
+                    render_json(json!({
+                        "division": division,
+                        "members": members,
+                        "solves": solves,
+                        "team": team
+                    }))
+                })
+        })
+        .boxed()
+}
+
+/// Replaces the join code of the user's team, optionally making the new code expire after a number
+/// of hours or uses, and returns it. Only the captain can do this.
+fn regenerate_join_code() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        #[serde(default)]
+        expires_hours: Option<u16>,
+        #[serde(default)]
+        uses: Option<u16>,
+    }
+
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, db, body: Body| {
+            let expires_in = body
+                .expires_hours
+                .map(|hours| Duration::hours(hours.into()));
+            let uses = body.uses.map(i32::from);
+            logic::regenerate_join_code(db, me.id, expires_in, uses)
+                .err_to_rejection()
+                .and_then(|code| render_json(json!({ "join_code": code })))
+        })
+        .boxed()
+}
+
+/// Registers a user, mailing them a login link. This is the only route that doesn't take an API
+/// token, since there can't be one yet.
+fn register() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        email: String,
+        username: String,
+    }
+
+    warp::path::end()
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Mailer>())
+        .and(warp::ext::get::<Config>())
+        .and(client_info())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(
+            |db, mailer, config: Config, client: ClientInfo, body: Body| {
+                logic::auth::register(db, mailer, &config, body.username, body.email, client)
+                    .map(|()| no_content())
+                    .err_to_rejection()
+            },
+        )
+        .boxed()
+}
+
+/// Replies to one of the user's team's tickets, reopening it if it was closed.
+fn reply_to_ticket() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        body: String,
+    }
+
+    path!(i32 / "reply")
+        .and(warp::path::end())
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::ext::get::<Mailer>())
+        .and(warp::body::content_length_limit(8 * 1024))
+        .and(warp::body::json())
+        .and_then(|ticket, me: User, db, events, mailer, body: Body| {
+            logic::reply_to_ticket(db, events, mailer, me.id, ticket, body.body)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Revokes an invite to the user's team. Only the captain can do this.
+fn revoke_invite() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        invite: Uuid,
+    }
+
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, db, body: Body| {
+            logic::revoke_invite(db, me.id, body.invite)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Gets the scoreboard of every team.
+fn scoreboard() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|_: User, db| {
+            logic::get_scoreboard(db, None)
+                .err_to_rejection()
+                .and_then(|(division, standings)| {
+                    render_json(json!({ "division": division, "standings": standings }))
+                })
+        })
+        .boxed()
+}
+
+/// Sets the division of the user's team, or takes it out of its division if `null` is given.
+fn set_team_division() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        division: Option<i32>,
+    }
+
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, db, events, body: Body| {
+            logic::set_team_division(db, events, me.id, body.division)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Submits a flag for a problem for the user's team. A wrong flag is an error, and a right one
+/// says whether the team had already solved the problem.
+fn submit_flag() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        flag: String,
+    }
+
+    path!(String / "flag")
+        .and(warp::path::end())
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(|name, me: User, db, events, body: Body| {
+            logic::submit_flag(db, events, me.id, name, body.flag)
+                .err_to_rejection()
+                .and_then(|new| render_json(json!({ "already_solved": !new })))
+        })
+        .boxed()
+}
+
+/// Gets the user's team and its members.
+fn team() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, db| match me.team {
+            Some(team) => Either::A(logic::get_team(db, team).err_to_rejection().and_then(
+                |(team, members)| {
+                    let join_code = json!({
+                        "code": team.join_code,
+                        "expires": team.join_code_expires,
+                        "uses": team.join_code_uses,
+                    });
+                    render_json(json!({ "team": team, "members": members, "join_code": join_code }))
+                },
+            )),
+            None => Either::B(err(custom(
+                user_err!(NotFound, "You aren't on a team.").compat(),
+            ))),
+        })
+        .boxed()
+}
+
+/// Lists the outstanding invites to the user's team. Only the captain can see them.
+fn team_invites() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, db: DB| match me.team {
+            Some(team) => Either::A(
+                logic::get_team(db.clone(), team)
+                    .and_then(move |(team, _)| {
+                        if team.captain == Some(me.id) {
+                            Ok(logic::team_invites(db, team.id))
+                        } else {
+                            Err(user_err!(Forbidden, "Only the team captain can do that."))
+                        }
+                    })
+                    .flatten()
+                    .err_to_rejection()
+                    .and_then(render_json),
+            ),
+            None => Either::B(err(custom(
+                user_err!(NotFound, "You aren't on a team.").compat(),
+            ))),
+        })
+        .boxed()
+}
+
+/// Lists the problems the user's team has solved, in the order it solved them.
+fn team_solves() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, db| match me.team {
+            Some(team) => Either::A(
+                logic::list_team_solves(db, team)
+                    .err_to_rejection()
+                    .and_then(render_json),
+            ),
+            None => Either::B(err(custom(
+                user_err!(NotFound, "You aren't on a team.").compat(),
+            ))),
+        })
+        .boxed()
+}
+
+/// Lists every team, along with its members' names.
+fn teams() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|_: User, db| {
+            logic::list_teams(db).err_to_rejection().and_then(|teams| {
+                let teams = teams
+                    .into_iter()
+                    .map(|(team, members)| json!({ "team": team, "members": members }))
+                    .collect::<Vec<_>>();
+                render_json(teams)
+            })
+        })
+        .boxed()
+}
+
+/// Gets one of the user's team's tickets and its messages.
+fn ticket() -> Resp!() {
+    path!(i32)
+        .and(warp::path::end())
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|ticket, me: User, db| match me.team {
+            Some(team) => Either::A(
+                logic::get_team_ticket(db, team, ticket)
+                    .err_to_rejection()
+                    .and_then(|(ticket, messages)| {
+                        render_json(json!({ "messages": messages, "ticket": ticket }))
+                    }),
+            ),
+            None => Either::B(err(custom(
+                user_err!(NotFound, "You aren't on a team.").compat(),
+            ))),
+        })
+        .boxed()
+}
+
+/// Lists the user's team's tickets, newest first.
+fn tickets() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, db| match me.team {
+            Some(team) => Either::A(
+                logic::list_team_tickets(db, team)
+                    .err_to_rejection()
+                    .and_then(render_json),
+            ),
+            None => Either::B(err(custom(
+                user_err!(NotFound, "You aren't on a team.").compat(),
+            ))),
+        })
+        .boxed()
+}
+
+/// Makes another member the captain of the user's team. Only the captain can do this.
+fn transfer_captaincy() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Body {
+        member: String,
+    }
+
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, db, body: Body| {
+            logic::transfer_captaincy(db, me.id, body.member)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Edits the public profile of the user's team.
+fn update_team_profile() -> Resp!() {
+    warp::path::end()
+        .and(bearer())
+        .and(warp::ext::get::<DB>())
+        .and(warp::body::content_length_limit(4 * 1024))
+        .and(warp::body::json())
+        .and_then(|me: User, db, profile: TeamProfile| {
+            logic::update_team_profile(db, me.id, profile)
+                .map(|()| no_content())
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Turns a rejection into a JSON error.
+fn error(rejection: Rejection) -> Result<Response<String>, Rejection> {
+    let (status, message) = if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not found.".to_string())
+    } else if let Some(cause) = rejection.find_cause::<Compat<Error>>() {
+        match error_status(cause.get_ref()) {
+            Some(status) => status,
+            None => {
+                error!("Unhandled API error: {}", cause);
+                let message = "Something went wrong.".to_string();
+                (StatusCode::INTERNAL_SERVER_ERROR, message)
+            }
+        }
+    } else {
+        // Warp's own rejections, like for a malformed body or the wrong method.
+        let status = rejection.status();
+        if status == StatusCode::INTERNAL_SERVER_ERROR {
+            error!("Unhandled API error: {:?}", rejection);
+        }
+        let message = rejection
+            .cause()
+            .map(|cause| cause.to_string())
+            .or_else(|| status.canonical_reason().map(str::to_string))
+            .unwrap_or_default();
+        (status, message)
+    };
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(json!({ "error": message }).to_string())
+        .map_err(|e| custom(Error::from(e).compat()))
+}
+
+/// An empty response, for requests that don't have anything to return.
+fn no_content() -> Response<String> {
+    let mut response = Response::new(String::new());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::collections::BTreeSet;
+
+    /// Reads the route table out of `routes()`, as (method, path prefix, handler) triples.
+    fn route_table() -> Vec<(String, String, String)> {
+        include_str!("api.rs")
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| *line != "route_any! {")
+            .skip(1)
+            .take_while(|line| *line != "}")
+            .map(|line| {
+                let open = line.find('(').unwrap();
+                let close = line.find(')').unwrap();
+                let method = line[..open].to_lowercase();
+                let prefix = line[open + 1..close]
+                    .split('/')
+                    .map(|segment| segment.trim().trim_matches('"'))
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let handler = line[line.find("=> ").unwrap() + 3..]
+                    .trim_end_matches("(),")
+                    .to_string();
+                (method, format!("/{}", prefix), handler)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn openapi_matches_the_route_table() {
+        let routes = route_table();
+        assert!(!routes.is_empty());
+
+        let openapi: Value = serde_json::from_str(OPENAPI).unwrap();
+        let mut documented = BTreeSet::new();
+        for (path, operations) in openapi["paths"].as_object().unwrap() {
+            for (method, operation) in operations.as_object().unwrap() {
+                let handler = operation["operationId"]
+                    .as_str()
+                    .unwrap_or_else(|| panic!("{} {} doesn't have an operationId", method, path));
+                let route = routes
+                    .iter()
+                    .find(|(m, _, h)| m == method && h == handler)
+                    .unwrap_or_else(|| panic!("{} {} isn't routed to {}", method, path, handler));
+                assert!(
+                    path == &route.1 || path.starts_with(&format!("{}/", route.1)),
+                    "{} {} is routed under {}",
+                    method,
+                    path,
+                    route.1
+                );
+                assert!(
+                    documented.insert(handler.to_string()),
+                    "{} is documented twice",
+                    handler
+                );
+            }
+        }
+
+        let routed = routes
+            .into_iter()
+            .map(|(_, _, h)| h)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(documented, routed);
+    }
+}
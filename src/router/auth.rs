@@ -1,62 +1,281 @@
 use crate::{
-    dal::{Mailer, DB},
-    logic,
+    dal::{Mailer, Oidc, DB},
+    logic::{
+        self,
+        auth::{
+            oidc::{OidcCallback, OidcLogin},
+            passkey::{PasskeyLogin, PASSKEY_NAME_MISSING},
+            totp::WRONG_CODE,
+            LoginCode, LoginLink,
+        },
+    },
     router::{
-        simple_page,
         team::TeamMembers,
-        util::{FilterExt, FutureExt},
+        util::{constraint_name, format, redirect, FilterExt, FutureExt, CLEAR_ACCESS_COOKIE},
+    },
+    schema::{
+        ClientInfo, Config, ErrorKind, PasskeyAssertion, PasskeyRegistration, Team, TeamMember,
+        User, UserError,
     },
-    schema::{Team, User},
-    view::render_html,
+    view::{render_as, render_html, render_qr_svg},
 };
-use chrono::Duration;
-use failure::{Compat, Error};
+use bytes::Buf;
+use failure::Error;
 use futures::{
-    future::{ok, result, Either},
+    future::{ok, Either},
     Future,
 };
-use serde_derive::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+    net::{IpAddr, SocketAddr},
+};
 use uuid::Uuid;
 use warp::{
+    filters::body::FullBody,
     http::{
-        header::{LOCATION, SET_COOKIE},
+        header::{HeaderValue, LOCATION, SET_COOKIE},
         Response, StatusCode,
     },
-    path, Filter, Rejection,
+    path,
+    path::FullPath,
+    reject::custom,
+    Filter, Rejection,
 };
 
-/// A filter that parses a user's authentication cookie.
+/// The `Set-Cookie` value that discards what was saved when logging in with the OpenID Connect
+/// provider started.
+const CLEAR_OIDC_COOKIE: &str = "oidc=; Max-Age=0; Path=/login/oidc; HttpOnly; SameSite=Lax";
+
+/// The CSRF token of the user's session, which every form they submit must carry.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct CsrfToken(pub Uuid);
+
+/// The error for a form submitted without the CSRF token of the user's session.
+#[derive(Debug)]
+pub struct InvalidCsrfToken;
+
+impl Display for InvalidCsrfToken {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.write_str("Invalid CSRF token")
+    }
+}
+
+impl StdError for InvalidCsrfToken {}
+
+/// A signed access token to send to the user along with the response, since theirs was missing or
+/// expired.
+#[derive(Clone, Debug)]
+pub struct NewAccessToken(pub String);
+
+/// A filter that parses a user's authentication cookie. If access tokens are turned on, a valid
+/// access token is trusted instead of looking the session up in the database (the user's team is
+/// still looked up, since others can change it); when it's missing or expired, a new one is issued
+/// on the next page view.
+///
+/// Access tokens are only trusted for viewing pages outside of `/admin`. Anything else could
+/// change state or show admin-only data, so it always checks the session, and whether the user is
+/// banned or an admin, against the database.
 pub fn parse_auth_cookie() -> impl Clone + Filter<Extract = (), Error = Rejection> {
+    let trust_access = warp::get2()
+        .and(warp::path::full())
+        .map(|path: FullPath| !path.as_str().starts_with("/admin"))
+        .or(warp::any().map(|| false))
+        .unify();
+
     warp::cookie("auth")
+        .and(warp::cookie::optional("access"))
+        .and(trust_access)
+        .and(warp::ext::get::<Config>())
         .and(warp::ext::get::<DB>())
-        .and_then(move |token: String, db: DB| {
-            logic::auth::authed_user(db.clone(), &token)
-                .and_then(move |user| {
-                    let team = user.team;
-                    warp::ext::set(user);
-                    if let Some(team) = team {
-                        Either::A(
-                            db.get_team(team)
-                                .map(warp::ext::set)
-                                .join(
-                                    db.get_team_members(team)
-                                        .map(TeamMembers)
-                                        .map(warp::ext::set),
-                                )
-                                .map(|((), ())| ()),
-                        )
-                    } else {
-                        Either::B(ok(()))
-                    }
-                })
-                .err_to_rejection()
-        })
+        .and_then(
+            move |token: String, access: Option<String>, trust_access, config: Config, db: DB| {
+                let claims = match access.filter(|_| trust_access) {
+                    Some(access) => Either::A(logic::auth::verify_access_token(
+                        db.clone(),
+                        &config,
+                        &access,
+                    )),
+                    None => Either::B(ok(None)),
+                };
+                claims
+                    .and_then(move |claims| match claims {
+                        Some(claims) => Either::A(ok(claims)),
+                        None => Either::B(logic::auth::authed_user(db, &token).and_then(
+                            move |(user, csrf)| {
+                                if trust_access {
+                                    if let Some(access) =
+                                        logic::auth::issue_access_token(&config, &user, csrf)?
+                                    {
+                                        warp::ext::set(NewAccessToken(access_cookie(
+                                            &access, &config,
+                                        )));
+                                    }
+                                }
+                                Ok((user, csrf))
+                            },
+                        )),
+                    })
+                    .map(move |(user, csrf)| {
+                        warp::ext::set(user);
+                        warp::ext::set(CsrfToken(csrf));
+                    })
+                    .err_to_rejection()
+            },
+        )
         .untuple_one()
         .or(warp::any())
         .unify()
 }
 
+/// A filter that retrieves the access token to send to the user, if one was issued. The
+/// `parse_auth_cookie` filter must have already been run.
+pub fn opt_new_access_token(
+) -> impl Clone + Filter<Extract = (Option<NewAccessToken>,), Error = Rejection> {
+    warp::ext::get::<NewAccessToken>()
+        .map(Some)
+        .or(warp::any().map(|| None))
+        .unify()
+}
+
+/// Creates the `Set-Cookie` value for an access token.
+fn access_cookie(access: &str, config: &Config) -> String {
+    format!(
+        "access={}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax{}",
+        access,
+        config.access_token_lifetime.num_seconds(),
+        if config.secure_cookies {
+            "; Secure"
+        } else {
+            ""
+        }
+    )
+}
+
+/// A filter that extracts information about the client, to be recorded with any session it logs
+/// in.
+pub fn client_info() -> impl Clone + Filter<Extract = (ClientInfo,), Error = Rejection> {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::header::optional::<String>("user-agent"))
+        .and(warp::ext::get::<Config>())
+        .map(
+            |addr: Option<SocketAddr>,
+             forwarded_for: Option<String>,
+             user_agent: Option<String>,
+             config: Config| ClientInfo {
+                ip: addr.map(|addr| {
+                    client_ip(addr.ip(), forwarded_for.as_ref(), &config.trusted_proxies)
+                        .to_string()
+                }),
+                user_agent: user_agent.map(|user_agent| user_agent.chars().take(256).collect()),
+            },
+        )
+}
+
+/// Finds the address a request came from. Requests from a trusted proxy came from the last
+/// address in their `X-Forwarded-For` header that isn't a trusted proxy too; anyone else's
+/// `X-Forwarded-For` header is ignored, since they could've put anything in it.
+fn client_ip(peer: IpAddr, forwarded_for: Option<&String>, trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut ip = peer;
+    if let Some(forwarded_for) = forwarded_for {
+        for hop in forwarded_for.rsplit(',') {
+            if !trusted_proxies.contains(&ip) {
+                break;
+            }
+            match hop.trim().parse() {
+                Ok(hop) => ip = hop,
+                Err(_) => break,
+            }
+        }
+    }
+    ip
+}
+
+/// A filter that checks a form's CSRF token, then deserializes it. Logged-out users don't have a
+/// CSRF token yet, so their forms (logging in and registering) are checked by their `Origin`
+/// header instead, which browsers send with cross-site form submissions. The `parse_auth_cookie`
+/// filter must have already been run.
+pub fn csrf_form<T>() -> impl Clone + Filter<Extract = (T,), Error = Rejection>
+where
+    T: 'static + DeserializeOwned + Send,
+{
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        csrf: Option<String>,
+    }
+
+    warp::body::concat()
+        .and(opt_csrf_token())
+        .and(warp::header::optional::<String>("origin"))
+        .and(warp::header::optional::<String>("host"))
+        .and(warp::ext::get::<Config>())
+        .and_then(
+            |body: FullBody,
+             token: Option<CsrfToken>,
+             origin: Option<String>,
+             host: Option<String>,
+             config: Config| {
+                let body = body.bytes();
+                match token {
+                    Some(CsrfToken(token)) => {
+                        let form: Form = serde_urlencoded::from_bytes(body).map_err(custom)?;
+                        if form.csrf.and_then(|csrf| csrf.parse().ok()) != Some(token) {
+                            return Err(custom(InvalidCsrfToken));
+                        }
+                    }
+                    None => {
+                        if !same_origin(origin.as_ref(), host.as_ref(), &config) {
+                            return Err(custom(InvalidCsrfToken));
+                        }
+                    }
+                }
+                serde_urlencoded::from_bytes(body).map_err(custom)
+            },
+        )
+}
+
+/// A filter that checks the CSRF token of a form with no other fields.
+pub fn check_csrf() -> impl Clone + Filter<Extract = (), Error = Rejection> {
+    #[derive(Debug, Deserialize)]
+    struct Form {}
+
+    warp::body::content_length_limit(1024)
+        .and(csrf_form())
+        .map(|Form {}| ())
+        .untuple_one()
+}
+
+/// Returns whether a request's `Origin` header, if it has one, is this site: either the host the
+/// request was made to, or the configured origin, in case a reverse proxy changed the `Host`
+/// header. Requests without an `Origin` header don't come from another site's form, since
+/// browsers always send one with those.
+fn same_origin(origin: Option<&String>, host: Option<&String>, config: &Config) -> bool {
+    let origin = match origin {
+        Some(origin) => origin,
+        None => return true,
+    };
+    if origin.eq_ignore_ascii_case(&config.webauthn_origin) {
+        return true;
+    }
+    match (origin.splitn(2, "://").nth(1), host) {
+        (Some(origin), Some(host)) => origin.eq_ignore_ascii_case(host),
+        _ => false,
+    }
+}
+
+/// A filter that retrieves the CSRF token of the user's session. The `parse_auth_cookie` filter
+/// must have already been run.
+pub fn opt_csrf_token() -> impl Clone + Filter<Extract = (Option<CsrfToken>,), Error = Rejection> {
+    warp::ext::get::<CsrfToken>()
+        .map(Some)
+        .or(warp::any().map(|| None))
+        .unify()
+}
+
 /// A filter that optionally authenticates the user via a cookie. The `parse_auth_cookie` filter
 /// must have already been run.
 pub fn opt_auth() -> impl Clone + Filter<Extract = (Option<User>,), Error = Rejection> {
@@ -69,57 +288,322 @@ pub fn opt_auth() -> impl Clone + Filter<Extract = (Option<User>,), Error = Reje
 /// A filter that retrieves the user's team from their authentication cookie. The
 /// `parse_auth_cookie` filter must have already been run.
 pub fn opt_team() -> impl Clone + Filter<Extract = (Option<Team>,), Error = Rejection> {
-    warp::ext::get::<Team>()
-        .map(Some)
-        .or(warp::any().map(|| None))
-        .unify()
+    load_team().and(
+        warp::ext::get::<Team>()
+            .map(Some)
+            .or(warp::any().map(|| None))
+            .unify(),
+    )
 }
 
 /// A filter that retrieves the user's team's members from their authentication cookie. The
 /// `parse_auth_cookie` filter must have already been run.
-pub fn opt_team_members() -> impl Clone + Filter<Extract = (Option<Vec<String>>,), Error = Rejection>
-{
-    warp::ext::get::<TeamMembers>()
-        .map(|TeamMembers(tm)| Some(tm))
-        .or(warp::any().map(|| None))
+pub fn opt_team_members(
+) -> impl Clone + Filter<Extract = (Option<Vec<TeamMember>>,), Error = Rejection> {
+    load_team().and(
+        warp::ext::get::<TeamMembers>()
+            .map(|TeamMembers(tm)| Some(tm))
+            .or(warp::any().map(|| None))
+            .unify(),
+    )
+}
+
+/// A filter that requires the user to be on a team, extracting it. The `parse_auth_cookie` filter
+/// must have already been run.
+pub fn team() -> impl Clone + Filter<Extract = (Team,), Error = Rejection> {
+    load_team().and(warp::ext::get::<Team>())
+}
+
+/// A filter that loads the user's team and its members into the request the first time a route
+/// asks for them, so pages that don't show the team don't query it.
+fn load_team() -> impl Clone + Filter<Extract = (), Error = Rejection> {
+    /// Marks a request whose team has already been loaded.
+    #[derive(Clone, Copy, Debug)]
+    struct TeamLoaded;
+
+    let load = opt_auth()
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: Option<User>, db: DB| {
+            warp::ext::set(TeamLoaded);
+            match me.and_then(|me| me.team) {
+                Some(team) => Either::A(
+                    db.get_team(team)
+                        .map(warp::ext::set)
+                        .join(
+                            db.get_team_members(team)
+                                .map(TeamMembers)
+                                .map(warp::ext::set),
+                        )
+                        .map(|((), ())| ())
+                        .err_to_rejection(),
+                ),
+                None => Either::B(ok(())),
+            }
+        })
+        .untuple_one();
+    warp::ext::get::<TeamLoaded>()
+        .map(|_| ())
+        .untuple_one()
+        .or(load)
         .unify()
 }
 
+/// The route for cancelling the user's pending email change.
+pub fn cancel_email_change() -> Resp!() {
+    warp::path::end()
+        .and(check_csrf())
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, db| {
+            logic::auth::cancel_email_change(db, me.id)
+                .and_then(|()| redirect("/settings"))
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// The route for requesting a change of the user's email address.
+pub fn change_email() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        email: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Mailer>())
+        .and(csrf_form())
+        .and_then(|me: User, db, mailer, form: Form| {
+            logic::auth::request_email_change(db, mailer, me.id, form.email)
+                .and_then(|()| redirect("/settings"))
+                .err_to_rejection()
+        })
+        .recover_with_fields("settings.html", |_| vec!["bad_email"])
+}
+
+/// The route for confirming an email change from the link mailed to the new address.
+pub fn confirm_email_change() -> Resp!() {
+    path!(Uuid)
+        .and(warp::path::end())
+        .and(check_csrf())
+        .and(opt_auth())
+        .and(warp::ext::get::<DB>())
+        .and_then(|change, me: Option<User>, db| {
+            logic::auth::confirm_email_change(db, change)
+                .and_then(move |()| match me {
+                    Some(_) => redirect("/settings"),
+                    None => redirect("/login"),
+                })
+                .err_to_rejection()
+        })
+        .recover_with_template("email-change-error.html")
+}
+
+/// The route for creating an API token, which shows it to the user once.
+pub fn create_api_token() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        name: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and(opt_csrf_token())
+        .and(csrf_form())
+        .and_then(|me: User, db, csrf, form: Form| {
+            logic::auth::create_api_token(db, me.id, form.name)
+                .err_to_rejection()
+                .and_then(move |token| {
+                    let data = json!({ "csrf": csrf, "me": me.for_page(), "token": token });
+                    render_html("api-token.html", data)
+                })
+        })
+        .recover_with_template("api-token-error.html")
+}
+
+/// The route for deleting one of the user's passkeys.
+pub fn delete_passkey() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        passkey: i32,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and(csrf_form())
+        .and_then(|me: User, db, form: Form| {
+            logic::auth::passkey::delete_passkey(db, me.id, form.passkey)
+                .and_then(|()| redirect("/settings"))
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// The route for turning off two-factor authentication, which takes a code.
+pub fn disable_totp() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        code: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::cookie("auth"))
+        .and(warp::ext::get::<DB>())
+        .and(csrf_form())
+        .and_then(|me: User, token: String, db, form: Form| {
+            logic::auth::totp::disable(db, me.id, &token, form.code)
+                .and_then(|()| redirect("/settings"))
+                .err_to_rejection()
+        })
+        .recover_with_template("totp-error.html")
+}
+
+/// The page for confirming an email change, linked to from the mail sent to the new address.
+pub fn email_change_page() -> Resp!() {
+    path!(Uuid)
+        .and(warp::path::end())
+        .and(opt_auth())
+        .and(opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and_then(|change, me: Option<User>, csrf, db| {
+            logic::auth::get_email_change(db, change)
+                .err_to_rejection()
+                .and_then(move |email| {
+                    let data = json!({
+                        "change": change,
+                        "csrf": csrf,
+                        "email": email,
+                        "me": me.as_ref().map(User::for_page)
+                    });
+                    render_html("email-change.html", data)
+                })
+        })
+        .recover_with_template("email-change-error.html")
+}
+
+/// The route for finishing setting up two-factor authentication, which shows the user their
+/// recovery codes.
+pub fn enable_totp() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        code: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::cookie("auth"))
+        .and(warp::ext::get::<DB>())
+        .and(opt_csrf_token())
+        .and(csrf_form())
+        .and_then(|me: User, token: String, db, csrf, form: Form| {
+            logic::auth::totp::enable(db, me.id, &token, form.code)
+                .err_to_rejection()
+                .and_then(move |codes| {
+                    let data = json!({ "codes": codes, "csrf": csrf, "me": me.for_page() });
+                    render_html("totp-recovery-codes.html", data)
+                })
+        })
+        .recover_with_template("totp-error.html")
+}
+
+/// The login page, which offers logging in with the OpenID Connect provider if there is one.
+pub fn login_page() -> Resp!() {
+    warp::path::end()
+        .and(opt_auth())
+        .and(opt_csrf_token())
+        .and(warp::ext::get::<Option<Oidc>>())
+        .and_then(|me: Option<User>, csrf, oidc: Option<Oidc>| {
+            let sso = oidc.map(|oidc| oidc.config().name.clone());
+            render_html(
+                "login.html",
+                json!({ "csrf": csrf, "me": me.as_ref().map(User::for_page), "sso": sso }),
+            )
+        })
+        .boxed()
+}
+
 pub fn login() -> Resp!() {
     #[derive(Debug, Deserialize)]
     struct Form {
-        username: String,
+        login: String,
     }
 
-    warp::body::content_length_limit(2 * 1024)
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
         .and(warp::ext::get::<DB>())
         .and(warp::ext::get::<Mailer>())
-        .and(warp::body::form())
-        .and_then(|db, mailer, form: Form| {
-            logic::auth::login_1(db, mailer, form.username).err_to_rejection()
+        .and(warp::ext::get::<Config>())
+        .and(client_info())
+        .and(csrf_form())
+        .and_then(|db, mailer, config: Config, client, form: Form| {
+            logic::auth::login_1(db, mailer, &config, form.login.clone(), client)
+                .map(move |()| form.login)
+                .err_to_rejection()
         })
-        .untuple_one()
-        .and(simple_page("login-ok.html"))
-        .recover_with_template("login.html", |err: &Compat<Error>| {
-            let err = err.to_string();
-            match coerce!(&err => &str) {
-                r#"NotFound"# => Some((
-                    StatusCode::NOT_FOUND,
-                    vec!["bad_username"],
-                    vec!["That user doesn't exist..."],
-                )),
-                _ => None,
+        .and(opt_auth())
+        .and(opt_csrf_token())
+        .and_then(login_ok_page)
+        .recover_with_template("login.html")
+}
+
+/// Renders the page shown once a login link has been sent to the user with the given username or
+/// email address, where the login code can be typed in.
+fn login_ok_page(
+    login: String,
+    me: Option<User>,
+    csrf: Option<CsrfToken>,
+) -> Result<Response<String>, Rejection> {
+    let data = json!({ "csrf": csrf, "login": login, "me": me.as_ref().map(User::for_page) });
+    render_html("login-ok.html", data)
+}
+
+/// The route for logging in with a code from a login mail.
+pub fn login_with_code() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        code: String,
+        login: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and(client_info())
+        .and(csrf_form())
+        .and_then(|config: Config, db, client, form: Form| {
+            let proof = (form.login, form.code);
+            logic::auth::log_in(db, &config, &LoginCode, proof, client)
+                .and_then(move |auth| set_auth_cookie(auth, &config, "/"))
+                .err_to_rejection()
+        })
+        .recover_with_fields("login-ok.html", |err| {
+            // Banned users get a different kind of error; every other one is about the code.
+            match err.downcast_ref::<UserError>() {
+                Some(err) if err.kind == ErrorKind::BadRequest => vec!["bad_code"],
+                _ => vec![],
             }
         })
-        .boxed()
 }
 
 pub fn login_from_mail_get() -> Resp!() {
     path!(Uuid)
         .and(warp::path::end())
         .and(opt_auth())
-        .and_then(move |login, me| {
-            render_html("login-from-mail.html", json!({ "login": login, "me": me }))
+        .and(opt_csrf_token())
+        .and_then(move |login, me: Option<User>, csrf| {
+            let data =
+                json!({ "csrf": csrf, "login": login, "me": me.as_ref().map(User::for_page) });
+            render_html("login-from-mail.html", data)
         })
         .boxed()
 }
@@ -127,35 +611,172 @@ pub fn login_from_mail_get() -> Resp!() {
 pub fn login_from_mail_post() -> Resp!() {
     path!(Uuid)
         .and(warp::path::end())
+        .and(check_csrf())
+        .and(warp::ext::get::<Config>())
         .and(warp::ext::get::<DB>())
-        .and_then(|login, db| logic::auth::login_2(db, login).err_to_rejection())
-        .and_then(|auth| {
+        .and(client_info())
+        .and_then(|login, config: Config, db, client| {
+            logic::auth::log_in(db, &config, &LoginLink, login, client)
+                .and_then(move |auth| set_auth_cookie(auth, &config, "/"))
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// The route for logging in with a passkey, once the browser has signed the challenge from
+/// `passkey_login_start`.
+pub fn passkey_login() -> Resp!() {
+    warp::path::end()
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and(client_info())
+        .and(csrf_form())
+        .and_then(|config: Config, db, client, assertion: PasskeyAssertion| {
+            let method = PasskeyLogin::new(&config);
+            logic::auth::log_in(db, &config, &method, assertion, client)
+                .and_then(move |auth| set_auth_cookie(auth, &config, "/"))
+                .err_to_rejection()
+        })
+        .recover_with_template("login.html")
+}
+
+/// The route that starts logging in with a passkey, returning the options for
+/// `navigator.credentials.get` as JSON.
+pub fn passkey_login_start() -> Resp!() {
+    warp::path::end()
+        .and(check_csrf())
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and_then(|config: Config, db| {
+            logic::auth::passkey::start_login(db, &config)
+                .map(|options| warp::reply::json(&options))
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// The route the OpenID Connect provider sends users back to after they log in there.
+pub fn oidc_callback() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Query {
+        code: Option<String>,
+        state: Option<String>,
+    }
+
+    warp::path::end()
+        .and(warp::query::<Query>())
+        .and(warp::cookie::optional("oidc"))
+        .and(oidc())
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and(client_info())
+        .and_then(|query: Query, saved, oidc, config: Config, db, client| {
+            let callback = OidcCallback {
+                code: query.code,
+                state: query.state,
+                saved,
+            };
+            logic::auth::log_in(db, &config, &OidcLogin(oidc), callback, client)
+                .and_then(move |auth| set_auth_cookie(auth, &config, "/"))
+                .map(|mut res| {
+                    let _ = res
+                        .headers_mut()
+                        .append(SET_COOKIE, HeaderValue::from_static(CLEAR_OIDC_COOKIE));
+                    res
+                })
+                .err_to_rejection()
+        })
+        .recover_with_template("login.html")
+}
+
+/// The route that starts logging in with the OpenID Connect provider, sending the user there.
+pub fn oidc_start() -> Resp!() {
+    warp::path::end()
+        .and(oidc())
+        .and(warp::ext::get::<Config>())
+        .and_then(|oidc, config: Config| {
+            let (url, saved) = logic::auth::oidc::start(&oidc);
             let set_cookie = format!(
-                "auth={}; Max-Age={}; Path=/",
-                auth,
-                Duration::weeks(520).num_seconds()
+                "oidc={}; Max-Age=600; Path=/login/oidc; HttpOnly; SameSite=Lax{}",
+                saved,
+                if config.secure_cookies {
+                    "; Secure"
+                } else {
+                    ""
+                }
             );
-            let r = Response::builder()
-                .header(LOCATION, "/")
+            Response::builder()
+                .header(LOCATION, url)
                 .header(SET_COOKIE, set_cookie)
                 .status(StatusCode::FOUND)
                 .body(String::new())
-                .map_err(Error::from);
-            result(r).err_to_rejection()
+                .map_err(|err| custom(Error::from(err).compat()))
         })
         .boxed()
 }
 
+/// A filter that retrieves the OpenID Connect provider, rejecting the request if there isn't one.
+fn oidc() -> impl Clone + Filter<Extract = (Oidc,), Error = Rejection> {
+    warp::ext::get::<Option<Oidc>>()
+        .and_then(|oidc: Option<Oidc>| oidc.ok_or_else(warp::reject::not_found))
+}
+
+/// Creates a response that sets the authentication cookie, then redirects to the given location.
+/// The cookie lasts as long as the session it's for, and is kept away from scripts and other
+/// sites.
+pub fn set_auth_cookie(
+    auth: Uuid,
+    config: &Config,
+    location: &str,
+) -> Result<Response<String>, Error> {
+    let set_cookie = format!(
+        "auth={}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax{}",
+        auth,
+        config.session_lifetime.num_seconds(),
+        if config.secure_cookies {
+            "; Secure"
+        } else {
+            ""
+        }
+    );
+    Response::builder()
+        .header(LOCATION, location)
+        .header(SET_COOKIE, set_cookie)
+        .header(SET_COOKIE, CLEAR_ACCESS_COOKIE)
+        .status(StatusCode::FOUND)
+        .body(String::new())
+        .map_err(Error::from)
+}
+
+/// Creates a response that clears the authentication cookie, then redirects to the given
+/// location.
+fn clear_auth_cookie(location: &str) -> Result<Response<String>, Error> {
+    Response::builder()
+        .header(LOCATION, location)
+        .header(
+            SET_COOKIE,
+            "auth=; Max-Age=0; Path=/; HttpOnly; SameSite=Lax",
+        )
+        .header(SET_COOKIE, CLEAR_ACCESS_COOKIE)
+        .status(StatusCode::FOUND)
+        .body(String::new())
+        .map_err(Error::from)
+}
+
 pub fn logout() -> Resp!() {
     warp::path::end()
-        .and_then(|| {
-            let r = Response::builder()
-                .header(LOCATION, "/")
-                .header(SET_COOKIE, "auth=; Max-Age=0; Path=/")
-                .status(StatusCode::FOUND)
-                .body(String::new())
-                .map_err(Error::from);
-            result(r).err_to_rejection()
+        .and(check_csrf())
+        .and(warp::cookie::optional("auth"))
+        .and(warp::ext::get::<DB>())
+        .and_then(|token: Option<String>, db| {
+            let logout = match token {
+                Some(token) => Either::A(logic::auth::logout(db, &token)),
+                None => Either::B(ok(())),
+            };
+            logout
+                .and_then(|()| clear_auth_cookie("/"))
+                .err_to_rejection()
         })
         .boxed()
 }
@@ -170,46 +791,293 @@ pub fn register() -> Resp!() {
     warp::body::content_length_limit(2 * 1024)
         .and(warp::ext::get::<DB>())
         .and(warp::ext::get::<Mailer>())
-        .and(warp::body::form())
-        .and_then(|db, mailer, form: Form| {
-            logic::auth::register(db, mailer, form.username, form.email).err_to_rejection()
+        .and(warp::ext::get::<Config>())
+        .and(client_info())
+        .and(csrf_form())
+        .and_then(|db, mailer, config: Config, client, form: Form| {
+            let email = form.email.clone();
+            logic::auth::register(db, mailer, &config, form.username, form.email, client)
+                .map(move |()| email)
+                .err_to_rejection()
         })
-        .untuple_one()
-        .and(simple_page("login-ok.html"))
-        .recover_with_template("register.html", |err: &Compat<Error>| {
-            let err = err.to_string();
-            match coerce!(&err => &str) {
-                r#"new row for relation "users" violates check constraint "name_fmt""# => Some((
-                    StatusCode::BAD_REQUEST,
-                    vec!["bad_username"],
-                    vec!["Your username must contain only ASCII letters and digits"],
-                )),
-                r#"new row for relation "users" violates check constraint "name_len""# => Some((
-                    StatusCode::BAD_REQUEST,
-                    vec!["bad_username"],
-                    vec!["Your username must be at least 3 characters"],
-                )),
-                r#"duplicate key value violates unique constraint "users_name_key""# => Some((
-                    StatusCode::BAD_REQUEST,
-                    vec!["bad_username"],
-                    vec!["This username is already taken"],
-                )),
-                r#"new row for relation "users" violates check constraint "email_fmt""# => Some((
-                    StatusCode::BAD_REQUEST,
-                    vec!["bad_email"],
-                    vec!["That doesn't look like an email address..."],
-                )),
-                r#"new row for relation "users" violates check constraint "email_len""# => Some((
-                    StatusCode::BAD_REQUEST,
-                    vec!["bad_email"],
-                    vec!["That doesn't look like an email address..."],
-                )),
-                r#"duplicate key value violates unique constraint "users_email_key""# => Some((
-                    StatusCode::BAD_REQUEST,
-                    vec!["bad_email"],
-                    vec!["This email is already registered"],
-                )),
-                _ => None,
+        .and(opt_auth())
+        .and(opt_csrf_token())
+        .and_then(login_ok_page)
+        .recover_with_fields("register.html", register_fields)
+}
+
+/// Picks the fields of the registration form an error is about.
+fn register_fields(err: &Error) -> Vec<&'static str> {
+    match constraint_name(err) {
+        Some("name_fmt") | Some("name_len") | Some("users_name_key") => vec!["bad_username"],
+        Some("email_fmt") | Some("email_len") => vec!["bad_email"],
+        // The only other mistake is picking a username that's already taken.
+        _ => match err.downcast_ref::<UserError>() {
+            Some(err) if err.kind == ErrorKind::BadRequest => vec!["bad_username"],
+            _ => vec![],
+        },
+    }
+}
+
+/// The route for registering a passkey, once the browser has created it in response to the
+/// challenge from `register_passkey_start`.
+pub fn register_passkey() -> Resp!() {
+    warp::path::end()
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and(csrf_form())
+        .and_then(
+            |me: User, config: Config, db, registration: PasskeyRegistration| {
+                logic::auth::passkey::finish_registration(db, &config, me.id, registration)
+                    .and_then(|()| redirect("/settings"))
+                    .err_to_rejection()
+            },
+        )
+        .recover_with_fields("settings.html", |err| {
+            // The name is the only thing the database could find too long.
+            let named = match err.downcast_ref::<UserError>() {
+                Some(err) => err.message == PASSKEY_NAME_MISSING,
+                None => constraint_name(err).is_none(),
+            };
+            if named {
+                vec!["bad_passkey_name"]
+            } else {
+                vec![]
             }
         })
 }
+
+/// The route that starts registering a passkey for the user, returning the options for
+/// `navigator.credentials.create` as JSON.
+pub fn register_passkey_start() -> Resp!() {
+    warp::path::end()
+        .and(check_csrf())
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, config: Config, db| {
+            logic::auth::passkey::start_registration(db, &config, me)
+                .map(|options| warp::reply::json(&options))
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// Picks the fields of a second factor form an error is about.
+fn second_factor_fields(err: &Error) -> Vec<&'static str> {
+    match err.downcast_ref::<UserError>() {
+        Some(err) if err.message == WRONG_CODE => vec!["bad_code"],
+        _ => vec![],
+    }
+}
+
+/// The route for revoking one of the user's API tokens.
+pub fn revoke_api_token() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        token: i32,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and(csrf_form())
+        .and_then(|me: User, db, form: Form| {
+            logic::auth::revoke_api_token(db, me.id, form.token)
+                .and_then(|()| redirect("/settings"))
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// The route for logging out one of the user's other sessions.
+pub fn revoke_session() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        session: i32,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and(csrf_form())
+        .and_then(|me: User, db, form: Form| {
+            logic::auth::revoke_session(db, me.id, form.session)
+                .and_then(|()| redirect("/sessions"))
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// The route for logging out all of the user's sessions, including the current one.
+pub fn revoke_sessions() -> Resp!() {
+    warp::path::end()
+        .and(check_csrf())
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, db| {
+            logic::auth::revoke_sessions(db, me.id)
+                .and_then(|()| clear_auth_cookie("/"))
+                .err_to_rejection()
+        })
+        .boxed()
+}
+
+/// The page listing the user's sessions.
+pub fn sessions_page() -> Resp!() {
+    warp::path::end()
+        .and(warp::ext::get::<User>())
+        .and(warp::cookie("auth"))
+        .and(warp::ext::get::<DB>())
+        .and(opt_csrf_token())
+        .and(format())
+        .and_then(|me: User, current: String, db, csrf, format| {
+            logic::auth::get_sessions(db, me.id)
+                .err_to_rejection()
+                .and_then(move |sessions| {
+                    let sessions = sessions
+                        .into_iter()
+                        .map(|session| {
+                            json!({
+                                "created": session.created.to_rfc2822(),
+                                "current": session.token.to_string() == current,
+                                "expires": session.expires.to_rfc2822(),
+                                "ip": session.ip,
+                                "last_used": session.last_used.to_rfc2822(),
+                                "seq": session.seq,
+                                "user_agent": session.user_agent,
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    render_as(
+                        format,
+                        "sessions.html",
+                        json!({ "csrf": csrf, "me": me.for_page(), "sessions": sessions }),
+                    )
+                })
+        })
+        .boxed()
+}
+
+/// The route for starting to set up two-factor authentication.
+pub fn start_totp_setup() -> Resp!() {
+    warp::path::end()
+        .and(check_csrf())
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and_then(|me: User, db| {
+            logic::auth::totp::start_setup(db, me.id)
+                .and_then(|()| redirect("/settings/totp"))
+                .err_to_rejection()
+        })
+        .recover_with_template("totp-error.html")
+}
+
+/// The route for stepping up the user's session with a second factor, which admins need to do
+/// before using the admin pages.
+pub fn step_up() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        code: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::cookie("auth"))
+        .and(warp::ext::get::<DB>())
+        .and(csrf_form())
+        .and_then(|me: User, token: String, db, form: Form| {
+            logic::auth::totp::step_up(db, me.id, &token, form.code)
+                .and_then(|()| redirect("/admin"))
+                .err_to_rejection()
+        })
+        .recover_with_fields("step-up.html", second_factor_fields)
+}
+
+/// The page for adding the user's pending TOTP secret to their authenticator app, and entering a
+/// code from it to finish setting up two-factor authentication.
+pub fn totp_setup_page() -> Resp!() {
+    warp::path::end()
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and(opt_csrf_token())
+        .and_then(|me: User, db, csrf: Option<CsrfToken>| {
+            logic::auth::totp::get_setup(db, me.clone())
+                .err_to_rejection()
+                .and_then(move |setup| match setup {
+                    Some((secret, uri)) => {
+                        let qr = render_qr_svg(&uri).map_err(|err| custom(err.compat()))?;
+                        let data = json!({
+                            "csrf": csrf,
+                            "me": me.for_page(),
+                            "qr": qr,
+                            "secret": secret
+                        });
+                        render_html("totp-setup.html", data)
+                    }
+                    None => redirect("/settings").map_err(|err| custom(err.compat())),
+                })
+        })
+        .boxed()
+}
+
+/// The page for the user's account settings.
+pub fn settings_page() -> Resp!() {
+    warp::path::end()
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and(opt_csrf_token())
+        .and(format())
+        .and_then(|me: User, db: DB, csrf, format| {
+            logic::auth::get_pending_email_change(db.clone(), me.id)
+                .join4(
+                    logic::auth::get_api_tokens(db.clone(), me.id),
+                    logic::auth::passkey::get_passkeys(db.clone(), me.id),
+                    logic::auth::totp::get_status(db, me.id),
+                )
+                .err_to_rejection()
+                .and_then(move |(pending, api_tokens, passkeys, totp)| {
+                    let api_tokens = api_tokens
+                        .into_iter()
+                        .map(|token| {
+                            json!({
+                                "created": token.created.to_rfc2822(),
+                                "id": token.id,
+                                "last_used": token.last_used.map(|at| at.to_rfc2822()),
+                                "name": token.name,
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    let pending = pending.map(|(email, expires)| {
+                        json!({ "email": email, "expires": expires.to_rfc2822() })
+                    });
+                    let passkeys = passkeys
+                        .into_iter()
+                        .map(|passkey| {
+                            json!({
+                                "created": passkey.created.to_rfc2822(),
+                                "id": passkey.id,
+                                "last_used": passkey.last_used.map(|at| at.to_rfc2822()),
+                                "name": passkey.name,
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    let data = json!({
+                        "api_tokens": api_tokens,
+                        "csrf": csrf,
+                        "me": me.for_page(),
+                        "passkeys": passkeys,
+                        "pending": pending,
+                        "recovery_codes": totp,
+                        "totp": totp.is_some()
+                    });
+                    render_as(format, "settings.html", data)
+                })
+        })
+        .boxed()
+}
@@ -1,4 +1,7 @@
-use crate::{router::util::FutureExt, view::render_html};
+use crate::{
+    router::{admin::StepUpRequired, auth::InvalidCsrfToken, util::FutureExt},
+    view::render_html,
+};
 use failure::Error as FailureError;
 use futures::{future::result, Future};
 use log::error;
@@ -13,6 +16,15 @@ use warp::{
 pub fn internal(e: Rejection) -> impl Future<Item = Response<String>, Error = Rejection> {
     let (name, data, code) = if e.is_not_found() {
         ("404.html", json!({}), StatusCode::NOT_FOUND)
+    } else if e.find_cause::<InvalidCsrfToken>().is_some() {
+        ("csrf.html", json!({}), StatusCode::FORBIDDEN)
+    } else if let Some(step_up) = e.find_cause::<StepUpRequired>() {
+        let data = json!({
+            "csrf": step_up.csrf,
+            "me": step_up.me.for_page(),
+            "unenrolled": !step_up.enrolled,
+        });
+        ("step-up.html", data, StatusCode::FORBIDDEN)
     } else {
         error!("Unhandled error: {:?}", e);
         let data = json!({
@@ -0,0 +1,23 @@
+//! Server-Sent Events, which let pages update as things happen instead of polling.
+
+use crate::dal::Events;
+use futures::Stream;
+use std::io::{Error as IoError, ErrorKind};
+use warp::{sse::Sse, Filter};
+
+/// The stream of events, as `text/event-stream`. Each event's data is the JSON of a
+/// `schema::Event`.
+pub fn stream() -> Resp!() {
+    warp::path::end()
+        .and(warp::sse())
+        .and(warp::ext::get::<Events>())
+        .map(|sse: Sse, events: Events| {
+            let events = events
+                .subscribe()
+                .map(warp::sse::json)
+                // The receiving half of the channel never errors.
+                .map_err(|()| IoError::new(ErrorKind::Other, "the event channel closed"));
+            sse.reply(warp::sse::keep(events, None))
+        })
+        .boxed()
+}
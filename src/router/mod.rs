@@ -7,15 +7,24 @@
 #[macro_use]
 mod util;
 
+mod admin;
+mod announcements;
+mod api;
 mod auth;
 mod errors;
+mod events;
+mod problems;
+mod scoreboard;
 mod team;
+mod tickets;
 
 use crate::{
-    dal::{Mailer, DB},
+    dal::{Events, Mailer, Oidc, DB},
     router::util::set,
+    schema::{Config, User},
     view::render_html,
 };
+use either::Either;
 use futures::{
     future::{loop_fn, ok, Loop},
     Future,
@@ -25,7 +34,10 @@ use packer::Packer;
 use serde_json::json;
 use std::{net::SocketAddr, path::Path};
 use warp::{
-    http::{header::CONTENT_TYPE, Response},
+    http::{
+        header::{CONTENT_TYPE, SET_COOKIE},
+        Response,
+    },
     path,
     reject::custom,
     Filter, Rejection,
@@ -38,12 +50,18 @@ pub fn serve_on<T, E>(
     addr: SocketAddr,
     db: DB,
     mailer: Mailer,
+    oidc: Option<Oidc>,
+    config: Config,
 ) -> impl Future<Item = T, Error = E> {
+    let events = Events::new();
     loop_fn((), move |()| {
         info!("Starting to serve...");
         let server = set(db.clone())
+            .and(set(events.clone()))
             .and(set(mailer.clone()))
-            .and(statics().or(routes()))
+            .and(set(oidc.clone()))
+            .and(set(config.clone()))
+            .and(statics().or(api::routes()).or(routes()))
             .recover(errors::internal)
             .recover(errors::last_chance)
             .with(warp::log("nihctfplat::router"));
@@ -62,22 +80,94 @@ fn routes() -> Resp!() {
     auth::parse_auth_cookie()
         .and(route_any! {
             GET() => simple_page("index.html"),
+            GET("announcements") => announcements::page(),
+            GET("admin") => admin::page(),
+            POST("admin" / "announcement" / "edit") => admin::edit_announcement(),
+            POST("admin" / "announcement" / "post") => admin::post_announcement(),
+            POST("admin" / "division" / "create") => admin::create_division(),
+            POST("admin" / "division" / "delete") => admin::delete_division(),
+            GET("admin" / "mail") => admin::mail_page(),
+            POST("admin" / "mail" / "retry") => admin::retry_mail(),
+            POST("admin" / "problem" / "create") => admin::create_problem(),
+            POST("admin" / "problem" / "edit") => admin::edit_problem(),
+            POST("admin" / "step-up") => auth::step_up(),
+            POST("admin" / "team" / "disband") => admin::disband_team(),
+            POST("admin" / "team" / "merge") => admin::merge_teams(),
+            POST("admin" / "team" / "remove-member") => admin::remove_team_member(),
+            POST("admin" / "team" / "rename") => admin::rename_team(),
+            POST("admin" / "ticket" / "answer") => admin::answer_ticket(),
+            POST("admin" / "ticket" / "close") => admin::close_ticket(),
+            GET("admin" / "tickets") => admin::tickets_page(),
+            GET("admin" / "tickets") => admin::ticket_page(),
+            GET("divisions") => team::list_divisions(),
+            GET("divisions") => team::division_page(),
+            GET("events" / "stream") => events::stream(),
             GET("humans.txt") => {
                 warp::path::end().map(|| env!("CARGO_PKG_AUTHORS").replace(':', "\n"))
             },
-            GET("login") => simple_page("login.html"),
+            GET("invite") => team::invite_page(),
+            POST("invite") => team::accept_invite(),
+            GET("login") => auth::login_page(),
             GET("login") => auth::login_from_mail_get(),
             POST("login") => auth::login(),
+            POST("login" / "code") => auth::login_with_code(),
+            GET("login" / "oidc") => auth::oidc_start(),
+            GET("login" / "oidc" / "callback") => auth::oidc_callback(),
+            POST("login" / "passkey") => auth::passkey_login(),
+            POST("login" / "passkey" / "start") => auth::passkey_login_start(),
             POST("login") => auth::login_from_mail_post(),
             POST("logout") => auth::logout(),
+            GET("problems") => problems::list(),
+            GET("problems") => problems::page(),
+            POST("problems" / "submit") => problems::submit(),
             GET("register") => simple_page("register.html"),
             POST("register") => auth::register(),
+            GET("scoreboard") => scoreboard::page(),
+            GET("scoreboard") => scoreboard::division_page(),
+            GET("sessions") => auth::sessions_page(),
+            POST("sessions" / "revoke") => auth::revoke_session(),
+            POST("sessions" / "revoke-all") => auth::revoke_sessions(),
+            GET("settings") => auth::settings_page(),
+            POST("settings" / "api-tokens") => auth::create_api_token(),
+            POST("settings" / "api-tokens" / "revoke") => auth::revoke_api_token(),
+            POST("settings" / "email") => auth::change_email(),
+            POST("settings" / "email" / "cancel") => auth::cancel_email_change(),
+            GET("settings" / "email") => auth::email_change_page(),
+            POST("settings" / "email") => auth::confirm_email_change(),
+            POST("settings" / "passkeys") => auth::register_passkey(),
+            POST("settings" / "passkeys" / "delete") => auth::delete_passkey(),
+            POST("settings" / "passkeys" / "start") => auth::register_passkey_start(),
+            GET("settings" / "totp") => auth::totp_setup_page(),
+            POST("settings" / "totp") => auth::start_totp_setup(),
+            POST("settings" / "totp" / "disable") => auth::disable_totp(),
+            POST("settings" / "totp" / "enable") => auth::enable_totp(),
             GET("sponsoring-ctf3") => simple_page("sponsoring-ctf3.html"),
-            GET("team") => simple_page("team.html"),
+            GET("team") => team::page(),
+            POST("team" / "captain") => team::transfer_captaincy(),
             GET("team" / "create") => simple_page("create-team.html"),
             POST("team" / "create") => team::create(),
+            POST("team" / "division") => team::set_division(),
             GET("team" / "join") => simple_page("join-team.html"),
             POST("team" / "join") => team::join(),
+            POST("team" / "invite") => team::invite(),
+            POST("team" / "invite" / "revoke") => team::revoke_invite(),
+            POST("team" / "join-code") => team::regenerate_join_code(),
+            POST("team" / "kick") => team::kick(),
+            POST("team" / "leave") => team::leave(),
+            POST("team" / "profile") => team::update_profile(),
+            GET("teams") => team::list(),
+            GET("teams") => team::public_page(),
+            GET("tickets") => tickets::list(),
+            POST("tickets") => tickets::open(),
+            GET("tickets") => tickets::page(),
+            POST("tickets" / "reply") => tickets::reply(),
+        })
+        .and(auth::opt_new_access_token())
+        .map(|reply, access: Option<auth::NewAccessToken>| match access {
+            Some(auth::NewAccessToken(access)) => {
+                Either::Left(warp::reply::with_header(reply, SET_COOKIE, access))
+            }
+            None => Either::Right(reply),
         })
         .boxed()
 }
@@ -97,6 +187,7 @@ fn statics() -> impl Clone + Filter<Extract = (Response<&'static [u8]>,), Error
                     .and_then(|s| s.to_str());
                 let ct = match ext {
                     Some("css") => "text/css",
+                    Some("js") => "application/javascript",
                     Some("txt") => "text/plain; charset=utf-8",
                     Some("woff2") => "font/woff2",
                     _ => {
@@ -117,9 +208,11 @@ fn simple_page(name: &'static str) -> Resp!() {
         .and(auth::opt_auth())
         .and(auth::opt_team())
         .and(auth::opt_team_members())
-        .and_then(move |me, team, team_members| {
+        .and(auth::opt_csrf_token())
+        .and_then(move |me: Option<User>, team, team_members, csrf| {
             let data = json!({
-                "me": me,
+                "csrf": csrf,
+                "me": me.as_ref().map(User::for_page),
                 "team": team,
                 "team_members": team_members
             });
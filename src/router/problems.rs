@@ -0,0 +1,98 @@
+//! The problems, and submitting flags for them.
+
+use crate::{
+    dal::{Events, DB},
+    logic,
+    router::{
+        auth,
+        util::{format, redirect, FilterExt, FutureExt},
+    },
+    schema::User,
+    view::render_as,
+};
+use futures::Future;
+use percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use serde_derive::Deserialize;
+use serde_json::json;
+use warp::{path, Filter};
+
+/// The list of problems, marking the ones the user's team has solved.
+pub fn list() -> Resp!() {
+    warp::path::end()
+        .and(warp::ext::get::<User>())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|me: User, csrf, db, format| {
+            logic::list_problems(db, me.team)
+                .err_to_rejection()
+                .and_then(move |problems| {
+                    let data = json!({ "csrf": csrf, "me": me.for_page(), "problems": problems });
+                    render_as(format, "problems.html", data)
+                })
+        })
+        .boxed()
+}
+
+/// The page for a problem, with the announcements about it and a form for submitting its flag.
+pub fn page() -> Resp!() {
+    path!(String)
+        .and(warp::path::end())
+        .and(warp::ext::get::<User>())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|name: String, me: User, csrf, db: DB, format| {
+            logic::get_problem(db.clone(), name.clone(), me.team)
+                .join(logic::list_problem_announcements(db, name))
+                .err_to_rejection()
+                .and_then(move |(problem, announcements)| {
+                    let announcements = announcements
+                        .into_iter()
+                        .map(|announcement| {
+                            json!({
+                                "body": announcement.body,
+                                "created": announcement.created.to_rfc2822(),
+                                "edited": announcement.edited.map(|at| at.to_rfc2822()),
+                                "id": announcement.id,
+                                "title": announcement.title
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    let data = json!({
+                        "announcements": announcements,
+                        "csrf": csrf,
+                        "me": me.for_page(),
+                        "problem": problem
+                    });
+                    render_as(format, "problem.html", data)
+                })
+        })
+        .recover_with_template("problem-error.html")
+}
+
+/// The route for submitting a flag.
+pub fn submit() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        problem: String,
+        flag: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::csrf_form())
+        .and_then(|me: User, db, events, form: Form| {
+            let location = format!(
+                "/problems/{}",
+                utf8_percent_encode(&form.problem, PATH_SEGMENT_ENCODE_SET)
+            );
+            logic::submit_flag(db, events, me.id, form.problem, form.flag)
+                .and_then(move |_| redirect(&location))
+                .err_to_rejection()
+        })
+        .recover_with_template("problem-error.html")
+}
@@ -0,0 +1,63 @@
+//! The scoreboard.
+
+use crate::{
+    dal::DB,
+    logic,
+    router::{
+        auth,
+        util::{format, FutureExt},
+    },
+    schema::User,
+    view::{render_as, Format},
+};
+use futures::Future;
+use serde_json::json;
+use warp::{http::Response, path, Filter, Rejection};
+
+/// The scoreboard of every team.
+pub fn page() -> Resp!() {
+    warp::path::end()
+        .and(auth::opt_auth())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|me: Option<User>, csrf, db, format| render(db, None, me, csrf, format))
+        .boxed()
+}
+
+/// The scoreboard of the teams in a division.
+pub fn division_page() -> Resp!() {
+    path!(String)
+        .and(warp::path::end())
+        .and(auth::opt_auth())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|name, me: Option<User>, csrf, db, format| {
+            render(db, Some(name), me, csrf, format)
+        })
+        .boxed()
+}
+
+/// Renders a scoreboard, with links to the scoreboards of the other divisions.
+fn render(
+    db: DB,
+    division: Option<String>,
+    me: Option<User>,
+    csrf: Option<auth::CsrfToken>,
+    format: Format,
+) -> impl Future<Item = Response<String>, Error = Rejection> {
+    logic::get_scoreboard(db.clone(), division)
+        .join(logic::list_divisions(db))
+        .err_to_rejection()
+        .and_then(move |((division, standings), divisions)| {
+            let data = json!({
+                "csrf": csrf,
+                "division": division,
+                "divisions": divisions,
+                "me": me.as_ref().map(User::for_page),
+                "standings": standings
+            });
+            render_as(format, "scoreboard.html", data)
+        })
+}
@@ -1,22 +1,286 @@
 use crate::{
-    dal::DB,
+    dal::{Events, Mailer, DB},
     logic,
-    router::util::{FilterExt, FutureExt},
-    schema::User,
+    router::{
+        auth,
+        util::{constraint_name, format, redirect, FilterExt, FutureExt},
+    },
+    schema::{Config, ErrorKind, Team, TeamMember, TeamProfile, User, UserError},
+    view::{render_as, render_html, render_score_svg, Format},
+};
+use chrono::Duration;
+use failure::{Error, Fallible};
+use futures::{
+    future::{ok, result, Either},
+    Future,
 };
-use failure::{Compat, Error};
-use futures::Future;
 use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
 use uuid::Uuid;
-use warp::{
-    filters::body::BodyDeserializeError,
-    http::{header::LOCATION, Response, StatusCode},
-    Filter,
-};
+use warp::{path, Filter};
 
-/// A wrapper type for a team's member's names.
+/// A wrapper type for a team's members.
 #[derive(Clone, Debug, Serialize)]
-pub struct TeamMembers(pub Vec<String>);
+pub struct TeamMembers(pub Vec<TeamMember>);
+
+/// The page for the user's team.
+pub fn page() -> Resp!() {
+    warp::path::end()
+        .and(auth::opt_auth())
+        .and(auth::opt_team())
+        .and(auth::opt_team_members())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(
+            |me: Option<User>,
+             team: Option<Team>,
+             team_members: Option<Vec<TeamMember>>,
+             csrf,
+             db: DB,
+             format: Format| {
+                let is_captain = match (&me, &team) {
+                    (Some(me), Some(team)) => team.captain == Some(me.id),
+                    _ => false,
+                };
+                let invites = match team {
+                    Some(ref team) if is_captain => {
+                        Either::A(logic::team_invites(db.clone(), team.id))
+                    }
+                    _ => Either::B(ok(Vec::new())),
+                };
+                let divisions = logic::list_divisions(db);
+                invites
+                    .join(divisions)
+                    .err_to_rejection()
+                    .and_then(move |(invites, divisions)| {
+                        let join_code = team.as_ref().map(|team| {
+                            json!({
+                                "code": team.join_code,
+                                "expires": team.join_code_expires.map(|e| e.to_rfc2822()),
+                                "uses": team.join_code_uses,
+                            })
+                        });
+                        let invites = invites
+                            .into_iter()
+                            .map(|invite| {
+                                json!({
+                                    "email": invite.email,
+                                    "expires": invite.expires.to_rfc2822(),
+                                    "id": invite.id,
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        let data = json!({
+                            "csrf": csrf,
+                            "divisions": divisions,
+                            "invites": invites,
+                            "is_captain": is_captain,
+                            "join_code": join_code,
+                            "me": me.as_ref().map(User::for_page),
+                            "team": team,
+                            "team_members": team_members
+                        });
+                        render_as(format, "team.html", data)
+                    })
+            },
+        )
+        .boxed()
+}
+
+/// The list of every division.
+pub fn list_divisions() -> Resp!() {
+    warp::path::end()
+        .and(auth::opt_auth())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|me: Option<User>, csrf, db, format| {
+            logic::list_divisions(db)
+                .err_to_rejection()
+                .and_then(move |divisions| {
+                    let data = json!({
+                        "csrf": csrf,
+                        "divisions": divisions,
+                        "me": me.as_ref().map(User::for_page)
+                    });
+                    render_as(format, "divisions.html", data)
+                })
+        })
+        .boxed()
+}
+
+/// The page for a division, listing the teams in it.
+pub fn division_page() -> Resp!() {
+    path!(String)
+        .and(warp::path::end())
+        .and(auth::opt_auth())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|name, me: Option<User>, csrf, db, format| {
+            logic::get_division_teams(db, name)
+                .err_to_rejection()
+                .and_then(move |(division, teams)| {
+                    let data = json!({
+                        "csrf": csrf,
+                        "division": division,
+                        "me": me.as_ref().map(User::for_page),
+                        "teams": teams
+                    });
+                    render_as(format, "division.html", data)
+                })
+        })
+        .boxed()
+}
+
+/// The list of every team.
+pub fn list() -> Resp!() {
+    warp::path::end()
+        .and(auth::opt_auth())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|me: Option<User>, csrf, db, format| {
+            logic::list_teams(db)
+                .err_to_rejection()
+                .and_then(move |teams| {
+                    let teams = teams
+                        .into_iter()
+                        .map(|(team, members)| json!({ "team": team, "members": members }))
+                        .collect::<Vec<_>>();
+                    render_as(
+                        format,
+                        "teams.html",
+                        json!({
+                            "csrf": csrf,
+                            "me": me.as_ref().map(User::for_page),
+                            "teams": teams
+                        }),
+                    )
+                })
+        })
+        .boxed()
+}
+
+/// A team's public page, with its solves grouped by category and its score over time. This must
+/// never show the team's join code.
+pub fn public_page() -> Resp!() {
+    path!(String)
+        .and(warp::path::end())
+        .and(auth::opt_auth())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|name, me: Option<User>, csrf, db, format| {
+            logic::get_public_team(db, name)
+                .err_to_rejection()
+                .and_then(move |(team, members, division, solves)| {
+                    let mut categories = BTreeMap::new();
+                    for solve in &solves {
+                        categories
+                            .entry(solve.category.as_str())
+                            .or_insert_with(Vec::new)
+                            .push(solve);
+                    }
+                    let categories = categories
+                        .into_iter()
+                        .map(|(name, solves)| {
+                            let points = solves.iter().map(|solve| solve.points).sum::<i32>();
+                            let solves = solves
+                                .into_iter()
+                                .map(|solve| {
+                                    json!({
+                                        "points": solve.points,
+                                        "problem": solve.problem,
+                                        "solved": solve.solved.to_rfc2822(),
+                                        "user": solve.user
+                                    })
+                                })
+                                .collect::<Vec<_>>();
+                            json!({ "name": name, "points": points, "solves": solves })
+                        })
+                        .collect::<Vec<_>>();
+                    let mut score = 0;
+                    let series = solves
+                        .iter()
+                        .map(|solve| {
+                            score += solve.points;
+                            json!({ "at": solve.solved, "score": score })
+                        })
+                        .collect::<Vec<_>>();
+                    let data = json!({
+                        "categories": categories,
+                        "csrf": csrf,
+                        "division": division,
+                        "me": me.as_ref().map(User::for_page),
+                        "members": members,
+                        "score": score,
+                        "score_svg": render_score_svg(&solves),
+                        "series": series,
+                        "team": team
+                    });
+                    render_as(format, "team-public.html", data)
+                })
+        })
+        .boxed()
+}
+
+/// The page for accepting an invite, linked to from the invite mail with the invite's token.
+pub fn invite_page() -> Resp!() {
+    path!(Uuid)
+        .and(warp::path::end())
+        .and(auth::opt_auth())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and_then(|token, me: Option<User>, csrf, db| {
+            logic::get_invite(db, token)
+                .err_to_rejection()
+                .and_then(move |(team, registered)| {
+                    let data = json!({
+                        "csrf": csrf,
+                        "me": me.as_ref().map(User::for_page),
+                        "registered": registered,
+                        "team": team
+                    });
+                    render_html("invite.html", data)
+                })
+        })
+        .recover_with_template("invite-error.html")
+}
+
+/// The route for accepting an invite, registering and logging in the invitee if they don't have
+/// an account yet. Invitees who do have one must log in first.
+pub fn accept_invite() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        username: Option<String>,
+    }
+
+    path!(Uuid)
+        .and(warp::path::end())
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(auth::opt_auth())
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(auth::client_info())
+        .and(auth::csrf_form())
+        .and_then(
+            |token, me: Option<User>, config: Config, db: DB, events, client, form: Form| {
+                let username = form.username.filter(|name| !name.is_empty());
+                let user = me.map(|me| me.id);
+                logic::accept_invite(db, events, &config, token, user, username, client)
+                    .and_then(move |token| match token {
+                        Some(token) => auth::set_auth_cookie(token, &config, "/team"),
+                        None => redirect("/team"),
+                    })
+                    .err_to_rejection()
+            },
+        )
+        .recover_with_template("invite-error.html")
+}
 
 /// The route for creating a team.
 pub fn create() -> Resp!() {
@@ -27,80 +291,224 @@ pub fn create() -> Resp!() {
 
     warp::body::content_length_limit(2 * 1024)
         .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
         .and(warp::ext::get::<User>())
-        .and(warp::body::form())
-        .and_then(|db: DB, user: User, form: Form| {
-            logic::create_team(db, user.id, form.name)
-                .and_then(|()| {
-                    Response::builder()
-                        .header(LOCATION, "/team")
-                        .status(StatusCode::FOUND)
-                        .body("")
-                        .map_err(Error::from)
-                })
+        .and(auth::csrf_form())
+        .and_then(|db: DB, events, user: User, form: Form| {
+            logic::create_team(db, events, user.id, form.name)
+                .and_then(|()| redirect("/team"))
                 .err_to_rejection()
         })
-        .recover_with_template("create-team.html", |err: &Compat<Error>| {
-            let err = err.to_string();
-            match coerce!(&err => &str) {
-                r#"new row for relation "teams" violates check constraint "name_fmt""# => Some((
-                    StatusCode::BAD_REQUEST,
-                    vec!["bad_name"],
-                    vec!["Your team name must contain only ASCII letters and digits"],
-                )),
-                r#"new row for relation "teams" violates check constraint "name_len""# => Some((
-                    StatusCode::BAD_REQUEST,
-                    vec!["bad_name"],
-                    vec!["Your team name must be at least 3 characters"],
-                )),
-                r#"duplicate key value violates unique constraint "teams_name_key""# => Some((
-                    StatusCode::BAD_REQUEST,
-                    vec!["bad_name"],
-                    vec!["This team name is already taken"],
-                )),
-                _ => None,
-            }
+        .recover_with_fields("create-team.html", |err| match constraint_name(err) {
+            Some("name_fmt") | Some("name_len") | Some("teams_name_key") => vec!["bad_name"],
+            _ => vec![],
         })
 }
 
+/// The route for the captain to invite someone to their team by email.
+pub fn invite() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        email: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Mailer>())
+        .and(warp::ext::get::<User>())
+        .and(auth::csrf_form())
+        .and_then(|db: DB, mailer: Mailer, user: User, form: Form| {
+            logic::invite_to_team(db, mailer, user.id, form.email)
+                .and_then(|()| redirect("/team"))
+                .err_to_rejection()
+        })
+        .recover_with_template("team-error.html")
+}
+
 /// The route for joining a team.
 pub fn join() -> Resp!() {
     #[derive(Debug, Deserialize)]
     struct Form {
-        join_code: Uuid,
+        #[serde(default)]
+        join_code: String,
     }
 
     warp::body::content_length_limit(2 * 1024)
+        .and(warp::ext::get::<Config>())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::ext::get::<User>())
+        .and(auth::csrf_form())
+        .and_then(|config: Config, db: DB, events, user: User, form: Form| {
+            logic::join_team(db, events, &config, user.id, form.join_code)
+                .and_then(|()| redirect("/team"))
+                .err_to_rejection()
+        })
+        .recover_with_fields("join-team.html", |err| {
+            // A code that doesn't belong to any team is the only thing that can't be found.
+            match err.downcast_ref::<UserError>() {
+                Some(err) if err.kind == ErrorKind::NotFound => vec!["bad_join_code"],
+                _ => vec![],
+            }
+        })
+}
+
+/// The route for the captain to remove a member from their team.
+pub fn kick() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        member: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::ext::get::<User>())
+        .and(auth::csrf_form())
+        .and_then(|db: DB, events, user: User, form: Form| {
+            logic::kick_team_member(db, events, user.id, form.member)
+                .and_then(|()| redirect("/team"))
+                .err_to_rejection()
+        })
+        .recover_with_template("team-error.html")
+}
+
+/// The route for leaving a team.
+pub fn leave() -> Resp!() {
+    warp::path::end()
+        .and(auth::check_csrf())
+        .and(warp::ext::get::<Config>())
         .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
         .and(warp::ext::get::<User>())
-        .and(warp::body::form())
+        .and_then(|config: Config, db: DB, events, user: User| {
+            logic::leave_team(db, events, &config, user.id)
+                .and_then(|()| redirect("/"))
+                .err_to_rejection()
+        })
+        .recover_with_template("team-error.html")
+}
+
+/// The route for the captain to replace their team's join code.
+pub fn regenerate_join_code() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        expires_hours: String,
+        uses: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<User>())
+        .and(auth::csrf_form())
         .and_then(|db: DB, user: User, form: Form| {
-            logic::join_team(db, user.id, form.join_code)
-                .and_then(|()| {
-                    Response::builder()
-                        .header(LOCATION, "/team")
-                        .status(StatusCode::FOUND)
-                        .body("")
-                        .map_err(Error::from)
+            let limits = parse_limit(&form.expires_hours)
+                .and_then(|hours| Ok((hours.map(Duration::hours), parse_limit(&form.uses)?)));
+            result(limits)
+                .and_then(move |(expires_in, uses)| {
+                    let uses = uses.map(|uses| uses as i32);
+                    logic::regenerate_join_code(db, user.id, expires_in, uses)
                 })
+                .and_then(|_| redirect("/team"))
                 .err_to_rejection()
         })
-        .recover_with_template("join-team.html", |_: &BodyDeserializeError| {
-            Some((
-                StatusCode::BAD_REQUEST,
-                vec!["bad_join_code"],
-                vec!["Your join code was invalid."],
-            ))
-        })
-        .recover_with_template("join-team.html", |err: &Compat<Error>| {
-            let err = err.to_string();
-            match coerce!(&err => &str) {
-                "The team is full." => Some((
-                    StatusCode::BAD_REQUEST,
-                    vec!["bad_join_code"],
-                    vec!["The team is full."],
-                )),
-                _ => None,
-            }
+        .recover_with_template("team-error.html")
+}
+
+/// The route for the captain to revoke an invite to their team.
+pub fn revoke_invite() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        invite: Uuid,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<User>())
+        .and(auth::csrf_form())
+        .and_then(|db: DB, user: User, form: Form| {
+            logic::revoke_invite(db, user.id, form.invite)
+                .and_then(|()| redirect("/team"))
+                .err_to_rejection()
+        })
+        .recover_with_template("team-error.html")
+}
+
+/// The route for the captain to choose their team's division.
+pub fn set_division() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        division: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::ext::get::<User>())
+        .and(auth::csrf_form())
+        .and_then(|db: DB, events, user: User, form: Form| {
+            let division = match form.division.as_str() {
+                "" => Ok(None),
+                division => division.parse().map(Some).map_err(Error::from),
+            };
+            result(division)
+                .and_then(move |division| logic::set_team_division(db, events, user.id, division))
+                .and_then(|()| redirect("/team"))
+                .err_to_rejection()
         })
+        .recover_with_template("team-error.html")
+}
+
+/// The route for the captain to edit their team's public profile.
+pub fn update_profile() -> Resp!() {
+    warp::path::end()
+        .and(warp::body::content_length_limit(4 * 1024))
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<User>())
+        .and(auth::csrf_form())
+        .and_then(|db: DB, user: User, profile: TeamProfile| {
+            logic::update_team_profile(db, user.id, profile)
+                .and_then(|()| redirect("/team"))
+                .err_to_rejection()
+        })
+        .recover_with_template("team-error.html")
+}
+
+/// The route for the captain to make another member the captain.
+pub fn transfer_captaincy() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        member: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(2 * 1024))
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<User>())
+        .and(auth::csrf_form())
+        .and_then(|db: DB, user: User, form: Form| {
+            logic::transfer_captaincy(db, user.id, form.member)
+                .and_then(|()| redirect("/team"))
+                .err_to_rejection()
+        })
+        .recover_with_template("team-error.html")
+}
+
+/// Parses an optional limit from a form field, where an empty field means no limit.
+fn parse_limit(s: &str) -> Fallible<Option<i64>> {
+    match s.trim() {
+        "" => Ok(None),
+        s => match s.parse() {
+            Ok(n) if 1 <= n && n <= 10_000 => Ok(Some(n)),
+            _ => user_bail!(
+                BadRequest,
+                "Limits must be whole numbers between 1 and 10000."
+            ),
+        },
+    }
 }
@@ -0,0 +1,157 @@
+//! Support tickets, which teams open to ask the mods questions. The mods' side is in `admin`.
+
+use crate::{
+    dal::{Events, Mailer, DB},
+    logic,
+    router::{
+        auth,
+        util::{format, parse_optional_id, redirect, FilterExt, FutureExt},
+    },
+    schema::{Team, Ticket, TicketMessage, User},
+    view::render_as,
+};
+use futures::{
+    future::{ok, result, Either},
+    Future,
+};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use warp::{path, Filter};
+
+/// The list of the user's team's tickets, with a form for opening a new one, which can be about
+/// one of the problems.
+pub fn list() -> Resp!() {
+    warp::path::end()
+        .and(warp::ext::get::<User>())
+        .and(auth::opt_team())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|me: User, team: Option<Team>, csrf, db: DB, format| {
+            let tickets = match team {
+                Some(ref team) => Either::A(logic::list_team_tickets(db.clone(), team.id)),
+                None => Either::B(ok(Vec::new())),
+            };
+            tickets
+                .join(logic::list_problems(db, team.as_ref().map(|team| team.id)))
+                .err_to_rejection()
+                .and_then(move |(tickets, problems)| {
+                    let problems = problems
+                        .into_iter()
+                        .map(|listing| {
+                            json!({ "id": listing.problem.id, "name": listing.problem.name })
+                        })
+                        .collect::<Vec<_>>();
+                    let tickets = tickets.iter().map(ticket_json).collect::<Vec<_>>();
+                    let data = json!({
+                        "csrf": csrf,
+                        "me": me.for_page(),
+                        "problems": problems,
+                        "team": team,
+                        "tickets": tickets
+                    });
+                    render_as(format, "tickets.html", data)
+                })
+        })
+        .boxed()
+}
+
+/// The route for opening a ticket.
+pub fn open() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        subject: String,
+        body: String,
+        problem: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(8 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::ext::get::<Mailer>())
+        .and(auth::csrf_form())
+        .and_then(|me: User, db, events, mailer, form: Form| {
+            result(parse_optional_id(&form.problem))
+                .and_then(move |problem| {
+                    logic::open_ticket(db, events, mailer, me.id, form.subject, form.body, problem)
+                })
+                .and_then(|ticket| redirect(&format!("/tickets/{}", ticket)))
+                .err_to_rejection()
+        })
+        .recover_with_template("ticket-error.html")
+}
+
+/// The page for one of the user's team's tickets.
+pub fn page() -> Resp!() {
+    path!(i32)
+        .and(warp::path::end())
+        .and(warp::ext::get::<User>())
+        .and(auth::team())
+        .and(auth::opt_csrf_token())
+        .and(warp::ext::get::<DB>())
+        .and(format())
+        .and_then(|ticket, me: User, team: Team, csrf, db, format| {
+            logic::get_team_ticket(db, team.id, ticket)
+                .err_to_rejection()
+                .and_then(move |(ticket, messages)| {
+                    let data = json!({
+                        "as_mod": false,
+                        "csrf": csrf,
+                        "me": me.for_page(),
+                        "messages": messages.iter().map(message_json).collect::<Vec<_>>(),
+                        "team": team.name,
+                        "ticket": ticket_json(&ticket)
+                    });
+                    render_as(format, "ticket.html", data)
+                })
+        })
+        .recover_with_template("ticket-error.html")
+}
+
+/// The route for replying to a ticket.
+pub fn reply() -> Resp!() {
+    #[derive(Debug, Deserialize)]
+    struct Form {
+        ticket: i32,
+        body: String,
+    }
+
+    warp::path::end()
+        .and(warp::body::content_length_limit(8 * 1024))
+        .and(warp::ext::get::<User>())
+        .and(warp::ext::get::<DB>())
+        .and(warp::ext::get::<Events>())
+        .and(warp::ext::get::<Mailer>())
+        .and(auth::csrf_form())
+        .and_then(|me: User, db, events, mailer, form: Form| {
+            let location = format!("/tickets/{}", form.ticket);
+            logic::reply_to_ticket(db, events, mailer, me.id, form.ticket, form.body)
+                .and_then(move |()| redirect(&location))
+                .err_to_rejection()
+        })
+        .recover_with_template("ticket-error.html")
+}
+
+/// Renders a ticket message for a template.
+pub fn message_json(message: &TicketMessage) -> Value {
+    json!({
+        "announcement": message.announcement,
+        "author": message.author,
+        "body": message.body,
+        "from_mod": message.from_mod,
+        "sent": message.sent.to_rfc2822()
+    })
+}
+
+/// Renders a ticket for a template.
+pub fn ticket_json(ticket: &Ticket) -> Value {
+    json!({
+        "closed": ticket.closed.map(|at| at.to_rfc2822()),
+        "created": ticket.created.to_rfc2822(),
+        "id": ticket.id,
+        "problem": ticket.problem,
+        "subject": ticket.subject
+    })
+}
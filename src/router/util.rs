@@ -1,16 +1,26 @@
-use crate::{router::auth, view::render_html};
+use crate::{
+    router::auth,
+    schema::{ErrorKind, User, UserError},
+    view::{render_html, Format},
+};
+use diesel::result::Error as DieselError;
 use either::Either;
-use failure::Error;
+use failure::{Compat, Error};
 use futures::{Async, Future};
 use maplit::hashmap;
 use serde_json::Value;
-use std::error::Error as StdError;
 use warp::{
     filters::BoxedFilter,
-    http::{Response, StatusCode},
+    http::{
+        header::{LOCATION, SET_COOKIE},
+        Response, StatusCode,
+    },
     Filter, Rejection, Reply,
 };
 
+/// The `Set-Cookie` value that discards the user's access token.
+pub const CLEAR_ACCESS_COOKIE: &str = "access=; Max-Age=0; Path=/; HttpOnly; SameSite=Lax";
+
 /// The type of a responder. Since `impl Trait` can't be used in `type` items, this magics one up.
 macro_rules! Resp {
     () => { warp::filters::BoxedFilter<(impl warp::Reply,)> };
@@ -33,19 +43,22 @@ macro_rules! route_any {
 
 /// An extension trait for Filters.
 pub trait FilterExt<T>: Sized {
-    /// An error-handling function. The argument function should return keys to set to true.
-    fn recover_with_template<E, F>(
+    /// Renders errors the user caused with the given template, flashing the error's message with
+    /// the status it calls for. Other errors are passed on.
+    fn recover_with_template(
+        self,
+        template: &'static str,
+    ) -> BoxedFilter<(Either<T, Response<String>>,)>;
+
+    /// Like `recover_with_template`, but also marks the form fields an error is about. The
+    /// argument function should return keys to set to true.
+    fn recover_with_fields<F>(
         self,
         template: &'static str,
-        func: F,
+        fields: F,
     ) -> BoxedFilter<(Either<T, Response<String>>,)>
     where
-        E: 'static + StdError,
-        F: 'static
-            + Clone
-            + Fn(&E) -> Option<(StatusCode, Vec<&'static str>, Vec<&'static str>)>
-            + Send
-            + Sync;
+        F: 'static + Clone + Fn(&Error) -> Vec<&'static str> + Send + Sync;
 }
 
 impl<Fi, T> FilterExt<T> for Fi
@@ -53,45 +66,53 @@ where
     Fi: 'static + Filter<Extract = (T,), Error = Rejection> + Send + Sync,
     T: 'static + Reply + Send + Sync,
 {
-    fn recover_with_template<E, F>(
+    fn recover_with_template(
         self,
         template: &'static str,
-        func: F,
+    ) -> BoxedFilter<(Either<T, Response<String>>,)> {
+        self.recover_with_fields(template, |_| vec![])
+    }
+
+    fn recover_with_fields<F>(
+        self,
+        template: &'static str,
+        fields: F,
     ) -> BoxedFilter<(Either<T, Response<String>>,)>
     where
-        E: 'static + StdError,
-        F: 'static
-            + Clone
-            + Fn(&E) -> Option<(StatusCode, Vec<&'static str>, Vec<&'static str>)>
-            + Send
-            + Sync,
+        F: 'static + Clone + Fn(&Error) -> Vec<&'static str> + Send + Sync,
     {
         self.map(Ok)
             .recover(|e| Ok(Err(e)))
             .unify()
             .and(auth::opt_auth())
-            .and_then(move |res: Result<T, Rejection>, me| match res {
-                Ok(r) => Ok(Either::Left(r)),
-                Err(r) => match r.find_cause() {
-                    Some(err) => match func(err) {
-                        Some((status, codes, flashes)) => {
-                            let mut hm = hashmap! {
-                                "flashes" => serde_json::to_value(flashes).unwrap(),
-                                "me" => serde_json::to_value(me).unwrap(),
-                            };
-                            for code in codes {
-                                let _ = hm.insert(code, Value::Bool(true));
-                            }
-                            render_html(template, hm).map(|mut r| {
-                                *r.status_mut() = status;
-                                Either::Right(r)
-                            })
+            .and(auth::opt_csrf_token())
+            .and_then(
+                move |res: Result<T, Rejection>, me: Option<User>, csrf| match res {
+                    Ok(r) => Ok(Either::Left(r)),
+                    Err(r) => {
+                        let err = match r.find_cause::<Compat<Error>>() {
+                            Some(err) => err.get_ref(),
+                            None => return Err(r),
+                        };
+                        let (status, flash) = match error_status(err) {
+                            Some(status) => status,
+                            None => return Err(r),
+                        };
+                        let mut hm = hashmap! {
+                            "csrf" => serde_json::to_value(csrf).unwrap(),
+                            "flashes" => serde_json::to_value(vec![flash]).unwrap(),
+                            "me" => serde_json::to_value(me.as_ref().map(User::for_page)).unwrap(),
+                        };
+                        for field in fields(err) {
+                            let _ = hm.insert(field, Value::Bool(true));
                         }
-                        None => Err(r),
-                    },
-                    None => Err(r),
+                        render_html(template, hm).map(|mut r| {
+                            *r.status_mut() = status;
+                            Either::Right(r)
+                        })
+                    }
                 },
-            })
+            )
             .boxed()
     }
 }
@@ -124,6 +145,120 @@ impl<F: Future<Error = Error>> Future for ErrToRejection<F> {
     }
 }
 
+/// Returns the name of the database constraint an error is for violating, if it is one.
+pub fn constraint_name(err: &Error) -> Option<&str> {
+    match err.downcast_ref::<DieselError>()? {
+        DieselError::DatabaseError(_, info) => info.constraint_name(),
+        _ => None,
+    }
+}
+
+/// Picks the status and message an error from the logic is shown with. Errors the user caused
+/// are `UserError`s, whose kind gives the status; the database's own errors are recognized by the
+/// constraint they violate. Returns `None` for unexpected errors.
+pub fn error_status(err: &Error) -> Option<(StatusCode, String)> {
+    if let Some(err) = err.downcast_ref::<UserError>() {
+        let status = match err.kind {
+            ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorKind::Forbidden => StatusCode::FORBIDDEN,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+        };
+        return Some((status, err.message.clone()));
+    }
+
+    let info = match err.downcast_ref::<DieselError>()? {
+        DieselError::NotFound => return Some((StatusCode::NOT_FOUND, "Not found.".to_string())),
+        DieselError::DatabaseError(_, info) => info,
+        _ => return None,
+    };
+    let message = match (info.table_name(), info.constraint_name()) {
+        (Some("announcements"), Some("announcements_problemid_fkey")) => {
+            "That problem doesn't exist."
+        }
+        (Some("attachments"), Some("attachments_problemid_name_key")) => {
+            "A problem's attachments must have different names."
+        }
+        (Some("attachments"), Some("name_fmt")) => {
+            "Attachment names can't contain slashes or spaces, or start with a dot."
+        }
+        (Some("attachments"), Some("url_fmt")) => {
+            "Attachment links must start with http:// or https://."
+        }
+        (Some("divisions"), Some("divisions_name_key")) => {
+            "There's already a division with that name."
+        }
+        (Some("email_changes"), Some("email_fmt"))
+        | (Some("email_changes"), Some("email_len"))
+        | (Some("invites"), Some("email_fmt"))
+        | (Some("invites"), Some("email_len"))
+        | (Some("users"), Some("email_fmt"))
+        | (Some("users"), Some("email_len")) => "That doesn't look like an email address.",
+        (Some("passkeys"), Some("passkeys_credential_id_key")) => {
+            "That passkey is already registered."
+        }
+        (Some("problems"), Some("flag_len")) => "Problems need a flag.",
+        (Some("problems"), Some("name_fmt")) => {
+            "Problem names must contain only ASCII letters, digits, and dashes."
+        }
+        (Some("problems"), Some("points_positive")) => "Problems must be worth some points.",
+        (Some("problems"), Some("problems_name_key")) => {
+            "There's already a problem with that name."
+        }
+        (Some("teams"), Some("name_fmt")) => {
+            "Team names must contain only ASCII letters and digits."
+        }
+        (Some("teams"), Some("name_len")) => "Team names must be at least 3 characters.",
+        (Some("teams"), Some("teams_name_key")) => "This team name is already taken.",
+        (Some("teams"), Some("website_fmt")) => "Websites must start with http:// or https://.",
+        (Some("users"), Some("name_fmt")) => {
+            "Usernames must contain only ASCII letters and digits."
+        }
+        (Some("users"), Some("name_len")) => "Usernames must be at least 3 characters.",
+        (Some("users"), Some("users_email_key")) => {
+            "That email address is already in use by another account."
+        }
+        (Some("users"), Some("users_name_key")) => "This username is already taken.",
+        // Postgres doesn't say which column was too long, so there's no better way to tell.
+        _ if info
+            .message()
+            .starts_with("value too long for type character varying") =>
+        {
+            "That's too long."
+        }
+        _ => return None,
+    };
+    Some((StatusCode::BAD_REQUEST, message.to_string()))
+}
+
+/// A filter that picks the format to render a response in from the `Accept` header.
+pub fn format() -> impl Clone + Filter<Extract = (Format,), Error = Rejection> {
+    warp::header::optional::<String>("accept")
+        .map(|accept: Option<String>| Format::from_accept(accept.as_ref().map(|s| s.as_str())))
+}
+
+/// Parses the ID chosen in a form's select that can be left empty, like the one for picking the
+/// problem an announcement is about.
+pub fn parse_optional_id(id: &str) -> Result<Option<i32>, Error> {
+    match id {
+        "" => Ok(None),
+        id => id.parse().map(Some).map_err(Error::from),
+    }
+}
+
+/// Creates a response that redirects to the given location. Since redirects follow changes the
+/// user made, this also discards their access token, so the next page they see reflects the
+/// change.
+pub fn redirect(location: &str) -> Result<Response<String>, Error> {
+    Response::builder()
+        .header(LOCATION, location)
+        .header(SET_COOKIE, CLEAR_ACCESS_COOKIE)
+        .status(StatusCode::FOUND)
+        .body(String::new())
+        .map_err(Error::from)
+}
+
 /// Inserts a value into the request extensions.
 pub fn set<T: 'static + Clone + Send + Sync>(
     t: T,
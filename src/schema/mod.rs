@@ -3,9 +3,562 @@
 //! > Schema defines the plain old data types that views operate on. Notably, the schema module has
 //! > no knowledge of the database, nor any dependencies on any of the rest of the system.
 
-use serde_derive::Serialize;
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    error::Error as StdError,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    net::IpAddr,
+};
 use uuid::Uuid;
 
+/// An announcement posted by the admins, such as a correction to a problem.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct Announcement {
+    /// The announcement's database ID.
+    pub id: i32,
+
+    /// The announcement's title.
+    pub title: String,
+
+    /// The announcement's text.
+    pub body: String,
+
+    /// When the announcement was posted.
+    pub created: DateTime<Utc>,
+
+    /// When the announcement was last edited, if ever.
+    pub edited: Option<DateTime<Utc>>,
+
+    /// The name of the problem the announcement is about, if any.
+    pub problem: Option<String>,
+}
+
+/// An API token, which scripts can use to act as a user.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct ApiToken {
+    /// The token's database ID.
+    pub id: i32,
+
+    /// The name the user gave the token.
+    pub name: String,
+
+    /// When the token was created.
+    pub created: DateTime<Utc>,
+
+    /// When the token was last used, if ever.
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+/// A link to a file players need for a problem.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct Attachment {
+    /// The file's name, which it's saved as when downloaded.
+    pub name: String,
+
+    /// Where the file can be downloaded from.
+    pub url: String,
+}
+
+/// Information about the client a request came from.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    /// The client's IP address, if known.
+    pub ip: Option<String>,
+
+    /// The client's `User-Agent` header, if it sent one.
+    pub user_agent: Option<String>,
+}
+
+/// Settings for the event.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// How long an access token is trusted for before the session is checked again.
+    pub access_token_lifetime: Duration,
+
+    /// The keys access tokens are signed with. New tokens are signed with the first; tokens
+    /// signed with any of them are accepted. If this is empty, access tokens aren't used.
+    pub jwt_keys: Vec<JwtKey>,
+
+    /// How long login links and codes work for.
+    pub login_link_lifetime: Duration,
+
+    /// How long a user must wait after being sent a login mail before another can be sent.
+    pub login_mail_cooldown: Duration,
+
+    /// How many login mails can be requested from one IP address per hour.
+    pub login_mails_per_ip: i64,
+
+    /// How many login mails a user can be sent per hour.
+    pub login_mails_per_user: i64,
+
+    /// The maximum number of members a team may have. Always at least 1.
+    pub max_team_size: i64,
+
+    /// The OpenID Connect provider users can log in with, if any.
+    pub oidc: Option<OidcConfig>,
+
+    /// Whether the authentication cookie is only sent over HTTPS.
+    pub secure_cookies: bool,
+
+    /// How long a user stays logged in for.
+    pub session_lifetime: Duration,
+
+    /// Whether users who haven't created or joined a team play on their own, implicitly-created,
+    /// personal team.
+    pub solo: bool,
+
+    /// The reverse proxies whose `X-Forwarded-For` headers are trusted to say which address a
+    /// request came from.
+    pub trusted_proxies: Vec<IpAddr>,
+
+    /// The origin pages are served from, which passkeys are checked against, e.g.
+    /// `https://ctf.acm.umn.edu`.
+    pub webauthn_origin: String,
+
+    /// The relying party ID passkeys are registered under, which is the host of
+    /// `webauthn_origin`.
+    pub webauthn_rp_id: String,
+}
+
+/// A division, which has its own scoreboard.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct Division {
+    /// The division's database ID.
+    pub id: i32,
+
+    /// The division's name.
+    pub name: String,
+
+    /// The email domains every member of a team in the division must have addresses at. If this
+    /// is empty, anyone may be on a team in the division.
+    pub email_domains: Vec<String>,
+}
+
+impl Division {
+    /// Returns whether a user with the given email address may be on a team in this division.
+    /// Subdomains of the division's email domains are allowed too.
+    pub fn admits(&self, email: &str) -> bool {
+        if self.email_domains.is_empty() {
+            return true;
+        }
+
+        let domain = match email.rfind('@') {
+            Some(i) => email[i + 1..].to_lowercase(),
+            None => return false,
+        };
+        self.email_domains.iter().any(|allowed| {
+            let allowed = allowed.to_lowercase();
+            domain == allowed || domain.ends_with(&format!(".{}", allowed))
+        })
+    }
+}
+
+/// What kind of mistake a `UserError` is, which the API picks its response's status from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// The request can't be done as asked, e.g. because a form was filled in wrong.
+    BadRequest,
+
+    /// The user isn't allowed to do that, e.g. because they aren't their team's captain.
+    Forbidden,
+
+    /// The thing the request is about doesn't exist, or isn't the user's.
+    NotFound,
+
+    /// The user has done this too often lately, and should try again later.
+    TooManyRequests,
+
+    /// The user needs to log in, or their credentials are wrong.
+    Unauthorized,
+}
+
+/// Something that happened which pages might want to update for, pushed to the clients listening
+/// to `/events/stream`. Events are serialized with their name as `type`, e.g.
+/// `{"type": "teams_changed"}`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Event {
+    /// An announcement was edited.
+    AnnouncementEdited {
+        /// The announcement's database ID.
+        id: i32,
+    },
+
+    /// An announcement was posted.
+    AnnouncementPosted {
+        /// The announcement's database ID.
+        id: i32,
+
+        /// The announcement's title.
+        title: String,
+
+        /// The name of the problem the announcement is about, if any.
+        problem: Option<String>,
+    },
+
+    /// A team solved a problem no other team had solved yet. This is published along with the
+    /// `ProblemSolved` event for the solve.
+    FirstBlood {
+        /// The problem's name.
+        problem: String,
+
+        /// The team's name.
+        team: String,
+    },
+
+    /// A team solved a problem.
+    ProblemSolved {
+        /// The problem's name.
+        problem: String,
+
+        /// The team's name.
+        team: String,
+    },
+
+    /// A problem was created or edited.
+    ProblemsChanged,
+
+    /// A team's points changed, because it solved a problem or a problem's points were edited.
+    ScoreboardChanged,
+
+    /// A team was created, renamed, disbanded, or changed members or divisions.
+    TeamsChanged,
+
+    /// A ticket was opened, replied to, or closed.
+    TicketsChanged,
+}
+
+/// An invitation to join a team, sent by email.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct Invite {
+    /// The invite's database ID.
+    pub id: Uuid,
+
+    /// The email address the invite was sent to.
+    pub email: String,
+
+    /// When the invite stops working.
+    pub expires: DateTime<Utc>,
+}
+
+/// A key for signing access tokens.
+#[derive(Clone)]
+pub struct JwtKey {
+    /// The key's ID, which tokens name the key they were signed with by.
+    pub id: String,
+
+    /// The secret used to sign tokens.
+    pub secret: Vec<u8>,
+}
+
+impl Debug for JwtKey {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("JwtKey")
+            .field("id", &self.id)
+            .field("secret", &"<redacted>")
+            .finish()
+    }
+}
+
+/// A JSON Web Key, as published by an OpenID Connect provider to verify the ID tokens it signs.
+/// Only the fields needed for RSA keys are kept.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Jwk {
+    /// The key's type. Only `RSA` keys are used.
+    pub kty: String,
+
+    /// The key's ID, which ID tokens name the key they were signed with by.
+    pub kid: Option<String>,
+
+    /// The modulus of an RSA key, as unpadded URL-safe base64.
+    pub n: Option<String>,
+
+    /// The public exponent of an RSA key, as unpadded URL-safe base64.
+    pub e: Option<String>,
+}
+
+/// Settings for logging in with an OpenID Connect provider.
+#[derive(Clone)]
+pub struct OidcConfig {
+    /// The provider's issuer URL, which its discovery document is found under.
+    pub issuer: String,
+
+    /// The client ID this site is registered with at the provider.
+    pub client_id: String,
+
+    /// The client secret this site is registered with at the provider.
+    pub client_secret: String,
+
+    /// The URL the provider sends users back to after they log in there.
+    pub redirect_url: String,
+
+    /// The provider's name, as shown on the login button.
+    pub name: String,
+}
+
+impl Debug for OidcConfig {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("OidcConfig")
+            .field("issuer", &self.issuer)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"<redacted>")
+            .field("redirect_url", &self.redirect_url)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// An identity at an OpenID Connect provider, as vouched for by an ID token.
+#[derive(Clone, Debug)]
+pub struct OidcIdentity {
+    /// The provider's identifier for the user, which never changes.
+    pub subject: String,
+
+    /// The user's email address, if the provider gave it.
+    pub email: Option<String>,
+
+    /// Whether the provider has verified that the email address is the user's.
+    pub email_verified: bool,
+
+    /// The username the user goes by at the provider, if it gave one.
+    pub preferred_username: Option<String>,
+}
+
+/// A passkey a user can log in with.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct Passkey {
+    /// The passkey's database ID.
+    pub id: i32,
+
+    /// The name the user gave the passkey.
+    pub name: String,
+
+    /// When the passkey was registered.
+    pub created: DateTime<Utc>,
+
+    /// When the passkey was last used to log in, if ever.
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+/// What the browser sends back when the user logs in with a passkey. The binary fields are
+/// unpadded URL-safe base64, and the challenge is read from the client data.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PasskeyAssertion {
+    /// The ID of the passkey's credential.
+    pub credential_id: String,
+
+    /// The client data JSON the browser had the authenticator sign.
+    pub client_data: String,
+
+    /// The authenticator data the authenticator signed.
+    pub authenticator_data: String,
+
+    /// The signature over the authenticator data and the client data's hash.
+    pub signature: String,
+}
+
+/// A passkey's credential, as needed to check a signature made with it.
+#[derive(Clone, Debug, Queryable)]
+pub struct PasskeyCredential {
+    /// The passkey's database ID.
+    pub id: i32,
+
+    /// The database ID of the user the passkey belongs to.
+    pub user: i32,
+
+    /// The COSE algorithm the passkey signs with.
+    pub algorithm: i32,
+
+    /// The passkey's public key, in the form `ring` checks signatures with.
+    pub public_key: Vec<u8>,
+
+    /// How many signatures the authenticator said it had made, the last time it was used.
+    pub sign_count: i64,
+}
+
+/// What the browser sends back when the user registers a passkey. The binary fields are unpadded
+/// URL-safe base64, and the challenge is read from the client data.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PasskeyRegistration {
+    /// The name the user gave the passkey.
+    pub name: String,
+
+    /// The client data JSON the browser gave the authenticator.
+    pub client_data: String,
+
+    /// The attestation object the authenticator created, holding the new credential.
+    pub attestation_object: String,
+}
+
+/// A problem, which teams solve by finding its flag.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct Problem {
+    /// The problem's database ID.
+    pub id: i32,
+
+    /// The problem's name, made of ASCII letters, digits, and dashes.
+    pub name: String,
+
+    /// The problem's category, such as "web" or "crypto".
+    pub category: String,
+
+    /// What players are told about the problem.
+    pub description: String,
+
+    /// How many points solving the problem is worth.
+    pub points: i32,
+
+    /// The problem's flag. This is never serialized, since it's the answer.
+    #[serde(skip)]
+    pub flag: String,
+
+    /// Whether players can see the problem.
+    pub visible: bool,
+
+    /// When the problem was created.
+    pub created: DateTime<Utc>,
+}
+
+/// A problem as admins write it.
+#[derive(Clone, Debug)]
+pub struct ProblemDraft {
+    /// The problem's name.
+    pub name: String,
+
+    /// The problem's category.
+    pub category: String,
+
+    /// What players are told about the problem.
+    pub description: String,
+
+    /// How many points solving the problem is worth.
+    pub points: i32,
+
+    /// The problem's flag.
+    pub flag: String,
+
+    /// Whether players can see the problem.
+    pub visible: bool,
+
+    /// The files players need for the problem.
+    pub attachments: Vec<Attachment>,
+}
+
+/// A problem as shown to a player, with its attachments and how it's been solved.
+#[derive(Clone, Debug, Serialize)]
+pub struct ProblemListing {
+    /// The problem.
+    #[serde(flatten)]
+    pub problem: Problem,
+
+    /// The files players need for the problem.
+    pub attachments: Vec<Attachment>,
+
+    /// How many teams have solved the problem.
+    pub solves: i64,
+
+    /// Whether the player's team has solved the problem.
+    pub solved: bool,
+}
+
+/// A mail in the outbox, waiting to be delivered or kept after delivery failed.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct QueuedMail {
+    /// The mail's database ID.
+    pub id: i32,
+
+    /// The address the mail is to.
+    pub recipient: String,
+
+    /// The mail's subject.
+    pub subject: String,
+
+    /// The mail's text. This is never serialized, since it may contain a login link.
+    #[serde(skip)]
+    pub body: String,
+
+    /// When the mail was queued.
+    pub created: DateTime<Utc>,
+
+    /// How many times delivering the mail has been tried.
+    pub attempts: i32,
+
+    /// When delivering the mail will next be tried.
+    pub next_attempt: DateTime<Utc>,
+
+    /// When the mail was delivered, if it has been.
+    pub sent: Option<DateTime<Utc>>,
+
+    /// Whether delivering the mail has been given up on.
+    pub failed: bool,
+
+    /// The error from the last failed attempt to deliver the mail, if any.
+    pub last_error: Option<String>,
+}
+
+/// A logged-in session.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct Session {
+    /// The session's authentication token. This is never serialized, since it's as good as a
+    /// password.
+    #[serde(skip)]
+    pub token: Uuid,
+
+    /// A number identifying the session to its user.
+    pub seq: i32,
+
+    /// When the session was created.
+    pub created: DateTime<Utc>,
+
+    /// When the session was last used.
+    pub last_used: DateTime<Utc>,
+
+    /// When the session expires.
+    pub expires: DateTime<Utc>,
+
+    /// The IP address the session was created from, if known.
+    pub ip: Option<String>,
+
+    /// The `User-Agent` of the browser the session was created from, if known.
+    pub user_agent: Option<String>,
+}
+
+/// A problem a team has solved.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct Solve {
+    /// The name of the problem that was solved.
+    pub problem: String,
+
+    /// The problem's category.
+    pub category: String,
+
+    /// How many points the problem is worth.
+    pub points: i32,
+
+    /// The name of the user who submitted the flag, unless they've since been deleted.
+    pub user: Option<String>,
+
+    /// When the problem was solved.
+    pub solved: DateTime<Utc>,
+}
+
+/// A team's place on the scoreboard.
+#[derive(Clone, Debug, Serialize)]
+pub struct Standing {
+    /// The team's place, starting from 1.
+    pub place: usize,
+
+    /// The team's name.
+    pub team: String,
+
+    /// How many points the team has.
+    pub points: i64,
+
+    /// When the team last solved a problem, if it has solved any.
+    pub last_solve: Option<DateTime<Utc>>,
+}
+
 /// A team.
 #[derive(Clone, Debug, Queryable, Serialize)]
 pub struct Team {
@@ -14,6 +567,111 @@ pub struct Team {
 
     /// The team's name.
     pub name: String,
+
+    /// The database ID of the team's captain. This is only `None` if the team has no members.
+    #[serde(skip)]
+    pub captain: Option<i32>,
+
+    /// Whether this is a user's implicitly-created personal team.
+    pub personal: bool,
+
+    /// The code used to join the team. This is never serialized, since it should only ever be
+    /// shown to the team's members.
+    #[serde(skip)]
+    pub join_code: String,
+
+    /// When the join code stops working, if ever.
+    #[serde(skip)]
+    pub join_code_expires: Option<DateTime<Utc>>,
+
+    /// How many more times the join code can be used, if it's limited.
+    #[serde(skip)]
+    pub join_code_uses: Option<i32>,
+
+    /// The school, company, or other organization the team is from. May be empty.
+    pub affiliation: String,
+
+    /// The country the team is from. May be empty.
+    pub country: String,
+
+    /// The team's website. Either empty or an HTTP(S) URL.
+    pub website: String,
+
+    /// A short description of the team. May be empty.
+    pub bio: String,
+
+    /// The database ID of the team's division, if it has chosen one.
+    pub division: Option<i32>,
+}
+
+/// The parts of a team's public profile that its captain can edit.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TeamProfile {
+    /// The school, company, or other organization the team is from.
+    pub affiliation: String,
+
+    /// The country the team is from.
+    pub country: String,
+
+    /// The team's website.
+    pub website: String,
+
+    /// A short description of the team.
+    pub bio: String,
+}
+
+/// A member of a team.
+#[derive(Clone, Debug, Serialize)]
+pub struct TeamMember {
+    /// The member's name.
+    pub name: String,
+
+    /// Whether the member is the team's captain.
+    pub captain: bool,
+}
+
+/// A support ticket opened by a team, for asking the mods a question.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct Ticket {
+    /// The ticket's database ID.
+    pub id: i32,
+
+    /// The team that opened the ticket, unless it's since been disbanded or merged away.
+    pub team: Option<Uuid>,
+
+    /// What the ticket is about.
+    pub subject: String,
+
+    /// When the ticket was opened.
+    pub created: DateTime<Utc>,
+
+    /// When the ticket was closed, if it's closed.
+    pub closed: Option<DateTime<Utc>>,
+
+    /// The name of the problem the ticket is about, if any.
+    pub problem: Option<String>,
+}
+
+/// A message in a ticket, from either the team or a mod.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct TicketMessage {
+    /// The message's database ID.
+    pub id: i32,
+
+    /// The name of the user who wrote the message, unless they've since been deleted.
+    pub author: Option<String>,
+
+    /// Whether the message is a mod's reply.
+    pub from_mod: bool,
+
+    /// The message's text.
+    pub body: String,
+
+    /// When the message was sent.
+    pub sent: DateTime<Utc>,
+
+    /// The announcement the reply was also posted as, if the mod made it public.
+    pub announcement: Option<i32>,
 }
 
 /// A user.
@@ -31,4 +689,53 @@ pub struct User {
 
     /// The database ID of the user's team.
     pub team: Option<Uuid>,
+
+    /// Whether the user is an admin. This isn't serialized, so it doesn't show up in the API; pages
+    /// get it through `UserPage`.
+    #[serde(skip_serializing)]
+    pub admin: bool,
+
+    /// Whether the user is banned, and can't log in.
+    pub banned: bool,
 }
+
+impl User {
+    /// Returns the user as they're shown to themselves on a page, whose navigation needs to know
+    /// whether they're an admin.
+    pub fn for_page(&self) -> UserPage {
+        UserPage {
+            user: self,
+            admin: self.admin,
+        }
+    }
+}
+
+/// The logged-in user as a page sees them: their public fields, plus whether they're an admin.
+#[derive(Clone, Debug, Serialize)]
+pub struct UserPage<'a> {
+    /// The user.
+    #[serde(flatten)]
+    pub user: &'a User,
+
+    /// Whether the user is an admin.
+    pub admin: bool,
+}
+
+/// An error caused by what the user asked for rather than by something breaking, with a message
+/// fit to show them. Its kind decides how it's reported, so the message can be reworded freely.
+#[derive(Clone, Debug)]
+pub struct UserError {
+    /// What kind of mistake it is.
+    pub kind: ErrorKind,
+
+    /// The message to show the user.
+    pub message: String,
+}
+
+impl Display for UserError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.write_str(&self.message)
+    }
+}
+
+impl StdError for UserError {}
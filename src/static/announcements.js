@@ -0,0 +1,59 @@
+// The banner for unseen announcements. The ID of the newest announcement the user has seen is kept
+// in localStorage, and marked when they visit /announcements. The newest announcement's ID is
+// fetched once per browser session, and kept up to date from the events events.js dispatches.
+
+(function() {
+	function seen() {
+		return parseInt(localStorage.getItem("announcements.seen"), 10) || 0;
+	}
+
+	function latest() {
+		var cached = sessionStorage.getItem("announcements.latest");
+		if(cached !== null) return Promise.resolve(parseInt(cached, 10) || 0);
+
+		return fetch("/announcements", { credentials: "same-origin", headers: { Accept: "application/json" } })
+			.then(function(res) {
+				if(!res.ok) throw new Error("Couldn't get the announcements: " + res.status);
+				return res.json();
+			})
+			.then(function(data) {
+				var id = data.latest || 0;
+				sessionStorage.setItem("announcements.latest", id);
+				return id;
+			});
+	}
+
+	function update(id, title, problem) {
+		var banner = document.getElementById("announcement-banner");
+		if(!banner) return;
+		if(title && problem) banner.textContent = "New announcement about " + problem + ": " + title;
+		else if(title) banner.textContent = "New announcement: " + title;
+		banner.hidden = id <= seen();
+	}
+
+	function setup() {
+		var list = document.getElementById("announcements");
+		if(list && list.dataset.latest) {
+			localStorage.setItem("announcements.seen", list.dataset.latest);
+			sessionStorage.setItem("announcements.latest", list.dataset.latest);
+		}
+		latest()
+			.then(function(id) { update(id); })
+			.catch(function(err) { console.error(err); });
+	}
+
+	document.addEventListener("ctf:event", function(e) {
+		if(e.detail.type !== "announcement_posted") return;
+		sessionStorage.setItem("announcements.latest", e.detail.id);
+		if(document.getElementById("announcements")) {
+			localStorage.setItem("announcements.seen", e.detail.id);
+		}
+		update(e.detail.id, e.detail.title, e.detail.problem);
+	});
+
+	if(window.Turbolinks) {
+		document.addEventListener("turbolinks:load", setup);
+	} else {
+		document.addEventListener("DOMContentLoaded", setup);
+	}
+})();
@@ -0,0 +1,68 @@
+// Live updates. Elements with a data-live attribute listing event types (and an id) are refreshed
+// in place when one of those events comes in on /events/stream, by fetching the page again and
+// swapping in the new element, so the server's templates are the only place pages are rendered.
+// Every event is also dispatched on the document as a "ctf:event" CustomEvent, for other scripts.
+
+(function() {
+	var source = null, queued = [], timer = null;
+
+	function refresh(stale) {
+		fetch(location.href, { credentials: "same-origin", headers: { Accept: "text/html" } })
+			.then(function(res) {
+				if(!res.ok) throw new Error("Couldn't refresh the page: " + res.status);
+				return res.text();
+			})
+			.then(function(html) {
+				var page = new DOMParser().parseFromString(html, "text/html");
+				stale.forEach(function(el) {
+					var fresh = page.getElementById(el.id);
+					if(fresh) el.innerHTML = fresh.innerHTML;
+				});
+			})
+			.catch(function(err) { console.error(err); });
+	}
+
+	// Events often come in bursts, so refreshes are batched up for a second.
+	function schedule(stale) {
+		stale.forEach(function(el) {
+			if(queued.indexOf(el) === -1) queued.push(el);
+		});
+		if(timer) return;
+		timer = setTimeout(function() {
+			refresh(queued);
+			queued = [];
+			timer = null;
+		}, 1000);
+	}
+
+	function setup() {
+		var live = Array.prototype.slice.call(document.querySelectorAll("[data-live]"));
+		if(!window.EventSource) return;
+
+		source = new EventSource("/events/stream");
+		source.addEventListener("message", function(e) {
+			var event = JSON.parse(e.data);
+			document.dispatchEvent(new CustomEvent("ctf:event", { detail: event }));
+
+			var stale = live.filter(function(el) {
+				return el.dataset.live.split(" ").indexOf(event.type) !== -1;
+			});
+			if(stale.length !== 0) schedule(stale);
+		});
+	}
+
+	function teardown() {
+		if(source) source.close();
+		if(timer) clearTimeout(timer);
+		source = null;
+		queued = [];
+		timer = null;
+	}
+
+	if(window.Turbolinks) {
+		document.addEventListener("turbolinks:load", setup);
+		document.addEventListener("turbolinks:before-render", teardown);
+	} else {
+		document.addEventListener("DOMContentLoaded", setup);
+	}
+})();
@@ -0,0 +1,12 @@
+// Shows the latest first blood on pages with a #first-blood element, like the scoreboard, from the
+// events events.js dispatches.
+
+(function() {
+	document.addEventListener("ctf:event", function(e) {
+		if(e.detail.type !== "first_blood") return;
+		var el = document.getElementById("first-blood");
+		if(!el) return;
+		el.textContent = "First blood: " + e.detail.team + " solved " + e.detail.problem + "!";
+		el.hidden = false;
+	});
+})();
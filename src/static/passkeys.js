@@ -0,0 +1,76 @@
+// Registering and logging in with passkeys. Forms marked with data-passkey="register" or
+// data-passkey="login" get a challenge from the server, have the browser sign it, put the result
+// in their hidden fields, then submit as usual, so errors show up like they do for other forms.
+
+(function() {
+	function decode(s) {
+		s = s.replace(/-/g, "+").replace(/_/g, "/");
+		while(s.length % 4) s += "=";
+		return Uint8Array.from(atob(s), function(c) { return c.charCodeAt(0); }).buffer;
+	}
+
+	function encode(buf) {
+		var s = String.fromCharCode.apply(null, new Uint8Array(buf));
+		return btoa(s).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+	}
+
+	function start(form, url) {
+		var body = new URLSearchParams();
+		body.set("csrf", form.elements.csrf.value);
+		return fetch(url, { body: body, credentials: "same-origin", method: "POST" })
+			.then(function(res) {
+				if(!res.ok) throw new Error("The server wouldn't start: " + res.status);
+				return res.json();
+			});
+	}
+
+	function register(form) {
+		return start(form, "/settings/passkeys/start").then(function(options) {
+			options.challenge = decode(options.challenge);
+			options.user.id = decode(options.user.id);
+			options.excludeCredentials.forEach(function(cred) { cred.id = decode(cred.id); });
+			return navigator.credentials.create({ publicKey: options });
+		}).then(function(cred) {
+			form.elements.client_data.value = encode(cred.response.clientDataJSON);
+			form.elements.attestation_object.value = encode(cred.response.attestationObject);
+		});
+	}
+
+	function login(form) {
+		return start(form, "/login/passkey/start").then(function(options) {
+			options.challenge = decode(options.challenge);
+			return navigator.credentials.get({ publicKey: options });
+		}).then(function(cred) {
+			form.elements.credential_id.value = encode(cred.rawId);
+			form.elements.client_data.value = encode(cred.response.clientDataJSON);
+			form.elements.authenticator_data.value = encode(cred.response.authenticatorData);
+			form.elements.signature.value = encode(cred.response.signature);
+		});
+	}
+
+	function setup() {
+		var forms = document.querySelectorAll("form[data-passkey]");
+		Array.prototype.forEach.call(forms, function(form) {
+			if(!window.PublicKeyCredential) {
+				form.hidden = true;
+				return;
+			}
+			form.addEventListener("submit", function(e) {
+				e.preventDefault();
+				var ceremony = form.dataset.passkey === "register" ? register : login;
+				ceremony(form).then(function() {
+					form.submit();
+				}, function(err) {
+					console.error(err);
+					alert("Your browser couldn't use a passkey. Try again?");
+				});
+			});
+		});
+	}
+
+	if(window.Turbolinks) {
+		document.addEventListener("turbolinks:load", setup);
+	} else {
+		document.addEventListener("DOMContentLoaded", setup);
+	}
+})();
@@ -2,6 +2,7 @@
 
 use futures::{future::poll_fn, Future};
 use log::error;
+use ring::rand::{SecureRandom, SystemRandom};
 
 /// A higher-level version of `tokio_threadpool::blocking`.
 pub fn blocking<E, F, T>(func: F) -> impl Future<Item = T, Error = E>
@@ -16,6 +17,95 @@ where
     .and_then(|r| r)
 }
 
+/// Encodes bytes as unpadded base32 (RFC 4648), which is how authenticator apps take secrets.
+pub fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut out = String::with_capacity((bytes.len() * 8 + 4) / 5);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for &b in bytes {
+        buffer = (buffer << 8) | u16::from(b);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[usize::from((buffer >> bits) & 31)] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[usize::from((buffer << (5 - bits)) & 31)] as char);
+    }
+    out
+}
+
+/// Generates the given number of random bytes from the system's secure random number generator.
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("The system's random number generator failed");
+    bytes
+}
+
+/// Generates a random code of the given length, made of uppercase letters and digits that are
+/// hard to mistake for each other.
+pub fn random_code(len: usize) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHJKMNPQRSTUVWXYZ123456789";
+
+    random_bytes(len)
+        .into_iter()
+        .map(|b| ALPHABET[(b & 31) as usize] as char)
+        .collect()
+}
+
+/// Generates a random string of the given number of decimal digits.
+pub fn random_digits(len: usize) -> String {
+    let mut code = String::with_capacity(len);
+    while code.len() < len {
+        // Bytes of 250 and up are skipped so every digit is equally likely.
+        for b in random_bytes(len - code.len())
+            .into_iter()
+            .filter(|&b| b < 250)
+        {
+            code.push((b'0' + b % 10) as char);
+        }
+    }
+    code
+}
+
+/// Encodes an RSA public key as a DER `RSAPublicKey`, which is the form `ring` checks signatures
+/// with.
+pub fn rsa_public_key_der(n: &[u8], e: &[u8]) -> Vec<u8> {
+    fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if value.len() < 0x80 {
+            out.push(value.len() as u8);
+        } else {
+            let len = (value.len() as u32).to_be_bytes();
+            let skip = len.iter().take_while(|&&b| b == 0).count();
+            out.push(0x80 | (len.len() - skip) as u8);
+            out.extend_from_slice(&len[skip..]);
+        }
+        out.extend_from_slice(value);
+        out
+    }
+
+    fn integer(bytes: &[u8]) -> Vec<u8> {
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        let mut value = Vec::with_capacity(bytes.len() - skip + 1);
+        // DER integers are signed, so a leading byte with its high bit set needs a zero before it.
+        if bytes.get(skip).map_or(true, |&b| b & 0x80 != 0) {
+            value.push(0);
+        }
+        value.extend_from_slice(&bytes[skip..]);
+        tlv(0x02, &value)
+    }
+
+    let mut body = integer(n);
+    body.extend(integer(e));
+    tlv(0x30, &body)
+}
+
 /// Logs an error, including its causes and backtrace (if possible).
 pub fn log_err(err: &failure::Error) {
     let mut first = true;
@@ -45,3 +135,72 @@ macro_rules! coerce {
         x
     }};
 }
+
+/// Creates a `failure::Error` from a `UserError` of the given kind, with a message formatted like
+/// `format!`'s.
+macro_rules! user_err {
+    ($kind:ident, $($arg:tt)*) => {
+        ::failure::Error::from($crate::schema::UserError {
+            kind: $crate::schema::ErrorKind::$kind,
+            message: format!($($arg)*),
+        })
+    };
+}
+
+/// Returns early with a `UserError`, like `failure::bail!`.
+macro_rules! user_bail {
+    ($kind:ident, $($arg:tt)*) => {
+        return Err(user_err!($kind, $($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base32_matches_rfc_4648() {
+        let vectors = [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ];
+        for &(input, output) in &vectors {
+            assert_eq!(base32(input.as_bytes()), output);
+        }
+    }
+
+    #[test]
+    fn random_digits_are_digits() {
+        for &len in &[0, 1, 6, 20, 100] {
+            let digits = random_digits(len);
+            assert_eq!(digits.len(), len);
+            assert!(digits.bytes().all(|b| b.is_ascii_digit()), "{}", digits);
+        }
+    }
+
+    #[test]
+    fn rsa_public_key_der_pads_and_strips_integers() {
+        assert_eq!(
+            rsa_public_key_der(&[0x00, 0xc1, 0x23], &[0x01, 0x00, 0x01]),
+            [0x30, 0x09, 0x02, 0x03, 0x00, 0xc1, 0x23, 0x02, 0x03, 0x01, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn rsa_public_key_der_matches_openssl() {
+        // The public half of the key the OpenID Connect tests sign with, as written by
+        // `openssl rsa -RSAPublicKey_out -outform DER`.
+        let expected = include_bytes!("../tests/fixtures/oidc-key.pub.der");
+        let n = include_str!("../tests/fixtures/oidc-key.n").trim();
+        let n = base64::decode_config(n, base64::URL_SAFE_NO_PAD).unwrap();
+        assert_eq!(
+            &rsa_public_key_der(&n, &[0x01, 0x00, 0x01])[..],
+            &expected[..]
+        );
+    }
+}
@@ -4,8 +4,10 @@
 //! > response. I'm happy to call this "view" in common with traditional stateless MVC, because
 //! > it's role is largely the same.
 
+use crate::schema::Solve;
 use failure::{Error, Fallible, SyncFailure};
 use packer::Packer;
+use qrcode::{render::svg, QrCode};
 use serde::Serialize;
 use tera::Tera;
 use warp::{
@@ -14,6 +16,38 @@ use warp::{
     Rejection,
 };
 
+/// A rendering of a response, picked by content negotiation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// A page rendered from a template.
+    Html,
+
+    /// The data the template would have been rendered with, as JSON.
+    Json,
+}
+
+impl Format {
+    /// Picks a format from the value of an `Accept` header. JSON is only used when it's asked for
+    /// ahead of HTML, so browsers (and clients that don't say) get HTML. Quality values aren't
+    /// looked at, just the order.
+    pub fn from_accept(accept: Option<&str>) -> Format {
+        let accept = match accept {
+            Some(accept) => accept,
+            None => return Format::Html,
+        };
+        accept
+            .split(',')
+            .map(|range| range.split(';').next().unwrap_or("").trim())
+            .filter_map(|range| match range {
+                "application/json" => Some(Format::Json),
+                "text/html" | "*/*" => Some(Format::Html),
+                _ => None,
+            })
+            .next()
+            .unwrap_or(Format::Html)
+    }
+}
+
 lazy_static::lazy_static! {
     static ref TERA: Tera = {
         #[derive(Packer)]
@@ -36,6 +70,77 @@ pub fn render<T: Serialize>(name: &str, data: T) -> Fallible<String> {
         .map_err(|err| SyncFailure::new(err).into())
 }
 
+/// Renders the data for a template in the given format: as the template's HTML, or as the data
+/// itself in JSON.
+pub fn render_as<T: Serialize>(
+    format: Format,
+    name: &str,
+    data: T,
+) -> Result<Response<String>, Rejection> {
+    match format {
+        Format::Html => render_html(name, data),
+        Format::Json => render_json(data),
+    }
+}
+
+/// Renders a value as JSON to a `warp::Reply`.
+pub fn render_json<T: Serialize>(data: T) -> Result<Response<String>, Rejection> {
+    serde_json::to_string(&data)
+        .map_err(Error::from)
+        .and_then(|body| {
+            Response::builder()
+                .header(CONTENT_TYPE, "application/json")
+                .body(body)
+                .map_err(Error::from)
+        })
+        .map_err(|err| custom(err.compat()))
+}
+
+/// Renders a QR code of the given data as an SVG image, to be embedded in a page.
+pub fn render_qr_svg(data: &str) -> Fallible<String> {
+    let code = QrCode::new(data.as_bytes())?;
+    Ok(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+/// Renders a team's score over time as an SVG image, to be embedded in a page. The score steps up
+/// at each solve, from zero to the team's current score.
+pub fn render_score_svg(solves: &[Solve]) -> String {
+    const WIDTH: f64 = 400.0;
+    const HEIGHT: f64 = 100.0;
+
+    let (first, last) = match (solves.first(), solves.last()) {
+        (Some(first), Some(last)) => (first.solved, last.solved),
+        _ => return String::new(),
+    };
+    let total = solves
+        .iter()
+        .map(|solve| i64::from(solve.points))
+        .sum::<i64>();
+    let span = (last - first).num_seconds().max(1) as f64;
+
+    let mut score = 0;
+    let mut points = vec![(0.0, HEIGHT)];
+    for solve in solves {
+        let x = (solve.solved - first).num_seconds() as f64 / span * WIDTH;
+        points.push((x, HEIGHT - score as f64 / total as f64 * HEIGHT));
+        score += i64::from(solve.points);
+        points.push((x, HEIGHT - score as f64 / total as f64 * HEIGHT));
+    }
+    let points = points
+        .into_iter()
+        .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        concat!(
+            r#"<svg class="score" viewBox="0 0 {} {}" xmlns="http://www.w3.org/2000/svg">"#,
+            r#"<polyline fill="none" points="{}" stroke="currentColor" stroke-width="2"/>"#,
+            "</svg>"
+        ),
+        WIDTH, HEIGHT, points
+    )
+}
+
 /// Renders a template as HTML to a `warp::Reply`.
 pub fn render_html<T: Serialize>(name: &str, data: T) -> Result<Response<String>, Rejection> {
     render(name, data)
@@ -0,0 +1,307 @@
+//! Tests logging in with an OpenID Connect provider, against a mock provider served on localhost.
+//!
+//! Logging in links identities to users in the database, so these tests need a Postgres database
+//! to run migrations in and create users in, given by `DATABASE_URL`. They're skipped if it isn't
+//! set.
+
+use chrono::Utc;
+use failure::Error;
+use jsonwebtoken::{encode, Algorithm, Header};
+use nihctfplat::{
+    dal::{Oidc, DB},
+    logic::auth::{
+        oidc::{OidcCallback, OidcLogin},
+        LoginMethod,
+    },
+    schema::OidcConfig,
+};
+use serde_json::{json, Value};
+use std::{
+    env,
+    sync::{Arc, Mutex},
+};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+use warp::Filter;
+
+/// The provider's signing key, as a DER `RSAPrivateKey`.
+const KEY: &[u8] = include_bytes!("fixtures/oidc-key.der");
+
+/// The modulus of the provider's signing key, as unpadded URL-safe base64.
+const KEY_N: &str = include_str!("fixtures/oidc-key.n");
+
+/// The ID of the provider's signing key.
+const KID: &str = "test-key";
+
+const CLIENT_ID: &str = "nihctfplat";
+const NONCE: &str = "the-nonce";
+const STATE: &str = "the-state";
+
+/// The error for a login at the identity provider that couldn't be finished.
+const OIDC_LOGIN_FAILED: &str = "Logging in with your identity provider didn't work. Try again?";
+
+/// A mock provider, and a client for it.
+struct Provider {
+    db: DB,
+    id_token: Arc<Mutex<String>>,
+    issuer: String,
+    oidc: Oidc,
+    runtime: Runtime,
+}
+
+impl Provider {
+    /// Starts a mock provider, returning `None` if there's no database to test with.
+    fn start() -> Option<Provider> {
+        let database_url = match env::var("DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("DATABASE_URL isn't set, so this test is skipped");
+                return None;
+            }
+        };
+        let db = DB::connect(&database_url).expect("couldn't connect to the database");
+        let mut runtime = Runtime::new().unwrap();
+
+        let issuer = Arc::new(Mutex::new(String::new()));
+        let id_token = Arc::new(Mutex::new(String::new()));
+        let discovery = warp::path(".well-known")
+            .and(warp::path("openid-configuration"))
+            .and(warp::path::end())
+            .map({
+                let issuer = issuer.clone();
+                move || {
+                    let issuer = issuer.lock().unwrap();
+                    warp::reply::json(&json!({
+                        "issuer": *issuer,
+                        "authorization_endpoint": format!("{}/authorize", issuer),
+                        "token_endpoint": format!("{}/token", issuer),
+                        "jwks_uri": format!("{}/jwks", issuer),
+                    }))
+                }
+            });
+        let jwks = warp::path("jwks").and(warp::path::end()).map(|| {
+            warp::reply::json(&json!({
+                "keys": [{ "kty": "RSA", "kid": KID, "n": KEY_N.trim(), "e": "AQAB" }]
+            }))
+        });
+        let token = warp::post2()
+            .and(warp::path("token"))
+            .and(warp::path::end())
+            .map({
+                let id_token = id_token.clone();
+                move || warp::reply::json(&json!({ "id_token": *id_token.lock().unwrap() }))
+            });
+        let routes = discovery.or(jwks).unify().or(token).unify();
+
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        runtime.spawn(server);
+        let url = format!("http://{}", addr);
+        *issuer.lock().unwrap() = url.clone();
+
+        let config = OidcConfig {
+            issuer: url.clone(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: "hunter2".to_string(),
+            redirect_url: "https://ctf.example.com/login/oidc/callback".to_string(),
+            name: "Mock SSO".to_string(),
+        };
+        let oidc = runtime
+            .block_on(Oidc::discover(config))
+            .expect("discovery failed");
+        Some(Provider {
+            db,
+            id_token,
+            issuer: url,
+            oidc,
+            runtime,
+        })
+    }
+
+    /// Returns the claims of a valid ID token for the given subject and email address.
+    fn claims(&self, subject: &str, email: &str) -> Value {
+        let now = Utc::now().timestamp();
+        json!({
+            "iss": self.issuer,
+            "aud": CLIENT_ID,
+            "sub": subject,
+            "email": email,
+            "email_verified": true,
+            "nonce": NONCE,
+            "iat": now,
+            "exp": now + 300,
+        })
+    }
+
+    /// Logs in, with the provider's token endpoint handing out an ID token with the given claims,
+    /// signed with the key with the given ID.
+    fn log_in_with_kid(&mut self, claims: &Value, kid: &str) -> Result<i32, Error> {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        *self.id_token.lock().unwrap() = encode(&header, claims, KEY).unwrap();
+
+        let callback = OidcCallback {
+            code: Some("the-code".to_string()),
+            state: Some(STATE.to_string()),
+            saved: Some(format!("{}.{}", STATE, NONCE)),
+        };
+        let login = OidcLogin(self.oidc.clone()).verify(self.db.clone(), callback);
+        self.runtime.block_on(login)
+    }
+
+    /// Logs in, with the provider's token endpoint handing out an ID token with the given claims.
+    fn log_in(&mut self, claims: &Value) -> Result<i32, Error> {
+        self.log_in_with_kid(claims, KID)
+    }
+}
+
+/// Returns a subject and email address no other test uses.
+fn new_identity() -> (String, String) {
+    let id = Uuid::new_v4().simple().to_string();
+    (id.clone(), format!("oidc{}@example.com", &id[..12]))
+}
+
+fn assert_login_failed(result: Result<i32, Error>) {
+    match result {
+        Ok(user) => panic!("logged in as user {}", user),
+        Err(err) => assert_eq!(err.to_string(), OIDC_LOGIN_FAILED),
+    }
+}
+
+#[test]
+fn first_login_registers_a_user() {
+    let mut provider = match Provider::start() {
+        Some(provider) => provider,
+        None => return,
+    };
+    let (subject, email) = new_identity();
+    let claims = provider.claims(&subject, &email);
+
+    let user = provider.log_in(&claims).unwrap();
+    assert_eq!(provider.log_in(&claims).unwrap(), user);
+    let user = provider
+        .runtime
+        .block_on(provider.db.get_user(user))
+        .unwrap();
+    assert_eq!(user.email, email);
+}
+
+#[test]
+fn identities_are_linked_to_existing_users_by_email() {
+    let mut provider = match Provider::start() {
+        Some(provider) => provider,
+        None => return,
+    };
+    let (subject, email) = new_identity();
+    let username = format!("oidc{}", &subject[..12]);
+    let (user, created) = provider
+        .runtime
+        .block_on(provider.db.create_user(username, email.to_uppercase()))
+        .unwrap();
+    assert!(created);
+
+    let claims = provider.claims(&subject, &email);
+    assert_eq!(provider.log_in(&claims).unwrap(), user);
+}
+
+#[test]
+fn unverified_emails_are_not_linked() {
+    let mut provider = match Provider::start() {
+        Some(provider) => provider,
+        None => return,
+    };
+    let (subject, email) = new_identity();
+    let mut claims = provider.claims(&subject, &email);
+    claims["email_verified"] = json!(false);
+
+    let err = provider.log_in(&claims).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Your identity provider hasn't verified your email address."
+    );
+}
+
+#[test]
+fn wrong_issuer_is_rejected() {
+    let mut provider = match Provider::start() {
+        Some(provider) => provider,
+        None => return,
+    };
+    let (subject, email) = new_identity();
+    let mut claims = provider.claims(&subject, &email);
+    claims["iss"] = json!("https://evil.example.com");
+
+    assert_login_failed(provider.log_in(&claims));
+}
+
+#[test]
+fn wrong_audience_is_rejected() {
+    let mut provider = match Provider::start() {
+        Some(provider) => provider,
+        None => return,
+    };
+    let (subject, email) = new_identity();
+    let mut claims = provider.claims(&subject, &email);
+    claims["aud"] = json!("someone-else");
+
+    assert_login_failed(provider.log_in(&claims));
+}
+
+#[test]
+fn wrong_nonce_is_rejected() {
+    let mut provider = match Provider::start() {
+        Some(provider) => provider,
+        None => return,
+    };
+    let (subject, email) = new_identity();
+    let mut claims = provider.claims(&subject, &email);
+    claims["nonce"] = json!("another-nonce");
+
+    assert_login_failed(provider.log_in(&claims));
+}
+
+#[test]
+fn expired_token_is_rejected() {
+    let mut provider = match Provider::start() {
+        Some(provider) => provider,
+        None => return,
+    };
+    let (subject, email) = new_identity();
+    let mut claims = provider.claims(&subject, &email);
+    claims["exp"] = json!(Utc::now().timestamp() - 3600);
+
+    assert_login_failed(provider.log_in(&claims));
+}
+
+#[test]
+fn unknown_key_is_rejected() {
+    let mut provider = match Provider::start() {
+        Some(provider) => provider,
+        None => return,
+    };
+    let (subject, email) = new_identity();
+    let claims = provider.claims(&subject, &email);
+
+    let err = provider
+        .log_in_with_kid(&claims, "rotated-away")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The identity provider's signing key wasn't found"
+    );
+}
+
+#[test]
+fn wrong_state_is_rejected() {
+    let mut provider = match Provider::start() {
+        Some(provider) => provider,
+        None => return,
+    };
+    let callback = OidcCallback {
+        code: Some("the-code".to_string()),
+        state: Some("forged-state".to_string()),
+        saved: Some(format!("{}.{}", STATE, NONCE)),
+    };
+    let login = OidcLogin(provider.oidc.clone()).verify(provider.db.clone(), callback);
+
+    assert_login_failed(provider.runtime.block_on(login));
+}
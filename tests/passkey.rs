@@ -0,0 +1,441 @@
+//! Tests registering and logging in with passkeys, using a software authenticator with a P-256
+//! key.
+//!
+//! Challenges and passkeys are kept in the database, so these tests need a Postgres database to
+//! run migrations in and create users in, given by `DATABASE_URL`. They're skipped if it isn't
+//! set.
+
+use chrono::Duration;
+use failure::Error;
+use nihctfplat::{
+    dal::DB,
+    logic::auth::{
+        passkey::{finish_registration, start_login, start_registration, PasskeyLogin},
+        LoginMethod,
+    },
+    schema::{Config, PasskeyAssertion, PasskeyRegistration},
+};
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+    signature::{ECDSAKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+};
+use serde_json::{json, Value};
+use std::env;
+use tokio::runtime::Runtime;
+use untrusted::Input;
+use uuid::Uuid;
+
+const ORIGIN: &str = "https://ctf.example.com";
+const RP_ID: &str = "ctf.example.com";
+
+/// The authenticator data flag set when the user was present.
+const FLAG_USER_PRESENT: u8 = 0x01;
+
+/// The authenticator data flag set when a new credential is included.
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// The error for a passkey login that couldn't be checked.
+const PASSKEY_LOGIN_FAILED: &str = "Logging in with your passkey didn't work. Try again?";
+
+/// The error for a passkey that couldn't be registered.
+const PASSKEY_SETUP_FAILED: &str = "That passkey couldn't be set up. Try again?";
+
+/// A software authenticator holding one P-256 key.
+struct Authenticator {
+    credential_id: Vec<u8>,
+    key_pair: ECDSAKeyPair,
+    public_key: Vec<u8>,
+    rng: SystemRandom,
+}
+
+impl Authenticator {
+    fn new() -> Authenticator {
+        let rng = SystemRandom::new();
+        let pkcs8 = ECDSAKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let pkcs8 = pkcs8.as_ref();
+        // The generated document ends with the uncompressed public key.
+        let public_key = pkcs8[pkcs8.len() - 65..].to_vec();
+        let key_pair =
+            ECDSAKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, Input::from(pkcs8)).unwrap();
+        let mut credential_id = vec![0; 16];
+        rng.fill(&mut credential_id).unwrap();
+        Authenticator {
+            credential_id,
+            key_pair,
+            public_key,
+            rng,
+        }
+    }
+
+    /// Returns the authenticator data for the given relying party ID, flags, and signature
+    /// counter.
+    fn auth_data(&self, rp_id: &str, flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = digest::digest(&digest::SHA256, rp_id.as_bytes())
+            .as_ref()
+            .to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data
+    }
+
+    /// Creates the credential, returning what the browser would send back for the given
+    /// challenge, origin, relying party ID, and flags.
+    fn register(
+        &self,
+        challenge: &str,
+        origin: &str,
+        rp_id: &str,
+        flags: u8,
+    ) -> PasskeyRegistration {
+        let client_data = json!({
+            "type": "webauthn.create",
+            "challenge": challenge,
+            "origin": origin,
+        })
+        .to_string();
+
+        // The COSE key is {1: 2, 3: -7, -1: 1, -2: x, -3: y}, i.e. an ES256 key on P-256.
+        let mut cose_key = vec![0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21];
+        cose_key.extend(cbor_bytes(&self.public_key[1..33]));
+        cose_key.push(0x22);
+        cose_key.extend(cbor_bytes(&self.public_key[33..]));
+
+        let mut auth_data = self.auth_data(rp_id, flags | FLAG_ATTESTED_CREDENTIAL, 0);
+        auth_data.extend_from_slice(&[0; 16]);
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        auth_data.extend(cose_key);
+
+        // The attestation object is {"fmt": "none", "attStmt": {}, "authData": auth_data}.
+        let mut attestation = vec![0xa3];
+        attestation.extend(cbor_text("fmt"));
+        attestation.extend(cbor_text("none"));
+        attestation.extend(cbor_text("attStmt"));
+        attestation.push(0xa0);
+        attestation.extend(cbor_text("authData"));
+        attestation.extend(cbor_bytes(&auth_data));
+
+        PasskeyRegistration {
+            name: "Software Authenticator".to_string(),
+            client_data: encode_base64(client_data.as_bytes()),
+            attestation_object: encode_base64(&attestation),
+        }
+    }
+
+    /// Signs an assertion for the given challenge, origin, relying party ID, flags, and signature
+    /// counter.
+    fn assert(
+        &self,
+        challenge: &str,
+        origin: &str,
+        rp_id: &str,
+        flags: u8,
+        sign_count: u32,
+    ) -> PasskeyAssertion {
+        let client_data = json!({
+            "type": "webauthn.get",
+            "challenge": challenge,
+            "origin": origin,
+        })
+        .to_string();
+        let auth_data = self.auth_data(rp_id, flags, sign_count);
+
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(digest::digest(&digest::SHA256, client_data.as_bytes()).as_ref());
+        let sig = self.key_pair.sign(Input::from(&signed), &self.rng).unwrap();
+
+        PasskeyAssertion {
+            credential_id: encode_base64(&self.credential_id),
+            client_data: encode_base64(client_data.as_bytes()),
+            authenticator_data: encode_base64(&auth_data),
+            signature: encode_base64(sig.as_ref()),
+        }
+    }
+}
+
+/// A database, and a user in it with a software authenticator.
+struct Fixture {
+    authenticator: Authenticator,
+    config: Config,
+    db: DB,
+    runtime: Runtime,
+    user: i32,
+}
+
+impl Fixture {
+    /// Creates a user, returning `None` if there's no database to test with.
+    fn new() -> Option<Fixture> {
+        let database_url = match env::var("DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("DATABASE_URL isn't set, so this test is skipped");
+                return None;
+            }
+        };
+        let db = DB::connect(&database_url).expect("couldn't connect to the database");
+        let mut runtime = Runtime::new().unwrap();
+
+        let id = Uuid::new_v4().simple().to_string();
+        let username = format!("passkey{}", &id[..12]);
+        let email = format!("passkey{}@example.com", &id[..12]);
+        let (user, _) = runtime.block_on(db.create_user(username, email)).unwrap();
+        Some(Fixture {
+            authenticator: Authenticator::new(),
+            config: config(),
+            db,
+            runtime,
+            user,
+        })
+    }
+
+    /// Starts registering a passkey, returning the challenge.
+    fn registration_challenge(&mut self) -> String {
+        let user = self.runtime.block_on(self.db.get_user(self.user)).unwrap();
+        let options = start_registration(self.db.clone(), &self.config, user);
+        challenge(self.runtime.block_on(options).unwrap())
+    }
+
+    fn finish_registration(&mut self, registration: PasskeyRegistration) -> Result<(), Error> {
+        let finish = finish_registration(self.db.clone(), &self.config, self.user, registration);
+        self.runtime.block_on(finish)
+    }
+
+    /// Registers the authenticator's passkey.
+    fn register(&mut self) {
+        let challenge = self.registration_challenge();
+        let registration =
+            self.authenticator
+                .register(&challenge, ORIGIN, RP_ID, FLAG_USER_PRESENT);
+        self.finish_registration(registration).unwrap();
+    }
+
+    /// Starts logging in with a passkey, returning the challenge.
+    fn login_challenge(&mut self) -> String {
+        let options = start_login(self.db.clone(), &self.config);
+        challenge(self.runtime.block_on(options).unwrap())
+    }
+
+    fn log_in(&mut self, assertion: PasskeyAssertion) -> Result<i32, Error> {
+        let login = PasskeyLogin::new(&self.config).verify(self.db.clone(), assertion);
+        self.runtime.block_on(login)
+    }
+}
+
+/// Returns the settings passkeys are checked against.
+fn config() -> Config {
+    Config {
+        access_token_lifetime: Duration::minutes(5),
+        jwt_keys: Vec::new(),
+        login_link_lifetime: Duration::hours(1),
+        login_mail_cooldown: Duration::minutes(1),
+        login_mails_per_ip: 20,
+        login_mails_per_user: 5,
+        max_team_size: 4,
+        oidc: None,
+        secure_cookies: true,
+        session_lifetime: Duration::days(14),
+        solo: false,
+        trusted_proxies: Vec::new(),
+        webauthn_origin: ORIGIN.to_string(),
+        webauthn_rp_id: RP_ID.to_string(),
+    }
+}
+
+/// Gets the challenge out of the options for `navigator.credentials.create` or
+/// `navigator.credentials.get`.
+fn challenge(options: Value) -> String {
+    options["challenge"].as_str().unwrap().to_string()
+}
+
+/// Encodes a CBOR byte string.
+fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut out = cbor_head(0x40, bytes.len());
+    out.extend_from_slice(bytes);
+    out
+}
+
+/// Encodes a CBOR text string.
+fn cbor_text(text: &str) -> Vec<u8> {
+    let mut out = cbor_head(0x60, text.len());
+    out.extend_from_slice(text.as_bytes());
+    out
+}
+
+/// Encodes the head of a CBOR item with the given major type and length.
+fn cbor_head(major: u8, len: usize) -> Vec<u8> {
+    if len < 24 {
+        vec![major | len as u8]
+    } else if len < 0x100 {
+        vec![major | 24, len as u8]
+    } else {
+        let len = (len as u16).to_be_bytes();
+        vec![major | 25, len[0], len[1]]
+    }
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+#[test]
+fn registered_passkeys_log_in() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    fixture.register();
+
+    for sign_count in 1..3 {
+        let challenge = fixture.login_challenge();
+        let assertion =
+            fixture
+                .authenticator
+                .assert(&challenge, ORIGIN, RP_ID, FLAG_USER_PRESENT, sign_count);
+        assert_eq!(fixture.log_in(assertion).unwrap(), fixture.user);
+    }
+}
+
+#[test]
+fn registration_from_wrong_origin_is_rejected() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    let challenge = fixture.registration_challenge();
+    let registration = fixture.authenticator.register(
+        &challenge,
+        "https://evil.example.com",
+        RP_ID,
+        FLAG_USER_PRESENT,
+    );
+
+    let err = fixture.finish_registration(registration).unwrap_err();
+    assert_eq!(err.to_string(), PASSKEY_SETUP_FAILED);
+}
+
+#[test]
+fn registration_for_wrong_relying_party_is_rejected() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    let challenge = fixture.registration_challenge();
+    let registration =
+        fixture
+            .authenticator
+            .register(&challenge, ORIGIN, "evil.example.com", FLAG_USER_PRESENT);
+
+    let err = fixture.finish_registration(registration).unwrap_err();
+    assert_eq!(err.to_string(), PASSKEY_SETUP_FAILED);
+}
+
+#[test]
+fn registration_without_user_presence_is_rejected() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    let challenge = fixture.registration_challenge();
+    let registration = fixture.authenticator.register(&challenge, ORIGIN, RP_ID, 0);
+
+    let err = fixture.finish_registration(registration).unwrap_err();
+    assert_eq!(err.to_string(), PASSKEY_SETUP_FAILED);
+}
+
+#[test]
+fn login_from_wrong_origin_is_rejected() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    fixture.register();
+    let challenge = fixture.login_challenge();
+    let assertion = fixture.authenticator.assert(
+        &challenge,
+        "https://evil.example.com",
+        RP_ID,
+        FLAG_USER_PRESENT,
+        1,
+    );
+
+    let err = fixture.log_in(assertion).unwrap_err();
+    assert_eq!(err.to_string(), PASSKEY_LOGIN_FAILED);
+}
+
+#[test]
+fn login_for_wrong_relying_party_is_rejected() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    fixture.register();
+    let challenge = fixture.login_challenge();
+    let assertion =
+        fixture
+            .authenticator
+            .assert(&challenge, ORIGIN, "evil.example.com", FLAG_USER_PRESENT, 1);
+
+    let err = fixture.log_in(assertion).unwrap_err();
+    assert_eq!(err.to_string(), PASSKEY_LOGIN_FAILED);
+}
+
+#[test]
+fn login_without_user_presence_is_rejected() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    fixture.register();
+    let challenge = fixture.login_challenge();
+    let assertion = fixture
+        .authenticator
+        .assert(&challenge, ORIGIN, RP_ID, 0, 1);
+
+    let err = fixture.log_in(assertion).unwrap_err();
+    assert_eq!(err.to_string(), PASSKEY_LOGIN_FAILED);
+}
+
+#[test]
+fn login_challenges_cannot_be_reused() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    fixture.register();
+    let challenge = fixture.login_challenge();
+    let first = fixture
+        .authenticator
+        .assert(&challenge, ORIGIN, RP_ID, FLAG_USER_PRESENT, 1);
+    assert_eq!(fixture.log_in(first).unwrap(), fixture.user);
+
+    let second = fixture
+        .authenticator
+        .assert(&challenge, ORIGIN, RP_ID, FLAG_USER_PRESENT, 2);
+    let err = fixture.log_in(second).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Logging in with your passkey took too long. Try again?"
+    );
+}
+
+#[test]
+fn sign_count_must_increase() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    fixture.register();
+    let challenge = fixture.login_challenge();
+    let first = fixture
+        .authenticator
+        .assert(&challenge, ORIGIN, RP_ID, FLAG_USER_PRESENT, 5);
+    assert_eq!(fixture.log_in(first).unwrap(), fixture.user);
+
+    let challenge = fixture.login_challenge();
+    let cloned = fixture
+        .authenticator
+        .assert(&challenge, ORIGIN, RP_ID, FLAG_USER_PRESENT, 5);
+    let err = fixture.log_in(cloned).unwrap_err();
+    assert_eq!(err.to_string(), PASSKEY_LOGIN_FAILED);
+}
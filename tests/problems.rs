@@ -0,0 +1,207 @@
+//! Tests submitting flags, the events solves publish, and that solves move with their team when
+//! teams are merged.
+//!
+//! These need a Postgres database to run migrations in and create users, teams, and problems in,
+//! given by `DATABASE_URL`. They're skipped if it isn't set.
+
+use futures::Stream;
+use nihctfplat::{
+    dal::{Events, DB},
+    logic::{self, admin::create_problem},
+    schema::{Event, ProblemDraft},
+};
+use std::env;
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+/// The flag of every problem made by these tests.
+const FLAG: &str = "flag{test}";
+
+/// A database with a user on a team of their own, and a visible problem.
+struct Fixture {
+    db: DB,
+    events: Events,
+    problem: String,
+    runtime: Runtime,
+    team: Uuid,
+    user: i32,
+}
+
+impl Fixture {
+    /// Creates a user, their team, and a problem, returning `None` if there's no database to test
+    /// with.
+    fn new() -> Option<Fixture> {
+        let database_url = match env::var("DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("DATABASE_URL isn't set, so this test is skipped");
+                return None;
+            }
+        };
+        let db = DB::connect(&database_url).expect("couldn't connect to the database");
+        let events = Events::new();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (user, team) = new_team(&db, &mut runtime);
+        let problem = format!("problem-{}", &Uuid::new_v4().simple().to_string()[..12]);
+        let draft = ProblemDraft {
+            name: problem.clone(),
+            category: "Test".to_string(),
+            description: "A problem for testing.".to_string(),
+            points: 100,
+            flag: FLAG.to_string(),
+            visible: true,
+            attachments: Vec::new(),
+        };
+        runtime
+            .block_on(create_problem(db.clone(), events.clone(), user, draft))
+            .unwrap();
+        Some(Fixture {
+            db,
+            events,
+            problem,
+            runtime,
+            team,
+            user,
+        })
+    }
+
+    /// Submits a flag for the problem as the given user.
+    fn submit(&mut self, user: i32, flag: &str) -> Result<bool, String> {
+        let submission = logic::submit_flag(
+            self.db.clone(),
+            self.events.clone(),
+            user,
+            self.problem.clone(),
+            flag.to_string(),
+        );
+        self.runtime.block_on(submission).map_err(|e| e.to_string())
+    }
+}
+
+/// Creates a user on a new team, returning the user's ID and the team's.
+fn new_team(db: &DB, runtime: &mut Runtime) -> (i32, Uuid) {
+    let id = Uuid::new_v4().simple().to_string();
+    let username = format!("problems{}", &id[..12]);
+    let email = format!("problems{}@example.com", &id[..12]);
+    let (user, _) = runtime.block_on(db.create_user(username, email)).unwrap();
+    let team = runtime
+        .block_on(db.create_team(user, format!("team{}", &id[..12])))
+        .unwrap();
+    (user, team)
+}
+
+#[test]
+fn right_flag_solves_once() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+
+    assert_eq!(fixture.submit(fixture.user, FLAG), Ok(true));
+    assert_eq!(
+        fixture.submit(fixture.user, &format!(" {}\n", FLAG)),
+        Ok(false)
+    );
+
+    let solves = fixture.db.get_team_solves(fixture.team);
+    let solves = fixture.runtime.block_on(solves).unwrap();
+    assert_eq!(solves.len(), 1);
+    assert_eq!(solves[0].problem, fixture.problem);
+
+    let listing = logic::get_problem(
+        fixture.db.clone(),
+        fixture.problem.clone(),
+        Some(fixture.team),
+    );
+    let listing = fixture.runtime.block_on(listing).unwrap();
+    assert!(listing.solved);
+    assert_eq!(listing.solves, 1);
+    assert_eq!(listing.problem.category, "test");
+}
+
+#[test]
+fn first_solve_is_first_blood() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    let (other_user, other_team) = new_team(&fixture.db, &mut fixture.runtime);
+    let first = fixture.db.get_team(fixture.team);
+    let first = fixture.runtime.block_on(first).unwrap().name;
+    let second = fixture.db.get_team(other_team);
+    let second = fixture.runtime.block_on(second).unwrap().name;
+
+    let events = fixture.events.subscribe();
+    assert_eq!(fixture.submit(fixture.user, FLAG), Ok(true));
+    assert_eq!(fixture.submit(other_user, FLAG), Ok(true));
+    assert_eq!(fixture.submit(other_user, FLAG), Ok(false));
+    // Replacing the channel closes the stream, so it can be read to the end.
+    fixture.events = Events::new();
+
+    let events = events.wait().collect::<Result<Vec<_>, ()>>().unwrap();
+    let problem = fixture.problem.clone();
+    assert_eq!(
+        events,
+        vec![
+            Event::FirstBlood {
+                problem: problem.clone(),
+                team: first.clone(),
+            },
+            Event::ProblemSolved {
+                problem: problem.clone(),
+                team: first,
+            },
+            Event::ScoreboardChanged,
+            Event::ProblemSolved {
+                problem,
+                team: second,
+            },
+            Event::ScoreboardChanged,
+        ]
+    );
+}
+
+#[test]
+fn wrong_flags_are_limited() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+
+    for _ in 0..10 {
+        assert_eq!(
+            fixture.submit(fixture.user, "flag{wrong}"),
+            Err("That flag is wrong.".to_string())
+        );
+    }
+    assert_eq!(
+        fixture.submit(fixture.user, FLAG),
+        Err("Your team has submitted too many wrong flags. Try again in a minute.".to_string())
+    );
+}
+
+#[test]
+fn merging_teams_keeps_the_earlier_solve() {
+    let mut fixture = match Fixture::new() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    let (other_user, other_team) = new_team(&fixture.db, &mut fixture.runtime);
+
+    assert_eq!(fixture.submit(fixture.user, FLAG), Ok(true));
+    assert_eq!(fixture.submit(other_user, FLAG), Ok(true));
+    let earlier = fixture.db.get_team_solves(fixture.team);
+    let earlier = fixture.runtime.block_on(earlier).unwrap();
+
+    let merge = fixture
+        .db
+        .merge_teams(fixture.user, fixture.team, other_team, 4, None);
+    fixture.runtime.block_on(merge).unwrap();
+
+    let solves = fixture.db.get_team_solves(other_team);
+    let solves = fixture.runtime.block_on(solves).unwrap();
+    assert_eq!(solves.len(), 1);
+    assert_eq!(solves[0].solved, earlier[0].solved);
+    assert_eq!(solves[0].user, earlier[0].user);
+}